@@ -0,0 +1,240 @@
+use crate::util::hash;
+
+pub const ADDRESS_VERSION: u8 = 0x3c;      // Version byte of public key hash addresses, encoding to a leading 'R'
+const PAYLOAD_LEN: usize = 20;             // Bytes of the public key hash kept in an address
+const CHECKSUM_LEN: usize = 4;             // Bytes of the double SHA256 checksum appended to an address
+const ENCODED_LEN: usize = 1 + PAYLOAD_LEN + CHECKSUM_LEN;
+const ALPHABET: &[u8; 58] = b"123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+
+// Reasons an address string cannot be parsed.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum AddressError {
+    Empty,                          // No characters given
+    InvalidCharacter(char, usize),  // Character outside the Base58 alphabet, which leaves out 0, O, I and l (character, position)
+    InvalidLength(usize),           // Decodes to the wrong number of bytes (bytes decoded)
+    UnknownVersion(u8),             // Version byte this build does not know
+    BadChecksum(Option<usize>)      // Checksum does not match, with the position of the likely typo if a single one explains it
+}
+
+/**
+ * Short human-readable reference to a wallet public key.
+ * The payload is the first 20 bytes of the double SHA256 hash of
+ * the DER public key. Addresses are written in Base58Check: the
+ * version byte, payload and first 4 bytes of the double SHA256
+ * of both, encoded in Base58, so most typos fail the checksum.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct Address {
+    pub version: u8,
+    pub payload: [u8; PAYLOAD_LEN]
+}
+
+impl Address {
+    // Derive the address of a public key.
+    pub fn from_public_key(public_key: &[u8]) -> Address {
+        let mut payload = [0; PAYLOAD_LEN];
+        payload.copy_from_slice(&double_hash(public_key)[..PAYLOAD_LEN]);
+        return Address { version: ADDRESS_VERSION, payload: payload };
+    }
+
+    // Check the address was derived from a public key.
+    pub fn is_for(&self, public_key: &[u8]) -> bool {
+        return *self == Address::from_public_key(public_key);
+    }
+
+    // Encode the address as a Base58Check string.
+    pub fn encode(&self) -> String {
+        let mut binary = vec![self.version];
+        binary.extend_from_slice(&self.payload);
+        let checksum = double_hash(&binary);
+        binary.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        return base58_encode(&binary);
+    }
+
+    // Parse a Base58Check address string.
+    // When the checksum fails, single character substitutions and adjacent swaps
+    // are tried to point at the likely typo, without ever correcting it.
+    pub fn parse(text: &str) -> Result<Address, AddressError> {
+        return match Address::decode(text) {
+            Err(AddressError::BadChecksum(_)) => Err(AddressError::BadChecksum(locate_typo(text))),
+            result => result
+        };
+    }
+
+    fn decode(text: &str) -> Result<Address, AddressError> {
+        if text.is_empty() {
+            return Err(AddressError::Empty);
+        }
+        let binary = base58_decode(text)?;
+        if binary.len() != ENCODED_LEN {
+            return Err(AddressError::InvalidLength(binary.len()));
+        }
+
+        let (body, checksum) = binary.split_at(1 + PAYLOAD_LEN);
+        if double_hash(body)[..CHECKSUM_LEN] != *checksum {
+            return Err(AddressError::BadChecksum(None));
+        }
+        if body[0] != ADDRESS_VERSION {
+            return Err(AddressError::UnknownVersion(body[0]));
+        }
+
+        let mut payload = [0; PAYLOAD_LEN];
+        payload.copy_from_slice(&body[1..]);
+        return Ok(Address { version: body[0], payload: payload });
+    }
+}
+
+// Encode the address of a public key, for display.
+pub fn address_of(public_key: &[u8]) -> String {
+    return Address::from_public_key(public_key).encode();
+}
+
+fn double_hash(binary: &[u8]) -> [u8; 32] {
+    return hash(&hash(&binary.to_vec()).to_vec());
+}
+
+// Return the position of the single substituted or swapped character that would make
+// an address with a bad checksum valid, or None if no single typo, or more than one, explains it.
+fn locate_typo(text: &str) -> Option<usize> {
+    let chars: Vec<u8> = text.bytes().collect();
+    let mut found: Option<usize> = None;
+    let mut try_fix = |fixed: &[u8], pos: usize| -> bool {
+        if Address::decode(std::str::from_utf8(fixed).unwrap()).is_ok() {
+            if found.is_some_and(|other| other != pos) {
+                return false;
+            }
+            found = Some(pos);
+        }
+        return true;
+    };
+
+    for pos in 0..chars.len() {
+        let mut fixed = chars.clone();
+        for c in ALPHABET.iter().filter(|c| **c != chars[pos]) {
+            fixed[pos] = *c;
+            if !try_fix(&fixed, pos) {
+                return None;
+            }
+        }
+        if pos + 1 < chars.len() && chars[pos] != chars[pos + 1] {
+            let mut swapped = chars.clone();
+            swapped.swap(pos, pos + 1);
+            if !try_fix(&swapped, pos) {
+                return None;
+            }
+        }
+    }
+    return found;
+}
+
+// Encode binary data in Base58, keeping each leading zero byte as a leading '1'.
+fn base58_encode(binary: &[u8]) -> String {
+    let zeros = binary.iter().take_while(|byte| **byte == 0).count();
+
+    // Digits of the number in base 58, least significant first.
+    let mut digits: Vec<u8> = vec![];
+    for byte in binary[zeros..].iter() {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % 58) as u8;
+            carry /= 58;
+        }
+        while carry > 0 {
+            digits.push((carry % 58) as u8);
+            carry /= 58;
+        }
+    }
+
+    let mut encoded = "1".repeat(zeros);
+    encoded.extend(digits.iter().rev().map(|digit| ALPHABET[*digit as usize] as char));
+    return encoded;
+}
+
+// Decode a Base58 string, turning each leading '1' into a zero byte.
+fn base58_decode(text: &str) -> Result<Vec<u8>, AddressError> {
+    let zeros = text.chars().take_while(|c| *c == '1').count();
+
+    // Bytes of the number, least significant first.
+    let mut bytes: Vec<u8> = vec![];
+    for (pos, c) in text.chars().enumerate().skip(zeros) {
+        let mut carry = match ALPHABET.iter().position(|letter| *letter as char == c) {
+            None => return Err(AddressError::InvalidCharacter(c, pos)),
+            Some(value) => value as u32
+        };
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * 58;
+            *byte = carry as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push(carry as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0; zeros];
+    decoded.extend(bytes.iter().rev());
+    return Ok(decoded);
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn encodes_base58() {
+        assert_eq!(base58_encode(b"hello world"), "StV1DL6CwTryKyV");
+        assert_eq!(base58_encode(&[0, 0, 0x28, 0x7f, 0xb4, 0xcd]), "11233QC4");
+        assert_eq!(base58_decode("11233QC4"), Ok(vec![0, 0, 0x28, 0x7f, 0xb4, 0xcd]));
+        assert_eq!(base58_decode("StV1DL6CwTryKyV"), Ok(b"hello world".to_vec()));
+        assert_eq!(base58_decode(""), Ok(vec![]));
+        assert_eq!(base58_decode("abc0"), Err(AddressError::InvalidCharacter('0', 3)));
+    }
+
+    #[test]
+    fn derives_and_parses_addresses() {
+        let address = Address::from_public_key(&[0xA1; 270]);
+        let encoded = address.encode();
+        assert_eq!(encoded.len(), 34);
+        assert!(encoded.starts_with('R'));
+        assert_eq!(Address::parse(&encoded), Ok(address));
+        assert!(address.is_for(&[0xA1; 270]));
+        assert!(!address.is_for(&[0xB0; 270]));
+        assert_ne!(address_of(&[0xB0; 270]), encoded);
+    }
+
+    #[test]
+    fn detects_typos() {
+        let encoded = address_of(&[0xA1; 270]);
+        let mut chars: Vec<char> = encoded.chars().collect();
+
+        // A substituted character fails the checksum, and is located.
+        let original = chars[10];
+        chars[10] = if original == 'z' { 'y' } else { 'z' };
+        let typo: String = chars.iter().collect();
+        assert_eq!(Address::parse(&typo), Err(AddressError::BadChecksum(Some(10))));
+        chars[10] = original;
+
+        // As does a swap of neighbouring characters.
+        let pos = (5..33).find(|pos| chars[*pos] != chars[pos + 1]).unwrap();
+        chars.swap(pos, pos + 1);
+        let swapped: String = chars.iter().collect();
+        assert_eq!(Address::parse(&swapped), Err(AddressError::BadChecksum(Some(pos))));
+
+        assert_eq!(Address::parse(""), Err(AddressError::Empty));
+        assert_eq!(Address::parse(&encoded.replacen('R', "0", 1)), Err(AddressError::InvalidCharacter('0', 0)));
+        assert!(matches!(Address::parse(&encoded[..30]), Err(AddressError::InvalidLength(_))));
+    }
+
+    #[test]
+    fn rejects_unknown_versions() {
+        let mut binary = vec![0x00];
+        binary.extend_from_slice(&[0xA1; PAYLOAD_LEN]);
+        let checksum = double_hash(&binary);
+        binary.extend_from_slice(&checksum[..CHECKSUM_LEN]);
+        assert_eq!(Address::parse(&base58_encode(&binary)), Err(AddressError::UnknownVersion(0)));
+    }
+}
@@ -0,0 +1,90 @@
+// Offline retarget simulator, built separately from the node so it never touches chain data.
+#![allow(clippy::needless_return, clippy::unused_unit)]
+
+use rusty_chain::config::Config;
+use rusty_chain::simulator::{self, Schedule, ALGORITHMS};
+use clap::{App, Arg, ArgMatches, value_t};
+use std::fs;
+use std::process;
+
+
+// Print an error message and exit with a failure status.
+fn exit_with(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+// Build the command line interface definition.
+fn build_cli<'a, 'b>() -> App<'a, 'b> {
+    return App::new("simulate")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("Simulate retargeting against a hashrate schedule offline, writing the blocks as CSV")
+        .arg(Arg::with_name("config")
+            .long("config")
+            .value_name("PATH")
+            .env("RUSTY_CHAIN_CONFIG")
+            .help("Path of the TOML config file the chain settings are read from"))
+        .arg(Arg::with_name("schedule").value_name("SCHEDULE").required(true).help("TOML file of miners joining, leaving and offsetting timestamps"))
+        .arg(Arg::with_name("algorithm").long("algorithm").value_name("NAME").possible_values(&ALGORITHMS).default_value("lwma").help("Retarget algorithm to simulate"))
+        .arg(Arg::with_name("blocks").long("blocks").value_name("COUNT").help("Number of blocks to mine, overriding the schedule"))
+        .arg(Arg::with_name("seed").long("seed").value_name("SEED").help("Random seed, overriding the schedule"))
+        .arg(Arg::with_name("genesis-diff").long("genesis-diff").value_name("BITS").help("Leading zero bits of the genesis target, overriding chain.genesis_diff"))
+        .arg(Arg::with_name("block-time").long("block-time").value_name("SECONDS").help("Expected block time, overriding chain.block_time"))
+        .arg(Arg::with_name("retarget-window").long("retarget-window").value_name("COUNT").help("Blocks each target is calculated from, overriding chain.retarget_window"))
+        .arg(Arg::with_name("out").long("out").value_name("PATH").help("File to write the CSV to, instead of stdout"));
+}
+
+// Load the schedule and chain settings, applying overrides from the command line.
+fn load(args: &ArgMatches) -> (Schedule, Config) {
+    let mut config = match Config::load(args.value_of("config")) {
+        Err(why) => exit_with(format!("Failed to load config: {:?}", why)),
+        Ok(config) => config
+    };
+    let mut schedule = match Schedule::from_file(args.value_of("schedule").unwrap()) {
+        Err(why) => exit_with(format!("Failed to load schedule: {:?}", why)),
+        Ok(schedule) => schedule
+    };
+    if args.is_present("blocks") {
+        schedule.blocks = value_t!(args, "blocks", usize).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("seed") {
+        schedule.seed = value_t!(args, "seed", u64).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("genesis-diff") {
+        config.chain.genesis_diff = value_t!(args, "genesis-diff", u8).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("block-time") {
+        config.chain.block_time = value_t!(args, "block-time", u32).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("retarget-window") {
+        config.chain.retarget_window = value_t!(args, "retarget-window", u32).unwrap_or_else(|e| e.exit());
+    }
+    if let Err(why) = config.validate() {
+        exit_with(format!("Invalid config: {:?}", why));
+    }
+
+    return (schedule, config);
+}
+
+fn main() {
+    let args = build_cli().get_matches();
+    let (schedule, config) = load(&args);
+    let algorithm = simulator::algorithm_by_name(args.value_of("algorithm").unwrap()).unwrap();
+
+    let blocks = match simulator::simulate(&schedule, algorithm.as_ref(), &config.chain) {
+        Err(why) => exit_with(format!("Invalid schedule: {:?}", why)),
+        Ok(blocks) => blocks
+    };
+    let csv = simulator::to_csv(&blocks);
+    match args.value_of("out") {
+        None => print!("{}", csv),
+        Some(out) => {
+            if let Err(why) = fs::write(out, csv) {
+                exit_with(format!("Failed to write CSV to {}: {}", out, why));
+            }
+            let (mean, deviation) = simulator::interval_stats(&blocks);
+            println!("Simulated {} block(s) with {} to {}", blocks.len() - 1, algorithm.name(), out);
+            println!("Block interval: mean {:.1}s, standard deviation {:.1}s, target {}s", mean, deviation, config.chain.block_time);
+        }
+    }
+}
@@ -1,90 +1,396 @@
 extern crate byteorder;
 
 use crate::util::hash;
-use crate::tx_struct::{Tx, DataTx, FinancialTx};
-use byteorder::ByteOrder;
+use crate::merkle::{merkle_root, generate_proof, MerkleProof};
+use crate::tx_struct::{Tx, tx_from_bin};
+use crate::json::{BlockJson, format};
+use crate::codec::{DecodeError, take, take_array, take_u8, take_u32, take_prefixed, push_prefixed, check_end};
+use byteorder::{ByteOrder, BigEndian};
 
+// Version of the block header format.
+// Version 1 replaced the one byte count of leading zero bits with compact target bits.
+pub const BLOCK_VERSION: u8 = 1;
 
 /**
  * A struct defining a block within the chain.
  * Note: Txs field has to be 'boxed' as Tx type size unknown at compile time.
  */
-pub struct Block<'a> {
+#[derive(Clone)]
+pub struct Block {
     pub nonce: [u8; 16],
     pub previous_hash: [u8; 32],
     pub version: u8,
-    pub difficulty: u8,
+    pub bits: u32,                  // Compact proof-of-work target, see target.rs
     pub height: [u8; 4],
     pub timestamp: [u8; 8],
     pub miner: Vec<u8>,
     pub reward_amount: [u8; 4],
     pub tx_count: u8,
-    // pub tx_merkle: [u8; 32],
-    pub txs: Vec<&'a Tx>,
+    pub tx_merkle: [u8; 32],
+    pub txs: Vec<Box<dyn Tx>>,
     pub hash: [u8; 32]
 }
 
-impl ToString for Block<'_> {
+/**
+ * A block header, holding every block field except the txs.
+ * Txs are committed to through tx_merkle, so the header alone is
+ * enough to check the proof-of-work and how blocks link together.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct BlockHeader {
+    pub nonce: [u8; 16],
+    pub previous_hash: [u8; 32],
+    pub version: u8,
+    pub bits: u32,                  // Compact proof-of-work target, see target.rs
+    pub height: [u8; 4],
+    pub timestamp: [u8; 8],
+    pub miner: Vec<u8>,
+    pub reward_amount: [u8; 4],
+    pub tx_count: u8,
+    pub tx_merkle: [u8; 32],
+    pub hash: [u8; 32]
+}
+
+impl ToString for Block {
     fn to_string(&self) -> String {
         return format!("Block {{
             nonce: {:?},
             previous_hash (hex): {:x?},
             version: {},
-            difficulty: {},
+            bits: {:#010x},
             height: {:?},
             timestamp: {:?},
             miner (hex): {:x?},
             reward_amount: {:?},
             tx_count: {:?},
+            tx_merkle (hex): {:x?},
             txs: [..],
             hash (hex): {:x?},
         }}",
             self.nonce,
             self.previous_hash,
             self.version,
-            self.difficulty,
+            self.bits,
             self.height,
             self.timestamp,
             self.miner,
             self.reward_amount,
             self.tx_count,
+            self.tx_merkle,
             // self.txs,
             self.hash
         );
     }
 }
 
-impl Block<'_> {
-    // Convert block fields into a binary used for generating hash.
+// Read the version byte of a header, rejecting formats other than the current one.
+fn take_version(bin: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    let version = take_u8(bin, pos)?;
+    if version != BLOCK_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+    return Ok(version);
+}
+
+impl BlockHeader {
+    // Convert header fields into a binary used for generating hash.
     // Excludes nonce as used as Proof-of-work to meet set difficulty.
+    // Txs are committed to through the merkle root of their hashes.
     pub fn to_hashable_bin(&self) -> Vec<u8> {
-
-        // Generate binary of all txs.
-        // TODO: Use merkle tree for static tx hash size.
-        let mut txs_bin: Vec<u8> = vec![];
-        for tx in self.txs.iter() {
-            txs_bin.extend_from_slice(&tx.to_bin().clone());
-        }
-
         let mut binary: Vec<u8> = vec![];
         binary.extend_from_slice(&self.previous_hash.clone());
         binary.push(self.version);
-        binary.push(self.difficulty);
+        binary.extend_from_slice(&self.bits.to_be_bytes());
         binary.extend_from_slice(&self.height.clone());
         binary.extend_from_slice(&self.timestamp.clone());
         binary.extend_from_slice(&self.miner.clone());
         binary.extend_from_slice(&self.reward_amount.clone());
         binary.push(self.tx_count);
-        binary.extend(&txs_bin.clone());
+        binary.extend_from_slice(&self.tx_merkle);
 
         return binary;
     }
 
+    // Convert all header fields to bin, used for sending headers to peers.
+    pub fn to_bin(&self) -> Vec<u8> {
+        let mut binary: Vec<u8> = vec![];
+        binary.extend_from_slice(&self.nonce);
+        binary.extend_from_slice(&self.previous_hash);
+        binary.push(self.version);
+        binary.extend_from_slice(&self.bits.to_be_bytes());
+        binary.extend_from_slice(&self.height);
+        binary.extend_from_slice(&self.timestamp);
+        push_prefixed(&mut binary, &self.miner);
+        binary.extend_from_slice(&self.reward_amount);
+        binary.push(self.tx_count);
+        binary.extend_from_slice(&self.tx_merkle);
+        binary.extend_from_slice(&self.hash);
+
+        return binary;
+    }
+
+    // Read a header binary, as produced by to_bin, starting at the read position.
+    pub fn take(bin: &[u8], pos: &mut usize) -> Result<BlockHeader, DecodeError> {
+        return Ok(BlockHeader {
+            nonce: take_array(bin, pos)?,
+            previous_hash: take_array(bin, pos)?,
+            version: take_version(bin, pos)?,
+            bits: take_u32(bin, pos)?,
+            height: take_array(bin, pos)?,
+            timestamp: take_array(bin, pos)?,
+            miner: take_prefixed(bin, pos)?,
+            reward_amount: take_array(bin, pos)?,
+            tx_count: take_u8(bin, pos)?,
+            tx_merkle: take_array(bin, pos)?,
+            hash: take_array(bin, pos)?
+        });
+    }
+
+    // Return the block height as a u32.
+    pub fn get_height(&self) -> u32 {
+        return BigEndian::read_u32(&self.height);
+    }
+
+    // Calculate the proof-of-work hash of the header.
+    pub fn calculate_hash(&self) -> [u8; 32] {
+        let mut bin: Vec<u8> = vec![];
+        bin.extend_from_slice(&self.nonce);
+        bin.extend_from_slice(&self.to_hashable_bin());
+        return hash(&bin);
+    }
+}
+
+impl Block {
+    // Convert block fields into a binary used for generating hash.
+    // Matches the binary of the block header.
+    pub fn to_hashable_bin(&self) -> Vec<u8> {
+        return self.get_header().to_hashable_bin();
+    }
+
+    // Return the header of the block.
+    pub fn get_header(&self) -> BlockHeader {
+        return BlockHeader {
+            nonce: self.nonce,
+            previous_hash: self.previous_hash,
+            version: self.version,
+            bits: self.bits,
+            height: self.height,
+            timestamp: self.timestamp,
+            miner: self.miner.clone(),
+            reward_amount: self.reward_amount,
+            tx_count: self.tx_count,
+            tx_merkle: self.tx_merkle,
+            hash: self.hash
+        }
+    }
+
+    // Convert all block contents to bin, used for storage.
+    // Variable length fields are prefixed with their length.
+    pub fn to_bin(&self) -> Vec<u8> {
+        let mut binary: Vec<u8> = vec![];
+        binary.extend_from_slice(&self.nonce);
+        binary.extend_from_slice(&self.previous_hash);
+        binary.push(self.version);
+        binary.extend_from_slice(&self.bits.to_be_bytes());
+        binary.extend_from_slice(&self.height);
+        binary.extend_from_slice(&self.timestamp);
+        push_prefixed(&mut binary, &self.miner);
+        binary.extend_from_slice(&self.reward_amount);
+        binary.push(self.tx_count);
+        binary.extend_from_slice(&self.tx_merkle);
+        for tx in self.txs.iter() {
+            let tx_bin = tx.to_bin();
+            binary.extend_from_slice(&(tx_bin.len() as u32).to_be_bytes());
+            binary.extend_from_slice(&tx_bin);
+        }
+        binary.extend_from_slice(&self.hash);
+
+        return binary;
+    }
+
+    // Convert the block to its JSON representation, see json.rs for the schema.
+    pub fn to_json(&self) -> String {
+        return format(&BlockJson::from_block(self));
+    }
+
+    // Parse a full block binary, as produced by to_bin, into a Block.
+    // The number of length-prefixed txs is given by the tx_count byte.
+    pub fn from_bin(bin: &[u8]) -> Result<Block, DecodeError> {
+        let mut pos: usize = 0;
+        let mut block = Block {
+            nonce: take_array(bin, &mut pos)?,
+            previous_hash: take_array(bin, &mut pos)?,
+            version: take_version(bin, &mut pos)?,
+            bits: take_u32(bin, &mut pos)?,
+            height: take_array(bin, &mut pos)?,
+            timestamp: take_array(bin, &mut pos)?,
+            miner: take_prefixed(bin, &mut pos)?,
+            reward_amount: take_array(bin, &mut pos)?,
+            tx_count: take_u8(bin, &mut pos)?,
+            tx_merkle: take_array(bin, &mut pos)?,
+            txs: vec![],
+            hash: [0; 32]
+        };
+        for _ in 0..block.tx_count {
+            let tx_len = take_u32(bin, &mut pos)? as usize;
+            block.txs.push(tx_from_bin(take(bin, &mut pos, tx_len)?)?);
+        }
+        block.hash = take_array(bin, &mut pos)?;
+        check_end(bin, pos)?;
+
+        return Ok(block);
+    }
+
+    // Create an empty block with all fields zeroed.
+    pub fn new() -> Block {
+        return Block {
+            nonce: [0; 16],
+            previous_hash: [0; 32],
+            version: BLOCK_VERSION,
+            bits: 0,
+            height: [0; 4],
+            timestamp: [0; 8],
+            miner: vec![],
+            reward_amount: [0; 4],
+            tx_count: 0,
+            tx_merkle: [0; 32],
+            txs: vec![],
+            hash: [0; 32]
+        }
+    }
+
+    // Return the block height as a u32.
+    pub fn get_height(&self) -> u32 {
+        return BigEndian::read_u32(&self.height);
+    }
+
+    // Calculate the merkle root of the block tx hashes.
+    pub fn calculate_merkle(&self) -> [u8; 32] {
+        let leaves: Vec<[u8; 32]> = self.txs.iter().map(|tx| tx.get_hash()).collect();
+        return merkle_root(&leaves);
+    }
+
+    // Generate and set the merkle root of the block txs.
+    pub fn generate_merkle(&mut self) -> () {
+        self.tx_merkle = self.calculate_merkle();
+    }
+
+    // Generate an inclusion proof for the tx at index, checkable against tx_merkle.
+    pub fn generate_tx_proof(&self, index: usize) -> Option<MerkleProof> {
+        let leaves: Vec<[u8; 32]> = self.txs.iter().map(|tx| tx.get_hash()).collect();
+        return generate_proof(&leaves, index);
+    }
+
+    // Calculate the proof-of-work hash of the block.
+    // Matches the miner, which hashes the nonce followed by the hashable binary.
+    pub fn calculate_hash(&self) -> [u8; 32] {
+        let mut bin: Vec<u8> = vec![];
+        bin.extend_from_slice(&self.nonce);
+        bin.extend_from_slice(&self.to_hashable_bin());
+        return hash(&bin);
+    }
+
     // Generate and set hash of block.
+    #[cfg(test)]
     pub fn generate_hash(&mut self) -> () {
-        let bin: Vec<u8> = self.to_hashable_bin();
-        let hash: [u8; 32] = hash(&bin);
-        self.hash = hash;
+        self.hash = self.calculate_hash();
     }
 }
 
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tx_struct::{DataTx, FinancialTx};
+    use crate::merkle::verify_proof;
+
+    fn test_block() -> Block {
+        let mut block = Block::new();
+        block.nonce = [1; 16];
+        block.previous_hash = [2; 32];
+        block.bits = 0x1d00_ffff;
+        block.height = [0, 0, 0, 5];
+        block.timestamp = [0, 0, 0, 0, 94, 200, 10, 0];
+        block.miner = vec![3; 270];
+        block.reward_amount = [0, 0, 0, 50];
+        block.txs.push(Box::new(DataTx::new()));
+        block.txs.push(Box::new(FinancialTx::new()));
+        block.tx_count = 2;
+        block.generate_merkle();
+        block.generate_hash();
+        return block;
+    }
+
+    #[test]
+    fn block_bin_round_trip() {
+        let block = test_block();
+        let decoded = Block::from_bin(&block.to_bin()).unwrap();
+
+        assert_eq!(decoded.to_bin(), block.to_bin());
+        assert_eq!(decoded.to_hashable_bin(), block.to_hashable_bin());
+        assert_eq!(decoded.get_height(), 5);
+        assert_eq!(decoded.txs.len(), 2);
+        assert_eq!(decoded.hash, block.hash);
+    }
+
+    #[test]
+    fn hash_commits_to_tx_merkle() {
+        let mut block = test_block();
+        let hash = block.hash;
+        let proof = block.generate_tx_proof(1).unwrap();
+
+        assert!(verify_proof(&block.txs[1].get_hash(), &proof, &block.tx_merkle, block.tx_count as usize));
+        block.txs.pop();
+        block.generate_merkle();
+        assert_ne!(block.calculate_hash(), hash);
+    }
+
+    #[test]
+    fn header_matches_block() {
+        let block = test_block();
+        let header = block.get_header();
+        let mut pos: usize = 0;
+
+        assert_eq!(header.calculate_hash(), block.hash);
+        assert_eq!(header.to_hashable_bin(), block.to_hashable_bin());
+        assert_eq!(BlockHeader::take(&header.to_bin(), &mut pos), Ok(header.clone()));
+        assert_eq!(pos, header.to_bin().len());
+    }
+
+    #[test]
+    fn decode_truncated_block() {
+        let bin = test_block().to_bin();
+
+        for len in 0..bin.len() {
+            assert!(matches!(Block::from_bin(&bin[0..len]), Err(DecodeError::Truncated(_, _))));
+        }
+    }
+
+    #[test]
+    fn decode_block_with_malformed_tx() {
+        let block = test_block();
+        let mut bin = block.to_bin();
+        let tx_pos = bin.len() - 32 - block.txs[1].to_bin().len();
+        bin[tx_pos + 1] = 0x09;
+
+        assert_eq!(Block::from_bin(&bin).err(), Some(DecodeError::UnknownTxType(0x09)));
+    }
+
+    #[test]
+    fn decode_unsupported_version() {
+        let mut bin = test_block().to_bin();
+        bin[48] = 0;
+
+        assert_eq!(Block::from_bin(&bin).err(), Some(DecodeError::UnsupportedVersion(0)));
+        assert_eq!(BlockHeader::take(&bin, &mut 0).err(), Some(DecodeError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn decode_block_trailing_bytes() {
+        let mut bin = test_block().to_bin();
+        bin.extend_from_slice(&[0; 3]);
+
+        assert_eq!(Block::from_bin(&bin).err(), Some(DecodeError::TrailingBytes(3)));
+    }
+}
@@ -1,83 +1,1042 @@
-use crate::env;
-use crate::block_struct::Block;
-use crate::tx_struct::{Tx, DataTx, FinancialTx, TxType};
-use crate::util::{parse_net_address, get_timestamp, hash};
-use crate::miner::{start_mining_server, MinerCommand};
-use crate::env::{DEBUG, GENESIS_DIFF};
+use crate::address::{Address, address_of};
+use crate::block_struct::{Block, BlockHeader, BLOCK_VERSION};
+use crate::config::{Config, ChainConfig};
+use crate::retarget::{BlockTime, ancestry_span, next_bits, median_time_past};
+use crate::ledger::{Ledger, LedgerError};
+use crate::mempool::Mempool;
+use crate::merkle::MerkleProof;
+use crate::util::get_timestamp;
+use crate::miner::{start_mining_server, MinerCommand, MinerStats, MiningJob};
+use crate::net::{start_net_interface, NetCommand, NetEvent, Message, InvItem, InvType, PeerId};
 use crate::log::{log, tlog, dlog};
-use crate::wallet_struct::Wallet;
-use std::thread;
+use crate::store::{BlockStore, FileStore, StoreError};
+use crate::sync::{BlockSync, MAX_HEADERS, build_locator, headers_after};
+use crate::fork::{BlockTree, MAX_FORK_DEPTH};
+use crate::target::bits_from_diff;
+use crate::rest::{start_rest_server, RestRequest, RestQuery, RestReply, SubmitError};
+use crate::validation::{validate_block, BlockError, ChainState, MAX_FUTURE_TIME, MAX_BLOCK_BYTES};
+use crate::tx_struct::Tx;
+use byteorder::{ByteOrder, BigEndian};
+use std::collections::VecDeque;
 use std::sync::mpsc;
-use std::net::SocketAddr;
+use std::sync::mpsc::{RecvTimeoutError, TryRecvError};
+use std::net::TcpListener;
+use std::path::Path;
+use std::time::{Duration, Instant};
 
-// N.B. The lifetime of the state is equal to the shortest lifetime
-// of either the previous_block, or tx in stored_txs.
-struct State<'a, 'b> {
-    previous_block: Block<'a>,
-    height: [u8; 4],
-    difficulty: u8,
-    mining_service: (mpsc::Sender<[u8; 4]>, mpsc::Receiver<[u8; 4]>),
-    network_service: (mpsc::Sender<[u8; 4]>, mpsc::Receiver<[u8; 4]>),
-    stored_txs: Vec<&'b Tx>
+const MINER_REPLY_TIMEOUT: Duration = Duration::from_secs(1);   // Time allowed for the mining server to report its statistics
+
+// Reasons a block cannot be appended to the chain.
+// The wrapped errors are only read through Debug when a rejection is logged.
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum ChainError {
+    Invalid(BlockError),    // Block failed validation against the tip
+    Ledger(LedgerError),    // Block txs could not be applied to the ledger
+    Store(StoreError),      // Block could not be persisted
+    Missing,                // Block could not be read back from the store
+    Orphan,                 // Block's parent is not known
+    InvalidParent,          // Block builds on a block that failed to connect
+    TooDeep                 // Block is on a side branch more than MAX_FORK_DEPTH blocks below the tip
+}
+
+// Where a connected block was placed in the block tree.
+enum Connected {
+    Extended,       // Block was appended on top of the active tip
+    Reorganised,    // Block completed a branch with more work, which became the active chain
+    SideBranch      // Block was held on a side branch with no more work than the active chain
 }
 
-pub fn start_server(mine_flag: bool, accept_tx_flag: bool, rest_api_flag: bool, spawn_chain_flag: bool) {
-    let peers: Vec<SocketAddr> = env::PEERS_LIST.iter().map(|peer| parse_net_address(peer)).collect::<Vec<SocketAddr>>();
+// Chain server state.
+struct State {
+    store: FileStore,
+    ledger: Ledger,
+    mempool: Mempool,
+    reward_key: Option<Vec<u8>>,                            // Public key credited with mined block rewards, set on mining nodes
+    template: Option<Block>,                                // Block currently being mined
+    job_id: u64,                                            // Id of the mining job for the template
+    sync: BlockSync,
+    tree: BlockTree,                                        // Side branches and cumulative work for fork choice
+    config: Config,
+    miner_tx: mpsc::Sender<MinerCommand>,
+    mining_rx: mpsc::Receiver<(u64, [u8; 16], [u8; 32])>,
+    net_tx: mpsc::Sender<NetCommand>,
+    net_rx: mpsc::Receiver<NetEvent>,
+    rest_rx: mpsc::Receiver<RestRequest>
+}
 
+// Start the chain node with a validated config.
+// Mining nodes, and nodes spawning a chain, credit block rewards to the given public key.
+pub fn start_server(config: Config, reward_key: Option<Vec<u8>>) {
     tlog("Starting Rusty-chain node!", &[
-        format!("Default peers: {:?}", peers),
-        format!("Spawning chain: {}", spawn_chain_flag),
-        format!("Joining chain: {}", !spawn_chain_flag),
-        format!("Mining node: {}", mine_flag),
-        format!("Accepting txs: {}", accept_tx_flag),
-        format!("Hosting REST API: {}", rest_api_flag)
+        format!("Peers: {:?}", config.node.get_peers()),
+        format!("Port: {}", config.node.port),
+        format!("Spawning chain: {}", config.node.spawn_chain),
+        format!("Joining chain: {}", !config.node.spawn_chain),
+        format!("Mining node: {}", config.mining.enabled),
+        format!("Mining threads: {}", config.mining.threads),
+        format!("Reward address: {}", reward_key.as_ref().map_or("none".to_string(), |key| address_of(key))),
+        format!("Accepting txs: {}", config.node.accept_txs),
+        format!("Hosting REST API: {}", config.node.rest_api),
+        format!("REST API port: {}", config.node.rest_port),
+        format!("Block store: {}", config.node.block_store)
     ]);
 
-    // Create Genesis block if spawn flag set.
-    // Creates an independent mining server for genesis block.
-    if spawn_chain_flag {
-        let gen_wallet = Wallet::new();
-
-        let mut genesis: Block = Block {
-            nonce: [0; 16],
-            previous_hash: [0; 32],
-            version: 0,
-            difficulty: GENESIS_DIFF,
-            height: [0; 4],
-            timestamp: get_timestamp(),
-            miner: gen_wallet.public_key,
-            reward_amount: [0; 4],
-            tx_count: 0,
-            txs: vec![],
-            hash: [0; 32]
+    // Open the block store and resume from its tip if blocks are already stored.
+    let mut store = match FileStore::open(Path::new(&config.node.block_store)) {
+        Err(why) => panic!("Failed to open block store: {:?}", why),
+        Ok(store) => store
+    };
+
+    match store.get_tip() {
+        Some(tip) => {
+            log(format!("Resuming chain from stored tip at height {}, Block Hash: {:x?}.", tip.get_height(), tip.hash));
+        },
+        None if config.node.spawn_chain => {
+            let genesis = mine_genesis(&config, reward_key.clone().expect("Spawning a chain needs a reward key."));
+            if let Err(why) = store.put_block(&genesis) {
+                panic!("Failed to store genesis block: {:?}", why);
+            }
+        },
+        None => ()
+    }
+
+    // Rebuild account balances from the stored chain.
+    let ledger = match Ledger::from_store(&store, config.chain.block_reward) {
+        Err(why) => panic!("Failed to rebuild ledger from block store: {:?}", why),
+        Ok(ledger) => ledger
+    };
+
+    // Load services.
+    let listener = match TcpListener::bind(("0.0.0.0", config.node.port)) {
+        Err(why) => panic!("Failed to listen on port {}: {}", config.node.port, why),
+        Ok(listener) => listener
+    };
+    let (event_tx, net_rx) = mpsc::channel();
+    let net_tx = start_net_interface(listener, &config.node, block_count(&store), event_tx);
+    let (rest_tx, rest_rx) = mpsc::channel();
+    if config.node.rest_api {
+        start_rest_server(config.node.rest_port, rest_tx);
+    }
+    let tree = BlockTree::from_store(&store);
+
+    let (chain_tx, mining_rx) = mpsc::channel();
+    let (miner_tx, miner_rx) = mpsc::channel();
+    if config.mining.enabled {
+        start_mining_server(chain_tx, miner_rx, config.mining.threads);
+    }
+
+    let mut state = State {
+        store: store,
+        ledger: ledger,
+        mempool: Mempool::with_capacity(config.chain.mempool_max_bytes),
+        reward_key: reward_key,
+        template: None,
+        job_id: 0,
+        sync: BlockSync::new(&config.chain),
+        tree: tree,
+        config: config,
+        miner_tx: miner_tx,
+        mining_rx: mining_rx,
+        net_tx: net_tx,
+        net_rx: net_rx,
+        rest_rx: rest_rx
+    };
+
+    if state.config.mining.enabled {
+        log("Starting block production.".to_string());
+    }
+    restart_template(&mut state);
+
+    // Handle events from the network server, queries from the REST server and solutions from the mining server.
+    // Each solved template is appended to the chain and a new template started on top.
+    loop {
+        let recv = state.net_rx.recv_timeout(Duration::from_millis(100));
+        match recv {
+            Ok(event) => handle_net_event(&mut state, event),
+            Err(RecvTimeoutError::Timeout) => (),
+            Err(RecvTimeoutError::Disconnected) => panic!("Network server stopped unexpectedly.")
+        }
+        drive_sync(&mut state);
+
+        while let Ok(request) = state.rest_rx.try_recv() {
+            handle_rest_request(&mut state, request);
+        }
+
+        if state.config.mining.enabled {
+            match state.mining_rx.try_recv() {
+                Ok((job_id, nonce, hash)) => handle_solution(&mut state, job_id, nonce, hash),
+                Err(TryRecvError::Empty) => (),
+                Err(TryRecvError::Disconnected) => panic!("Mining server stopped unexpectedly.")
+            }
+        }
+    }
+}
+
+// Return the number of blocks held in a store.
+fn block_count(store: &dyn BlockStore) -> u32 {
+    return store.tip_height().map(|height| height + 1).unwrap_or(0);
+}
+
+// Re-validate every stored block in height order, replaying each into a ledger.
+// Returns the rebuilt ledger, or the height and reason of the first invalid block.
+pub fn verify_chain(store: &dyn BlockStore, params: &ChainConfig) -> Result<Ledger, (u32, ChainError)> {
+    let mut ledger = Ledger::with_max_reward(params.block_reward);
+    let mut chain_state = ChainState::genesis(bits_from_diff(params.genesis_diff));
+    let tip_height = match store.tip_height() {
+        None => return Ok(ledger),
+        Some(height) => height
+    };
+
+    // Timestamps and bits of the most recent blocks, for retargeting.
+    let mut window: VecDeque<BlockTime> = VecDeque::new();
+    for height in 0..=tip_height {
+        let block = match store.get_by_height(height) {
+            None => return Err((height, ChainError::Missing)),
+            Some(block) => block
         };
+        validate_block(&block, &chain_state).map_err(|why| (height, ChainError::Invalid(why)))?;
+        ledger.apply_block(&block).map_err(|why| (height, ChainError::Ledger(why)))?;
+
+        if window.len() == ancestry_span(params) {
+            window.pop_front();
+        }
+        window.push_back(BlockTime::from_block(&block));
+        let window = window.make_contiguous();
+        chain_state = ChainState::from_tip(&block, next_bits(window, params), median_time_past(window));
+    }
+
+    return Ok(ledger);
+}
+
+// Find a known block by hash, on the active chain or a side branch.
+fn get_block(state: &State, hash: &[u8; 32]) -> Option<Block> {
+    return state.store.get_by_hash(hash).or_else(|| state.tree.get_side(hash).cloned());
+}
+
+// Build the chain state a block extending the given block is validated against.
+// The parent may be on a side branch, so its ancestors are looked up in the block tree.
+// New blocks may not be timestamped too far ahead of the local clock.
+fn state_after(state: &State, parent: &Block) -> ChainState {
+    let params = &state.config.chain;
+    let mut window = match parent.get_height() {
+        0 => vec![],
+        _ => state.tree.get_ancestry(&parent.previous_hash, ancestry_span(params) - 1)
+    };
+    window.push(BlockTime::from_block(parent));
+
+    let now = BigEndian::read_u64(&get_timestamp());
+    return ChainState {
+        max_timestamp: now.saturating_add(MAX_FUTURE_TIME),
+        ..ChainState::from_tip(parent, next_bits(&window, params), median_time_past(&window))
+    };
+}
+
+// Build a new block on top of the tip from the highest reward mempool txs.
+fn build_template(state: &State) -> Block {
+    let tip = state.store.get_tip().expect("Cannot build a template on an empty chain.");
+
+    // The timestamp must follow the median time past, which can pass the clock while blocks come quickly.
+    let chain_state = state_after(state, &tip);
+    let now = BigEndian::read_u64(&get_timestamp());
+    let timestamp = now.max(chain_state.median_time_past.unwrap_or(0) + 1);
+
+    let mut template = Block::new();
+    template.previous_hash = tip.hash;
+    template.bits = chain_state.bits;
+    template.height = (tip.get_height() + 1).to_be_bytes();
+    template.timestamp = timestamp.to_be_bytes();
+    template.miner = state.reward_key.clone().expect("Mining nodes are started with a reward key.");
+    template.reward_amount = state.config.chain.block_reward.to_be_bytes();
+    // Each tx is stored in the block with a 4 byte length prefix.
+    let max_count = u8::MAX as usize;
+    let max_bytes = MAX_BLOCK_BYTES - template.to_bin().len() - 4 * max_count;
+    template.txs = state.mempool.select_txs(max_count, max_bytes);
+    template.tx_count = template.txs.len() as u8;
+    template.generate_merkle();
+
+    return template;
+}
+
+// Replace any active mining job with a new template on the current tip.
+// Does nothing if the node is not mining, is syncing or holds no blocks yet.
+fn restart_template(state: &mut State) -> () {
+    if !state.config.mining.enabled || state.sync.is_syncing() || state.store.tip_height().is_none() {
+        return;
+    }
+
+    start_template(state);
+}
+
+// Build a new template and hand it to the mining server.
+fn start_template(state: &mut State) -> () {
+    let template = build_template(state);
+    dlog(module_path!(), "Mining new block template", &[
+        format!("Height: {}", template.get_height()),
+        format!("Target bits: {:#010x}", template.bits),
+        format!("Txs: {}", template.tx_count)
+    ]);
+
+    send_job(state, template);
+}
+
+// Hand a template to the mining server as a new job, replacing the current one.
+fn send_job(state: &mut State, template: Block) -> () {
+    state.job_id += 1;
+    let job = MiningJob {
+        id: state.job_id,
+        data: template.to_hashable_bin(),
+        bits: template.bits
+    };
+    state.miner_tx.send(MinerCommand::NEW_JOB(job)).unwrap();
+    state.template = Some(template);
+}
+
+// Handle a solution from the mining server.
+// Solutions to replaced jobs are discarded, and solutions not valid for the
+// current template are stale and the template is mined again.
+fn handle_solution(state: &mut State, job_id: u64, nonce: [u8; 16], hash: [u8; 32]) -> () {
+    if job_id != state.job_id {
+        dlog(module_path!(), "Discarded solution to replaced mining job", &[ format!("Job: {}", job_id) ]);
+        return;
+    }
+    let mut block = match state.template.take() {
+        None => return,
+        Some(template) => template
+    };
+    block.nonce = nonce;
+    block.hash = hash;
+
+    match append_block(state, &block) {
+        Ok(()) => {
+            log(format!("Mined block at height {}, Block Hash: {:x?}.", block.get_height(), block.hash));
+            announce_block(state, &block, None);
+            start_template(state);
+        },
+        Err(why) => {
+            dlog(module_path!(), "Discarded mined solution", &[ format!("Reason: {:?}", why) ]);
+            block.nonce = [0; 16];
+            block.hash = [0; 32];
+            send_job(state, block);
+        }
+    }
+}
+
+// Announce a newly appended block to peers, except the peer it came from.
+fn announce_block(state: &State, block: &Block, source: Option<PeerId>) -> () {
+    let inv = vec![ InvItem { inv_type: InvType::Block, hash: block.hash } ];
+    let _ = state.net_tx.send(NetCommand::UPDATE_BLOCK_COUNT(block_count(&state.store)));
+    let _ = state.net_tx.send(NetCommand::BROADCAST(Message::INV(inv), source));
+}
+
+// Handle an event from the network server.
+fn handle_net_event(state: &mut State, event: NetEvent) -> () {
+    match event {
+        NetEvent::PEER_READY(id, count) => {
+            dlog(module_path!(), "Peer ready", &[
+                format!("Peer: {}", id),
+                format!("Peer blocks: {}", count),
+                format!("Local blocks: {}", block_count(&state.store))
+            ]);
+            state.sync.update_peer(id, count);
+        },
+        NetEvent::PEER_LOST(id) => {
+            dlog(module_path!(), "Peer lost", &[ format!("Peer: {}", id) ]);
+            state.sync.remove_peer(id);
+        },
+        NetEvent::MESSAGE(id, message) => match message {
+            Message::INV(items) => request_unknown(state, id, items),
+            Message::GETDATA(items) => send_data(state, id, items),
+            Message::NOTFOUND(items) => {
+                let hashes: Vec<[u8; 32]> = items.iter().filter(|item| item.inv_type == InvType::Block).map(|item| item.hash).collect();
+                state.sync.blocks_not_found(id, &hashes);
+            },
+            Message::BLOCK(block) => receive_block(state, id, block),
+            Message::TX(tx) => receive_tx(state, id, tx),
+            Message::GETHEADERS(locator) => {
+                let headers = headers_after(&state.store, &locator);
+                let _ = state.net_tx.send(NetCommand::SEND(id, Message::HEADERS(headers)));
+            },
+            Message::HEADERS(headers) => receive_headers(state, id, headers),
+            _ => ()
+        }
+    }
+}
+
+// Add headers received from a peer to the sync.
+// Headers of blocks already held are skipped, the rest may fork from the active chain.
+// A peer with no new headers holds nothing the node lacks, so its block count is lowered to stop asking it.
+// A full headers message means the peer may hold more, so its block count is raised to ask again.
+fn receive_headers(state: &mut State, id: PeerId, headers: Vec<BlockHeader>) -> () {
+    let more = headers.len() >= MAX_HEADERS;
+    let headers: Vec<BlockHeader> = headers.into_iter().skip_while(|header| is_known(state, &header.hash)).collect();
+    let new = headers.len();
+    let parent = headers.first()
+        .and_then(|header| get_block(state, &header.previous_hash))
+        .map(|block| block.get_header());
+    let ancestry = parent.as_ref()
+        .map(|parent| state.tree.get_ancestry(&parent.hash, ancestry_span(&state.config.chain)))
+        .unwrap_or_default();
+
+    match state.sync.add_headers(id, headers, parent.as_ref(), ancestry) {
+        Ok(()) if new == 0 => {
+            let target = state.sync.get_target(block_count(&state.store));
+            state.sync.cap_peer(id, target);
+        },
+        Ok(()) if more => {
+            let target = state.sync.get_target(block_count(&state.store));
+            state.sync.update_peer(id, target + 1);
+        },
+        Ok(()) => (),
+        Err(why) => {
+            dlog(module_path!(), "Discarded headers from peer", &[
+                format!("Peer: {}", id),
+                format!("Reason: {:?}", why)
+            ]);
+            state.sync.remove_peer(id);
+        }
+    }
+}
+
+// Advance the initial block download.
+// Requests headers and blocks from peers, then applies downloaded blocks in height order.
+// A peer that sent a block failing to apply is dropped from the sync.
+// Mining is paused while syncing and restarted whenever the sync goes idle.
+fn drive_sync(state: &mut State) -> () {
+    let now = Instant::now();
+    let local_count = block_count(&state.store);
+
+    if let Some(id) = state.sync.next_header_request(local_count, now) {
+        pause_mining(state);
+        let mut locator = build_locator(&state.store);
+        if let Some(hash) = state.sync.get_header_tip() {
+            locator.insert(0, hash);
+        }
+        let _ = state.net_tx.send(NetCommand::SEND(id, Message::GETHEADERS(locator)));
+    }
+
+    for (id, hashes) in state.sync.next_block_requests(now).into_iter() {
+        let items = hashes.into_iter().map(|hash| InvItem { inv_type: InvType::Block, hash: hash }).collect();
+        let _ = state.net_tx.send(NetCommand::SEND(id, Message::GETDATA(items)));
+    }
+
+    let mut applied = false;
+    while let Some((id, block)) = state.sync.next_block() {
+        match connect_block(state, &block) {
+            Ok(_) => applied = true,
+            Err(why) => {
+                log(format!("Sync failed to apply block at height {} from peer {}: {:?}, restarting sync.", block.get_height(), id, why));
+                state.sync.remove_peer(id);
+                state.sync.reset();
+                break;
+            }
+        }
+    }
+
+    if applied {
+        let count = block_count(&state.store);
+        let _ = state.net_tx.send(NetCommand::UPDATE_BLOCK_COUNT(count));
+        if !state.sync.is_syncing() {
+            log(format!("Chain sync complete, tip at height {}.", count - 1));
+            restart_template(state);
+        }
+        else if state.sync.report_due(now) {
+            let target = state.sync.get_target(count);
+            log(format!("Syncing chain: {} of {} blocks ({:.1}%).", count, target, count as f64 * 100.0 / target as f64));
+        }
+    }
+
+    // Header requests pause mining, which resumes once no peer has anything left to send.
+    if state.template.is_none() {
+        restart_template(state);
+    }
+}
+
+// Stop mining the current template, used while the node is behind its peers.
+fn pause_mining(state: &mut State) -> () {
+    if state.config.mining.enabled && state.template.take().is_some() {
+        state.miner_tx.send(MinerCommand::STOP).unwrap();
+    }
+}
+
+// Check if a block is held, on the active chain or a side branch.
+fn is_known(state: &State, hash: &[u8; 32]) -> bool {
+    return state.tree.get_side(hash).is_some() || state.store.contains(hash);
+}
+
+// Request announced blocks and txs that are not already held.
+// Txs are only requested when the node accepts txs.
+fn request_unknown(state: &State, id: PeerId, items: Vec<InvItem>) -> () {
+    let unknown: Vec<InvItem> = items.into_iter().filter(|item| match item.inv_type {
+        InvType::Block => !is_known(state, &item.hash) && !state.tree.is_invalid(&item.hash),
+        InvType::Tx => state.config.node.accept_txs && state.mempool.get_tx(&item.hash).is_none()
+    }).collect();
+
+    if !unknown.is_empty() {
+        let _ = state.net_tx.send(NetCommand::SEND(id, Message::GETDATA(unknown)));
+    }
+}
+
+// Send requested blocks from the block tree and txs from the mempool.
+// Items not held are listed in a notfound reply.
+fn send_data(state: &State, id: PeerId, items: Vec<InvItem>) -> () {
+    let mut not_found: Vec<InvItem> = vec![];
+    for item in items.into_iter() {
+        let message = match item.inv_type {
+            InvType::Block => get_block(state, &item.hash).map(Message::BLOCK),
+            InvType::Tx => state.mempool.get_tx(&item.hash).map(|tx| Message::TX(tx.box_clone()))
+        };
+        match message {
+            Some(message) => { let _ = state.net_tx.send(NetCommand::SEND(id, message)); },
+            None => not_found.push(item)
+        }
+    }
+
+    if !not_found.is_empty() {
+        let _ = state.net_tx.send(NetCommand::SEND(id, Message::NOTFOUND(not_found)));
+    }
+}
+
+// Connect a block received from a peer, relaying it and restarting mining if it became the tip.
+// Blocks requested by the sync are buffered and applied in order instead.
+fn receive_block(state: &mut State, id: PeerId, block: Block) -> () {
+    let block = match state.sync.add_block(id, block) {
+        None => return,
+        Some(block) => block
+    };
+    if is_known(state, &block.hash) {
+        return;
+    }
+
+    match connect_block(state, &block) {
+        Ok(Connected::SideBranch) => {
+            dlog(module_path!(), "Held block from peer on a side branch", &[
+                format!("Peer: {}", id),
+                format!("Height: {}", block.get_height()),
+                format!("Block Hash: {:x?}", block.hash)
+            ]);
+        },
+        Ok(_) => {
+            log(format!("Received block at height {} from peer {}, Block Hash: {:x?}.", block.get_height(), id, block.hash));
+            announce_block(state, &block, Some(id));
+            restart_template(state);
+        },
+        Err(why) => {
+            dlog(module_path!(), "Discarded block from peer", &[
+                format!("Peer: {}", id),
+                format!("Block Hash: {:x?}", block.hash),
+                format!("Reason: {:?}", why)
+            ]);
+            // The peer may be ahead of the local tip, let the sync fetch the blocks between.
+            if matches!(why, ChainError::Orphan) {
+                state.sync.update_peer(id, block.get_height() + 1);
+            }
+        }
+    }
+}
+
+// Answer a query from the REST server.
+fn handle_rest_request(state: &mut State, request: RestRequest) -> () {
+    let reply = match request.query {
+        RestQuery::BLOCK_BY_HEIGHT(height) => RestReply::BLOCK(state.store.get_by_height(height)),
+        RestQuery::BLOCK_BY_HASH(hash) => RestReply::BLOCK(state.store.get_by_hash(&hash)),
+        RestQuery::TIP => RestReply::BLOCK(state.store.get_tip()),
+        RestQuery::TX(hash) => find_tx(state, &hash),
+        RestQuery::TX_PROOF(hash) => RestReply::TX_PROOF(prove_tx(state, &hash)),
+        RestQuery::MEMPOOL => RestReply::MEMPOOL(state.mempool.select_txs(usize::MAX, usize::MAX), state.mempool.get_size()),
+        RestQuery::BALANCE(public_key) => account_balance(state, Address::from_public_key(&public_key), Some(&public_key)),
+        RestQuery::ADDRESS_BALANCE(address) => account_balance(state, address, state.ledger.get_public_key(&address)),
+        RestQuery::SUBMIT_TX(tx) => RestReply::SUBMITTED(submit_tx(state, tx)),
+        RestQuery::MINER_STATS => RestReply::MINER(miner_stats(state)),
+        RestQuery::PAUSE_MINER => RestReply::MINER(control_miner(state, MinerCommand::PAUSE)),
+        RestQuery::RESUME_MINER => RestReply::MINER(control_miner(state, MinerCommand::RESUME)),
+        RestQuery::SET_MINER_THREADS(threads) => RestReply::MINER(control_miner(state, MinerCommand::SET_THREADS(threads)))
+    };
+    let _ = request.reply.send(reply);
+}
+
+// Ask the mining server for its statistics.
+// Returns None if the node is not mining, or the mining server does not answer in time.
+fn miner_stats(state: &State) -> Option<MinerStats> {
+    if !state.config.mining.enabled {
+        return None;
+    }
+    let (stats_tx, stats_rx) = mpsc::channel();
+    state.miner_tx.send(MinerCommand::STATS(stats_tx)).ok()?;
+    return stats_rx.recv_timeout(MINER_REPLY_TIMEOUT).ok();
+}
 
-        // Instantiate mining server, set data to be mined, and start mining.
-        log(format!("Starting mining server for genesis block creation."));
-        let gen_bin = genesis.to_hashable_bin();
-        let diff = genesis.difficulty;
-        let (chain_tx, chain_rx) = mpsc::channel();
+// Send a control command to the mining server, replying with its statistics once applied.
+// Returns None if the node is not mining.
+fn control_miner(state: &State, cmnd: MinerCommand) -> Option<MinerStats> {
+    if !state.config.mining.enabled {
+        return None;
+    }
+    log(format!("Mining server control requested through the REST API: {:?}.", cmnd));
+    state.miner_tx.send(cmnd).ok()?;
+    return miner_stats(state);
+}
+
+// Reply with the balance and last tx of an account.
+// Addresses whose public key has not appeared on chain hold nothing.
+fn account_balance(state: &State, address: Address, public_key: Option<&Vec<u8>>) -> RestReply {
+    return match public_key {
+        None => RestReply::BALANCE(address, 0, [0; 32]),
+        Some(public_key) => RestReply::BALANCE(address, state.ledger.get_balance(public_key), state.ledger.get_last_tx(public_key))
+    };
+}
+
+// Find a tx in the mempool, or confirmed on the active chain.
+fn find_tx(state: &State, hash: &[u8; 32]) -> RestReply {
+    if let Some(tx) = state.mempool.get_tx(hash) {
+        return RestReply::TX(Some(tx.box_clone()), None);
+    }
+
+    let block = state.store.get_tx_height(hash).and_then(|height| state.store.get_by_height(height));
+    return match block {
+        None => RestReply::TX(None, None),
+        Some(block) => {
+            let location = (block.get_height(), block.hash);
+            RestReply::TX(block.txs.into_iter().find(|tx| tx.get_hash() == *hash), Some(location))
+        }
+    };
+}
+
+// Prove a tx is confirmed on the active chain, returning the block confirming it and the merkle proof.
+fn prove_tx(state: &State, hash: &[u8; 32]) -> Option<(Block, MerkleProof)> {
+    let block = state.store.get_tx_height(hash).and_then(|height| state.store.get_by_height(height))?;
+    let index = block.txs.iter().position(|tx| tx.get_hash() == *hash)?;
+    let proof = block.generate_tx_proof(index)?;
+    return Some((block, proof));
+}
+
+// Add a tx submitted through the REST API to the mempool, announcing it to peers if accepted.
+fn submit_tx(state: &mut State, tx: Box<dyn Tx>) -> Result<[u8; 32], SubmitError> {
+    if !state.config.node.accept_txs {
+        return Err(SubmitError::NotAccepting);
+    }
+
+    let hash = tx.get_hash();
+    state.mempool.add_tx(tx, &state.ledger).map_err(SubmitError::Rejected)?;
+    log(format!("Accepted tx submitted through the REST API, Tx Hash: {:x?}.", hash));
+    let inv = vec![ InvItem { inv_type: InvType::Tx, hash: hash } ];
+    let _ = state.net_tx.send(NetCommand::BROADCAST(Message::INV(inv), None));
+
+    return Ok(hash);
+}
+
+// Add a tx received from a peer to the mempool, relaying it if accepted.
+fn receive_tx(state: &mut State, id: PeerId, tx: Box<dyn Tx>) -> () {
+    if !state.config.node.accept_txs {
+        return;
+    }
+
+    let hash = tx.get_hash();
+    match state.mempool.add_tx(tx, &state.ledger) {
+        Ok(()) => {
+            let inv = vec![ InvItem { inv_type: InvType::Tx, hash: hash } ];
+            let _ = state.net_tx.send(NetCommand::BROADCAST(Message::INV(inv), Some(id)));
+        },
+        Err(why) => {
+            dlog(module_path!(), "Discarded tx from peer", &[
+                format!("Peer: {}", id),
+                format!("Tx Hash: {:x?}", hash),
+                format!("Reason: {:?}", why)
+            ]);
+        }
+    }
+}
+
+// Connect a block to the block tree.
+// Blocks building on the tip are appended, other blocks with a known parent are validated
+// against their parent and held on a side branch, unless they are too far below the tip.
+// Once a side branch has more cumulative work than the active chain, the node reorganises to it.
+// Equal work keeps the active chain.
+fn connect_block(state: &mut State, block: &Block) -> Result<Connected, ChainError> {
+    let tip = state.store.get_tip();
+    if tip.as_ref().map(|tip| tip.hash == block.previous_hash).unwrap_or(true) {
+        append_block(state, block)?;
+        return Ok(Connected::Extended);
+    }
+    if state.tree.is_invalid(&block.previous_hash) {
+        state.tree.mark_invalid(&block.hash, block.get_height());
+        return Err(ChainError::InvalidParent);
+    }
+    if tip.as_ref().map(|tip| block.get_height().saturating_add(MAX_FORK_DEPTH) < tip.get_height()).unwrap_or(false) {
+        return Err(ChainError::TooDeep);
+    }
+
+    let parent = get_block(state, &block.previous_hash).ok_or(ChainError::Orphan)?;
+    validate_block(block, &state_after(state, &parent)).map_err(ChainError::Invalid)?;
+
+    let work = state.tree.add_side(block.clone()).ok_or(ChainError::Orphan)?;
+    let tip_work = tip.and_then(|tip| state.tree.get_work(&tip.hash)).unwrap_or(0);
+    if work <= tip_work {
+        return Ok(Connected::SideBranch);
+    }
+
+    reorganise(state, &block.hash)?;
+    return Ok(Connected::Reorganised);
+}
+
+// Switch the active chain to the side branch ending at the given block.
+// Active blocks past the fork point are disconnected and held as a side branch,
+// and their txs are returned to the mempool if still valid on the new chain.
+// If a branch block fails to connect, it is dropped along with its descendants
+// and the original chain is restored.
+fn reorganise(state: &mut State, new_tip: &[u8; 32]) -> Result<(), ChainError> {
+    let store = &state.store;
+    let (fork_hash, branch) = state.tree.get_branch(new_tip, &|hash| store.contains(hash)).ok_or(ChainError::Orphan)?;
+    let fork_height = state.store.get_by_hash(&fork_hash).ok_or(ChainError::Missing)?.get_height();
+
+    // Disconnect the active chain back to the fork point, most recent block first.
+    let mut disconnected: Vec<Block> = vec![];
+    while state.store.tip_height().map(|height| height > fork_height).unwrap_or(false) {
+        disconnected.push(disconnect_tip(state)?);
+    }
+
+    for (connected, hash) in branch.iter().enumerate() {
+        let block = state.tree.remove_side(hash).ok_or(ChainError::Missing)?;
+        if let Err(why) = append_block(state, &block) {
+            log(format!("Failed to connect branch block at height {}: {:?}, restoring the active chain.", block.get_height(), why));
+            state.tree.mark_invalid(hash, block.get_height());
+
+            let mut reverted: Vec<Block> = vec![];
+            for _ in 0..connected {
+                reverted.push(disconnect_tip(state)?);
+            }
+            for block in disconnected.into_iter().rev() {
+                append_block(state, &block).expect("Failed to restore the active chain.");
+            }
+            for block in reverted.iter().rev() {
+                state.tree.add_side(block.clone());
+            }
+            return_txs(state, &reverted);
+            return Err(why);
+        }
+    }
+
+    for block in disconnected.iter().rev() {
+        state.tree.add_side(block.clone());
+    }
+    return_txs(state, &disconnected);
+
+    log(format!("Reorganised chain from height {}, disconnected {} block(s) and connected {}, new tip Block Hash: {:x?}.",
+        fork_height, disconnected.len(), branch.len(), new_tip));
+
+    return Ok(());
+}
+
+// Remove the tip block from the active chain, rolling back its ledger changes.
+fn disconnect_tip(state: &mut State) -> Result<Block, ChainError> {
+    let block = state.store.pop_block().map_err(ChainError::Store)?;
+    state.ledger.rollback_block().map_err(ChainError::Ledger)?;
+
+    return Ok(block);
+}
+
+// Return the txs of disconnected blocks, given most recent block first, to the mempool.
+// Txs already confirmed on the new chain or no longer valid are dropped.
+fn return_txs(state: &mut State, blocks: &[Block]) -> () {
+    for block in blocks.iter().rev() {
+        for tx in block.txs.iter() {
+            let _ = state.mempool.add_tx(tx.clone(), &state.ledger);
+        }
+    }
+}
+
+// Validate a block on top of the tip, then persist it and apply it to the ledger.
+// On an empty chain the block must be a valid genesis block.
+// Txs included in the block are removed from the mempool.
+fn append_block(state: &mut State, block: &Block) -> Result<(), ChainError> {
+    let chain_state = match state.store.get_tip() {
+        Some(tip) => state_after(state, &tip),
+        None => ChainState::genesis(bits_from_diff(state.config.chain.genesis_diff))
+    };
+    validate_block(block, &chain_state).map_err(ChainError::Invalid)?;
+
+    state.ledger.apply_block(block).map_err(ChainError::Ledger)?;
+    if let Err(why) = state.store.put_block(block) {
+        state.ledger.rollback_block().expect("Failed to roll back ledger.");
+        return Err(ChainError::Store(why));
+    }
+    state.tree.add_active(block);
+    state.tree.prune(block.get_height().saturating_sub(MAX_FORK_DEPTH));
+    state.mempool.remove_block_txs(block, &state.ledger);
+
+    return Ok(());
+}
+
+// Create and mine the genesis block, rewarding the given public key.
+// Creates an independent mining server for genesis block.
+fn mine_genesis(config: &Config, reward_key: Vec<u8>) -> Block {
+    let mut genesis: Block = Block {
+        nonce: [0; 16],
+        previous_hash: [0; 32],
+        version: BLOCK_VERSION,
+        bits: bits_from_diff(config.chain.genesis_diff),
+        height: [0; 4],
+        timestamp: get_timestamp(),
+        miner: reward_key,
+        reward_amount: [0; 4],
+        tx_count: 0,
+        tx_merkle: [0; 32],
+        txs: vec![],
+        hash: [0; 32]
+    };
+
+    // Instantiate mining server, set data to be mined, and start mining.
+    log("Starting mining server for genesis block creation.".to_string());
+    let gen_bin = genesis.to_hashable_bin();
+    let bits = genesis.bits;
+    let (chain_tx, chain_rx) = mpsc::channel();
+    let (miner_tx, miner_rx) = mpsc::channel();
+    start_mining_server(chain_tx.clone(), miner_rx, config.mining.threads);
+    miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 0, data: gen_bin, bits: bits })).unwrap();
+
+    // Once valid hash found, save result and kill miner.
+    let (_, nonce, hash) = chain_rx.recv().unwrap();
+    genesis.nonce = nonce;
+    genesis.hash = hash;
+    log(format!("Genesis Mined, Block Hash: {:x?}.", genesis.hash));
+    log("Killing genesis block mining server.".to_string());
+    miner_tx.send(MinerCommand::KILL).unwrap();
+
+    dlog(module_path!(), "Created and mined genesis block", &[ genesis.to_string() ]);
+
+    return genesis;
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::mempool::MempoolError;
+    use crate::test_util::{mine, signed_transfer, temp_path};
+    use crate::wallet_struct::test_wallet;
+    use std::fs::remove_file;
+
+    // Build chain server state on a new store file, with mining disabled.
+    fn test_state() -> State {
+        let mut config = Config::default();
+        config.chain.genesis_diff = 1;
+        config.mining.enabled = false;
+        config.node.block_store = temp_path("chain").to_string_lossy().to_string();
+
+        let (net_tx, _) = mpsc::channel();
+        let (_, net_rx) = mpsc::channel();
+        let (miner_tx, _) = mpsc::channel();
+        let (_, mining_rx) = mpsc::channel();
+        let (_, rest_rx) = mpsc::channel();
+        return State {
+            store: FileStore::open(Path::new(&config.node.block_store)).unwrap(),
+            ledger: Ledger::with_max_reward(config.chain.block_reward),
+            mempool: Mempool::with_capacity(config.chain.mempool_max_bytes),
+            reward_key: Some(test_wallet(1).public_key),
+            template: None,
+            job_id: 0,
+            sync: BlockSync::new(&config.chain),
+            tree: BlockTree::new(),
+            config: config,
+            miner_tx: miner_tx,
+            mining_rx: mining_rx,
+            net_tx: net_tx,
+            net_rx: net_rx,
+            rest_rx: rest_rx
+        }
+    }
+
+    // Build chain server state with mining enabled, returning the commands sent to the mining server.
+    fn mining_state() -> (State, mpsc::Receiver<MinerCommand>) {
+        let mut state = test_state();
         let (miner_tx, miner_rx) = mpsc::channel();
-        start_mining_server(chain_tx.clone(), miner_rx);
-        miner_tx.send(MinerCommand::UPDATE_DIFF(diff)).unwrap();
-        miner_tx.send(MinerCommand::UPDATE_DATA(gen_bin)).unwrap();
-        miner_tx.send(MinerCommand::START).unwrap();
+        state.config.mining.enabled = true;
+        state.miner_tx = miner_tx;
+        return (state, miner_rx);
+    }
 
-        // Once valid hash found, save result and kill miner.
-        let (nonce, hash) = chain_rx.recv().unwrap();
-        genesis.nonce = nonce;
-        genesis.hash = hash;
-        log(format!("Genesis Mined, Block Hash: {:x?}.", genesis.hash));
-        log(format!("Killing genesis block mining server."));
-        miner_tx.send(MinerCommand::KILL).unwrap();
+    // Mine a block on top of the parent, one target block time after it.
+    fn mine_on(state: &State, parent: Option<&Block>, miner: &[u8], txs: Vec<Box<dyn Tx>>) -> Block {
+        let mut block = Block::new();
+        block.bits = bits_from_diff(state.config.chain.genesis_diff);
+        if let Some(parent) = parent {
+            block.previous_hash = parent.hash;
+            block.height = (parent.get_height() + 1).to_be_bytes();
+            block.bits = state_after(state, parent).bits;
+            block.timestamp = (BigEndian::read_u64(&parent.timestamp) + state.config.chain.block_time as u64).to_be_bytes();
+            block.reward_amount = state.config.chain.block_reward.to_be_bytes();
+        }
+        block.miner = miner.to_vec();
+        block.tx_count = txs.len() as u8;
+        block.txs = txs;
+        block.generate_merkle();
+        mine(&mut block);
 
-        dlog(module_path!(), &"Created and mined genesis block", &[ genesis.to_string() ]);
+        return block;
     }
 
-    // Load services.
-    // start_net_interface(server_tx.clone());
-    // start_rest_server(server_tx.clone());
-    // start_fork_recovery(server_tx.clone());
+    // Build the shared start of both chains: genesis, then a block rewarding the reward key.
+    fn start_chain(state: &mut State) -> Block {
+        let genesis = mine_on(state, None, &[0], vec![]);
+        connect_block(state, &genesis).unwrap();
+        let funding = mine_on(state, Some(&genesis), &test_wallet(1).public_key, vec![]);
+        connect_block(state, &funding).unwrap();
+        return funding;
+    }
+
+    #[test]
+    fn reorganises_to_branch_with_more_work() {
+        let mut state = test_state();
+        let funding = start_chain(&mut state);
+        let tx: Box<dyn Tx> = Box::new(signed_transfer(&test_wallet(1), 10, 0, [0; 32]));
+        let tx_hash = tx.get_hash();
+        let active = mine_on(&state, Some(&funding), &[1], vec![tx]);
+        assert!(matches!(connect_block(&mut state, &active), Ok(Connected::Extended)));
+
+        // A branch of equal work is held, the active chain is kept.
+        let side = mine_on(&state, Some(&funding), &[2], vec![]);
+        assert!(matches!(connect_block(&mut state, &side), Ok(Connected::SideBranch)));
+        assert_eq!(state.store.get_tip().unwrap().hash, active.hash);
+        assert_eq!(state.ledger.get_balance(&[0xB0; 4]), 10);
+
+        let orphan = mine_on(&state, Some(&side), &[2], vec![]);
+        let mut unknown = orphan.clone();
+        unknown.previous_hash = [9; 32];
+        assert!(matches!(connect_block(&mut state, &unknown), Err(ChainError::Orphan)));
+
+        // Extending the branch gives it more work, so the node switches to it.
+        assert!(matches!(connect_block(&mut state, &orphan), Ok(Connected::Reorganised)));
+        assert_eq!(state.store.get_tip().unwrap().hash, orphan.hash);
+        assert_eq!(state.ledger.get_tip_hash(), orphan.hash);
+        assert_eq!(state.ledger.get_balance(&[0xB0; 4]), 0);
+        assert!(state.mempool.get_tx(&tx_hash).is_some());
+        assert!(state.tree.get_side(&active.hash).is_some());
+        assert!(verify_chain(&state.store, &state.config.chain).is_ok());
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+
+    #[test]
+    fn restores_active_chain_when_branch_is_invalid() {
+        let mut state = test_state();
+        let funding = start_chain(&mut state);
+        let active = mine_on(&state, Some(&funding), &[1], vec![]);
+        connect_block(&mut state, &active).unwrap();
 
-    // loop {}
-}
\ No newline at end of file
+        // The branch overdraws the wallet, which is only found once its txs are applied.
+        let overdraft: Box<dyn Tx> = Box::new(signed_transfer(&test_wallet(1), u32::MAX, 0, [0; 32]));
+        let invalid = mine_on(&state, Some(&funding), &[2], vec![overdraft]);
+        assert!(matches!(connect_block(&mut state, &invalid), Ok(Connected::SideBranch)));
+        let child = mine_on(&state, Some(&invalid), &[2], vec![]);
+        assert!(matches!(connect_block(&mut state, &child), Err(ChainError::Ledger(_))));
+
+        assert_eq!(state.store.get_tip().unwrap().hash, active.hash);
+        assert_eq!(state.ledger.get_tip_hash(), active.hash);
+        assert!(state.tree.is_invalid(&child.hash));
+        let grandchild = mine_on(&state, Some(&child), &[2], vec![]);
+        assert!(matches!(connect_block(&mut state, &grandchild), Err(ChainError::InvalidParent)));
+        assert!(verify_chain(&state.store, &state.config.chain).is_ok());
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+
+    #[test]
+    fn refuses_and_prunes_branches_far_below_tip() {
+        let mut state = test_state();
+        let funding = start_chain(&mut state);
+        let side = mine_on(&state, Some(&funding), &[2], vec![]);
+        let deep = mine_on(&state, Some(&funding), &[3], vec![]);
+
+        let mut tip = mine_on(&state, Some(&funding), &[1], vec![]);
+        connect_block(&mut state, &tip).unwrap();
+        assert!(matches!(connect_block(&mut state, &side), Ok(Connected::SideBranch)));
+        for _ in 0..MAX_FORK_DEPTH {
+            tip = mine_on(&state, Some(&tip), &[1], vec![]);
+            connect_block(&mut state, &tip).unwrap();
+        }
+
+        // The side block is now MAX_FORK_DEPTH below the tip and still held, one more block prunes it.
+        assert_eq!(tip.get_height() - side.get_height(), MAX_FORK_DEPTH);
+        assert!(state.tree.get_side(&side.hash).is_some());
+        tip = mine_on(&state, Some(&tip), &[1], vec![]);
+        connect_block(&mut state, &tip).unwrap();
+        assert!(state.tree.get_side(&side.hash).is_none());
+        assert!(matches!(connect_block(&mut state, &deep), Err(ChainError::TooDeep)));
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+
+    #[test]
+    fn resumes_mining_when_peer_has_no_new_headers() {
+        let (mut state, miner_rx) = mining_state();
+        let funding = start_chain(&mut state);
+        restart_template(&mut state);
+
+        // The peer claims more blocks than it holds, so asking it pauses mining.
+        state.sync.update_peer(7, 10);
+        drive_sync(&mut state);
+        assert!(state.sync.is_syncing());
+        assert!(state.template.is_none());
+
+        // It only sends headers already held, so it is not asked again and mining resumes.
+        let genesis = state.store.get_by_height(0).unwrap();
+        receive_headers(&mut state, 7, vec![genesis.get_header(), funding.get_header()]);
+        drive_sync(&mut state);
+        assert!(!state.sync.is_syncing());
+        assert_eq!(state.template.as_ref().map(|template| template.previous_hash), Some(funding.hash));
+        assert!(state.sync.next_header_request(block_count(&state.store), Instant::now()).is_none());
+
+        let commands: Vec<MinerCommand> = miner_rx.try_iter().collect();
+        assert!(matches!(commands.as_slice(), [MinerCommand::NEW_JOB(_), MinerCommand::STOP, MinerCommand::NEW_JOB(_)]));
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+
+    #[test]
+    fn drops_peer_whose_blocks_fail_to_apply() {
+        let (mut state, _miner_rx) = mining_state();
+        let funding = start_chain(&mut state);
+        restart_template(&mut state);
+
+        // The peer's next block overdraws the wallet, which is only found once it is applied.
+        let overdraft: Box<dyn Tx> = Box::new(signed_transfer(&test_wallet(1), u32::MAX, 0, [0; 32]));
+        let invalid = mine_on(&state, Some(&funding), &[2], vec![overdraft]);
+        state.sync.update_peer(7, 3);
+        drive_sync(&mut state);
+        receive_headers(&mut state, 7, vec![invalid.get_header()]);
+        drive_sync(&mut state);
+        receive_block(&mut state, 7, invalid);
+        assert!(state.template.is_none());
+
+        // Applying the block fails, so the peer is forgotten and mining resumes on the old tip.
+        drive_sync(&mut state);
+        assert_eq!(state.store.get_tip().unwrap().hash, funding.hash);
+        assert!(!state.sync.is_syncing());
+        assert!(state.template.is_some());
+        assert!(state.sync.next_header_request(block_count(&state.store), Instant::now()).is_none());
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+
+    #[test]
+    fn refuses_txs_of_unknown_versions() {
+        let mut state = test_state();
+        state.config.node.accept_txs = true;
+        let funding = start_chain(&mut state);
+        let mut tx = signed_transfer(&test_wallet(1), 10, 0, [0; 32]);
+        tx.version = 7;
+        tx.generate_hash();
+        tx.generate_signature(&test_wallet(1));
+
+        // Neither a submitted tx nor a peer block carrying one is accepted, so no stored block fails to decode.
+        assert!(matches!(submit_tx(&mut state, Box::new(tx.clone())), Err(SubmitError::Rejected(MempoolError::UnsupportedVersion))));
+        let block = mine_on(&state, Some(&funding), &[1], vec![Box::new(tx)]);
+        assert!(matches!(connect_block(&mut state, &block), Err(ChainError::Invalid(BlockError::UnsupportedTxVersion(0)))));
+        receive_block(&mut state, 7, block.clone());
+        assert_eq!(state.store.get_tip().unwrap().hash, funding.hash);
+        assert!(state.store.get_by_hash(&block.hash).is_none());
+
+        remove_file(&state.config.node.block_store).unwrap();
+    }
+}
@@ -0,0 +1,595 @@
+use crate::chain;
+use crate::config::{Config, ENV_PREFIX};
+use crate::address::{Address, AddressError, address_of};
+use crate::block_struct::Block;
+use crate::json::{BlockJson, format};
+use crate::ledger::Ledger;
+use crate::mempool::Mempool;
+use crate::signature_scheme::Scheme;
+use crate::store::{BlockStore, FileStore};
+use crate::tx_struct::{Tx, FinancialTx, TxType, TX_VERSION};
+use crate::log;
+use crate::util::{to_hex, from_hex, read_hidden_line};
+use crate::wallet;
+use crate::wallet_manager::{WalletManager, WalletError};
+use crate::wallet_struct::Wallet;
+use crate::keystore::{Keystore, KeystoreError};
+use byteorder::{ByteOrder, BigEndian};
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand, value_t};
+use std::fs;
+use std::path::Path;
+use std::process;
+
+
+// Print an error message and exit with a failure status.
+fn exit_with(message: String) -> ! {
+    eprintln!("error: {}", message);
+    process::exit(1);
+}
+
+// Argument selecting the block store file.
+fn store_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("store")
+        .long("store")
+        .value_name("PATH")
+        .help("Path of the block store file, overriding node.block_store");
+}
+
+// Argument selecting a wallet key by name.
+fn wallet_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("wallet")
+        .long("wallet")
+        .value_name("NAME")
+        .help("Name of the wallet key to use, instead of the default key");
+}
+
+// Argument selecting the wallet directory.
+fn wallet_dir_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("wallet-dir")
+        .long("wallet-dir")
+        .value_name("DIR")
+        .help("Directory of the wallet keys, overriding wallet.dir");
+}
+
+// Positional argument naming a wallet key.
+fn key_name_arg<'a, 'b>() -> Arg<'a, 'b> {
+    return Arg::with_name("name")
+        .value_name("NAME")
+        .required(true)
+        .help("Name of the wallet key");
+}
+
+// Build the command line interface definition.
+fn build_cli<'a, 'b>() -> App<'a, 'b> {
+    return App::new("rusty-chain")
+        .version(env!("CARGO_PKG_VERSION"))
+        .about("A primitive example blockchain node")
+        .setting(AppSettings::SubcommandRequiredElseHelp)
+        .arg(Arg::with_name("config")
+            .long("config")
+            .value_name("PATH")
+            .env("RUSTY_CHAIN_CONFIG")
+            .help("Path of the TOML config file"))
+        .subcommand(SubCommand::with_name("node")
+            .about("Run a chain node")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("run")
+                .about("Start the node services")
+                .arg(Arg::with_name("no-mine").long("no-mine").help("Do not mine blocks"))
+                .arg(Arg::with_name("threads").long("threads").value_name("COUNT").help("Number of mining threads"))
+                .arg(Arg::with_name("accept-txs").long("accept-txs").help("Accept txs into the mempool"))
+                .arg(Arg::with_name("rest").long("rest").help("Host the REST API"))
+                .arg(Arg::with_name("rest-port").long("rest-port").value_name("PORT").help("Localhost port to serve the REST API on"))
+                .arg(Arg::with_name("join").long("join").help("Join an existing chain instead of spawning a genesis block"))
+                .arg(Arg::with_name("port").long("port").value_name("PORT").help("Port to listen on for peers"))
+                .arg(Arg::with_name("peer")
+                    .long("peer")
+                    .value_name("ADDR")
+                    .multiple(true)
+                    .number_of_values(1)
+                    .help("Peer address to connect to, replacing the configured peer list"))
+                .arg(Arg::with_name("reward-key").long("reward-key").value_name("NAME").help("Wallet key credited with block rewards, overriding mining.reward_key"))
+                .arg(wallet_dir_arg())
+                .arg(store_arg())))
+        .subcommand(SubCommand::with_name("wallet")
+            .about("Manage the named keys of the node wallet")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("new")
+                .about("Generate a new wallet key, encrypted under a passphrase")
+                .arg(key_name_arg())
+                .arg(Arg::with_name("default").long("default").help("Make the new key the default key"))
+                .arg(Arg::with_name("scheme")
+                    .long("scheme")
+                    .value_name("SCHEME")
+                    .possible_values(&["rsa", "ed25519"])
+                    .case_insensitive(true)
+                    .help("Signature scheme of the new key, overriding wallet.key_algo"))
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("list")
+                .about("List the wallet keys")
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("default")
+                .about("Set the default key, used to sign and credited with block rewards")
+                .arg(key_name_arg())
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("import")
+                .about("Import a keystore, or an unencrypted key file from an earlier version, under a name")
+                .arg(key_name_arg())
+                .arg(Arg::with_name("path").value_name("PATH").required(true).help("Key file to import"))
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("export")
+                .about("Write a key's encrypted keystore to a file")
+                .arg(key_name_arg())
+                .arg(Arg::with_name("path").value_name("PATH").required(true).help("File to write the keystore to"))
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("delete")
+                .about("Delete a key, moving its keystore into the deleted subdirectory")
+                .arg(key_name_arg())
+                .arg(Arg::with_name("force").long("force").help("Delete the key even if it is the default key"))
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("passwd")
+                .about("Change the passphrase of a wallet key")
+                .arg(wallet_arg())
+                .arg(wallet_dir_arg()))
+            .subcommand(SubCommand::with_name("show")
+                .about("Show a wallet key's public key and balance")
+                .arg(wallet_arg())
+                .arg(wallet_dir_arg())
+                .arg(store_arg())))
+        .subcommand(SubCommand::with_name("tx")
+            .about("Create transactions")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("create")
+                .about("Create a signed financial tx from the wallet, to submit with POST /txs")
+                .arg(Arg::with_name("to").long("to").value_name("ADDRESS|PUBLIC_KEY").required(true).help("Address or hex encoded public key of the receiver"))
+                .arg(Arg::with_name("amount").long("amount").value_name("TOKENS").required(true).help("Number of tokens to transfer"))
+                .arg(Arg::with_name("reward").long("reward").value_name("TOKENS").default_value("0").help("Mining reward offered"))
+                .arg(Arg::with_name("after").long("after").value_name("TX_HASH").help("Pending tx of the wallet to follow, instead of its last confirmed tx"))
+                .arg(Arg::with_name("json").long("json").help("Print the tx as JSON, ready to submit to the REST API"))
+                .arg(wallet_arg())
+                .arg(wallet_dir_arg())
+                .arg(store_arg())))
+        .subcommand(SubCommand::with_name("chain")
+            .about("Inspect the stored chain")
+            .setting(AppSettings::SubcommandRequiredElseHelp)
+            .subcommand(SubCommand::with_name("show")
+                .about("Show a block by height or hash")
+                .arg(Arg::with_name("block").value_name("HEIGHT|HASH").required(true))
+                .arg(Arg::with_name("json").long("json").help("Print the block as JSON"))
+                .arg(store_arg()))
+            .subcommand(SubCommand::with_name("export")
+                .about("Export every stored block as a JSON array")
+                .arg(Arg::with_name("out").long("out").value_name("PATH").help("File to write the export to, instead of stdout"))
+                .arg(store_arg()))
+            .subcommand(SubCommand::with_name("verify")
+                .about("Re-validate every stored block")
+                .arg(store_arg())));
+}
+
+// Parse the command line and run the selected command.
+// The config is loaded first so command flags can override it.
+pub fn run() -> () {
+    let matches = build_cli().get_matches();
+    let config = match Config::load(matches.value_of("config")) {
+        Err(why) => exit_with(format!("Failed to load config: {:?}", why)),
+        Ok(config) => config
+    };
+
+    match matches.subcommand() {
+        ("node", Some(node)) => match node.subcommand() {
+            ("run", Some(args)) => node_run(args, config),
+            _ => unreachable!()
+        },
+        ("wallet", Some(wallet)) => match wallet.subcommand() {
+            ("new", Some(args)) => wallet_new(args, apply_paths(args, config)),
+            ("list", Some(args)) => wallet_list(args, apply_paths(args, config)),
+            ("default", Some(args)) => wallet_default(args, apply_paths(args, config)),
+            ("import", Some(args)) => wallet_import(args, apply_paths(args, config)),
+            ("export", Some(args)) => wallet_export(args, apply_paths(args, config)),
+            ("delete", Some(args)) => wallet_delete(args, apply_paths(args, config)),
+            ("show", Some(args)) => wallet_show(args, apply_paths(args, config)),
+            ("passwd", Some(args)) => wallet_passwd(args, apply_paths(args, config)),
+            _ => unreachable!()
+        },
+        ("tx", Some(tx)) => match tx.subcommand() {
+            ("create", Some(args)) => tx_create(args, apply_paths(args, config)),
+            _ => unreachable!()
+        },
+        ("chain", Some(chain)) => match chain.subcommand() {
+            ("show", Some(args)) => chain_show(args, apply_paths(args, config)),
+            ("verify", Some(args)) => chain_verify(args, apply_paths(args, config)),
+            ("export", Some(args)) => chain_export(args, apply_paths(args, config)),
+            _ => unreachable!()
+        },
+        _ => unreachable!()
+    }
+}
+
+// Validate the config and apply its log settings, exiting if it is invalid.
+fn validated(config: Config) -> Config {
+    if let Err(why) = config.validate() {
+        exit_with(format!("Invalid config: {:?}", why));
+    }
+    log::init(&config.log);
+    return config;
+}
+
+// Override the configured store, wallet path and key scheme from command arguments.
+fn apply_paths(args: &ArgMatches, mut config: Config) -> Config {
+    if let Some(path) = args.value_of("store") {
+        config.node.block_store = path.to_string();
+    }
+    if let Some(path) = args.value_of("wallet-dir") {
+        config.wallet.dir = path.to_string();
+    }
+    if let Some(scheme) = args.value_of("scheme") {
+        config.wallet.key_algo = scheme.to_string();
+    }
+    return validated(config);
+}
+
+// Open the configured block store.
+fn open_store(config: &Config) -> FileStore {
+    let path = &config.node.block_store;
+    return match FileStore::open(Path::new(path)) {
+        Err(why) => exit_with(format!("Failed to open block store {}: {:?}", path, why)),
+        Ok(store) => store
+    };
+}
+
+// Rebuild the ledger from the configured block store.
+fn open_ledger(config: &Config) -> Ledger {
+    return match Ledger::from_store(&open_store(config), config.chain.block_reward) {
+        Err(why) => exit_with(format!("Failed to rebuild ledger: {:?}", why)),
+        Ok(ledger) => ledger
+    };
+}
+
+// Describe a wallet error, pointing at the command that fixes it where there is one.
+fn wallet_error(why: WalletError) -> String {
+    return match why {
+        WalletError::Keystore(KeystoreError::WrongPassphrase) => "Wrong passphrase".to_string(),
+        WalletError::Keystore(KeystoreError::NotAKeystore) => "Not an encrypted keystore, add it with `wallet import`".to_string(),
+        WalletError::NotFound(name) => format!("No wallet key named {}, create one with `wallet new {}`", name, name),
+        WalletError::NoDefault => "No default wallet key, create one with `wallet new <NAME>` or choose one with `wallet default <NAME>`".to_string(),
+        WalletError::AlreadyExists(name) => format!("A wallet key named {} already exists", name),
+        WalletError::InvalidName(name) => format!("Invalid key name {:?}, names are 1 to 64 letters, digits, '-' or '_'", name),
+        WalletError::IsDefault(name) => format!("{} is the default key, choose another with `wallet default` or use --force", name),
+        WalletError::InvalidKey => "Not a keystore or an RSA private key".to_string(),
+        why => format!("Wallet error: {:?}", why)
+    };
+}
+
+// Resolve the wallet key named by the --wallet argument, or the default key.
+fn select_key(args: &ArgMatches, manager: &WalletManager) -> String {
+    return match manager.resolve(args.value_of("wallet")) {
+        Err(why) => exit_with(wallet_error(why)),
+        Ok(name) => name
+    };
+}
+
+// Read a passphrase from the RUSTY_CHAIN_<VAR> environment variable if set, otherwise prompt for it.
+// A new passphrase is asked for twice when prompting.
+fn read_passphrase(var: &str, prompt: &str, new: bool) -> String {
+    if let Ok(passphrase) = std::env::var(format!("{}{}", ENV_PREFIX, var)) {
+        return passphrase;
+    }
+    let passphrase = read_hidden_line(prompt);
+    if passphrase.is_empty() {
+        exit_with("Passphrase must not be empty".to_string());
+    }
+    if new && read_hidden_line("Repeat passphrase: ") != passphrase {
+        exit_with("Passphrases do not match".to_string());
+    }
+    return passphrase;
+}
+
+// Unlock the wallet key selected by the command arguments.
+fn open_wallet(args: &ArgMatches, config: &Config) -> Wallet {
+    let manager = WalletManager::open(&config.wallet);
+    let name = select_key(args, &manager);
+    let passphrase = read_passphrase("WALLET_PASSPHRASE", &format!("Passphrase for {}: ", name), false);
+    return match manager.unlock(&name, &passphrase) {
+        Err(why) => exit_with(wallet_error(why)),
+        Ok(wallet) => wallet
+    };
+}
+
+fn node_run(args: &ArgMatches, mut config: Config) -> () {
+    if args.is_present("no-mine") {
+        config.mining.enabled = false;
+    }
+    if args.is_present("threads") {
+        config.mining.threads = value_t!(args, "threads", u8).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("accept-txs") {
+        config.node.accept_txs = true;
+    }
+    if args.is_present("rest") {
+        config.node.rest_api = true;
+    }
+    if args.is_present("rest-port") {
+        config.node.rest_port = value_t!(args, "rest-port", u16).unwrap_or_else(|e| e.exit());
+    }
+    if args.is_present("join") {
+        config.node.spawn_chain = false;
+    }
+    if args.is_present("port") {
+        config.node.port = value_t!(args, "port", u16).unwrap_or_else(|e| e.exit());
+    }
+    if let Some(peers) = args.values_of("peer") {
+        config.node.peers = peers.map(|peer| peer.to_string()).collect();
+    }
+
+    if let Some(name) = args.value_of("reward-key") {
+        config.mining.reward_key = Some(name.to_string());
+    }
+
+    // Mining, or spawning a chain, credits block rewards to a wallet key, which only needs its public key.
+    let config = apply_paths(args, config);
+    let manager = WalletManager::open(&config.wallet);
+    let reward_key = match config.mining.enabled || config.node.spawn_chain {
+        false => None,
+        true => match manager.resolve(config.mining.reward_key.as_deref()).and_then(|name| manager.public_key(&name)) {
+            Err(why) => exit_with(wallet_error(why)),
+            Ok(public_key) => Some(public_key)
+        }
+    };
+    chain::start_server(config, reward_key);
+}
+
+fn wallet_new(args: &ArgMatches, config: Config) -> () {
+    let manager = WalletManager::open(&config.wallet);
+    let name = args.value_of("name").unwrap();
+    if let Err(why) = manager.check_free(name) {
+        exit_with(wallet_error(why));
+    }
+
+    let passphrase = read_passphrase("WALLET_PASSPHRASE", "New wallet passphrase: ", true);
+    let public_key = match manager.create(name, &passphrase, &config.wallet) {
+        Err(why) => exit_with(wallet_error(why)),
+        Ok(public_key) => public_key
+    };
+    if args.is_present("default") {
+        manager.set_default(name).unwrap_or_else(|why| exit_with(wallet_error(why)));
+    }
+    println!("Created wallet key {}", name);
+    println!("Scheme: {}", Scheme::from_name(&config.wallet.key_algo).unwrap().name());
+    println!("Address: {}", address_of(&public_key));
+    println!("Public key: {}", to_hex(&public_key));
+}
+
+fn wallet_list(_args: &ArgMatches, config: Config) -> () {
+    let keys = match WalletManager::open(&config.wallet).list() {
+        Err(why) => exit_with(wallet_error(why)),
+        Ok(keys) => keys
+    };
+    if keys.is_empty() {
+        println!("No wallet keys in {}, create one with `wallet new <NAME>`", config.wallet.dir);
+        return;
+    }
+    for key in keys.iter() {
+        let marker = if key.is_default { "*" } else { " " };
+        println!("{} {:<24} {:<8} {}", marker, key.name, key.scheme.name(), address_of(&key.public_key));
+    }
+}
+
+fn wallet_default(args: &ArgMatches, config: Config) -> () {
+    let name = args.value_of("name").unwrap();
+    if let Err(why) = WalletManager::open(&config.wallet).set_default(name) {
+        exit_with(wallet_error(why));
+    }
+    println!("Default wallet key is now {}", name);
+}
+
+fn wallet_import(args: &ArgMatches, config: Config) -> () {
+    let name = args.value_of("name").unwrap();
+    let path = args.value_of("path").unwrap();
+    let data = fs::read(path).unwrap_or_else(|why| exit_with(format!("Failed to read {}: {}", path, why)));
+
+    // Keystores keep their passphrase, unencrypted key files are encrypted under a new one.
+    let manager = WalletManager::open(&config.wallet);
+    if let Err(why) = manager.check_free(name) {
+        exit_with(wallet_error(why));
+    }
+    let passphrase = match Keystore::from_bin(&data) {
+        Err(KeystoreError::NotAKeystore) => Some(read_passphrase("WALLET_PASSPHRASE", "New wallet passphrase: ", true)),
+        _ => None
+    };
+    if let Err(why) = manager.import(name, &data, passphrase.as_deref()) {
+        exit_with(wallet_error(why));
+    }
+    println!("Imported {} as wallet key {}", path, name);
+}
+
+fn wallet_export(args: &ArgMatches, config: Config) -> () {
+    let name = args.value_of("name").unwrap();
+    let path = args.value_of("path").unwrap();
+    if Path::new(path).exists() {
+        exit_with(format!("{} already exists", path));
+    }
+    let keystore = WalletManager::open(&config.wallet).export(name).unwrap_or_else(|why| exit_with(wallet_error(why)));
+    if let Err(why) = wallet::write_owner_only(path, &keystore) {
+        exit_with(wallet_error(WalletError::Keystore(why)));
+    }
+    println!("Exported wallet key {} to {}, it is still encrypted under its passphrase", name, path);
+}
+
+fn wallet_delete(args: &ArgMatches, config: Config) -> () {
+    let name = args.value_of("name").unwrap();
+    if let Err(why) = WalletManager::open(&config.wallet).delete(name, args.is_present("force")) {
+        exit_with(wallet_error(why));
+    }
+    println!("Deleted wallet key {}, its keystore was moved to {}", name, Path::new(&config.wallet.dir).join("deleted").display());
+}
+
+fn wallet_show(args: &ArgMatches, config: Config) -> () {
+    let manager = WalletManager::open(&config.wallet);
+    let name = select_key(args, &manager);
+    let public_key = manager.public_key(&name).unwrap_or_else(|why| exit_with(wallet_error(why)));
+    let scheme = manager.scheme(&name).unwrap_or_else(|why| exit_with(wallet_error(why)));
+    let ledger = open_ledger(&config);
+
+    println!("Wallet key: {}", name);
+    println!("Scheme: {}", scheme.name());
+    println!("Address: {}", address_of(&public_key));
+    println!("Public key: {}", to_hex(&public_key));
+    println!("Balance: {}", ledger.get_balance(&public_key));
+}
+
+fn wallet_passwd(args: &ArgMatches, config: Config) -> () {
+    let manager = WalletManager::open(&config.wallet);
+    let name = select_key(args, &manager);
+    let old = read_passphrase("WALLET_PASSPHRASE", "Current passphrase: ", false);
+    let new = read_passphrase("WALLET_NEW_PASSPHRASE", "New passphrase: ", true);
+    if let Err(why) = manager.change_passphrase(&name, &old, &new) {
+        exit_with(wallet_error(why));
+    }
+    println!("Changed passphrase of wallet key {}", name);
+}
+
+// Describe an address error, pointing at the likely typo where one is found.
+fn address_error(text: &str, why: AddressError) -> String {
+    return match why {
+        AddressError::BadChecksum(Some(pos)) => format!("Address {} fails its checksum, check character {} ({})", text, pos + 1, &text[pos..pos + 1]),
+        AddressError::BadChecksum(None) => format!("Address {} fails its checksum, check it for typos", text),
+        AddressError::InvalidCharacter(c, pos) => format!("Address {} has invalid character {:?} at position {}, addresses never contain 0, O, I or l", text, c, pos + 1),
+        why => format!("Invalid address {}: {:?}", text, why)
+    };
+}
+
+// Parse a receiver given by address or hex public key.
+// Addresses are resolved to the public key they were derived from, which must have appeared on chain.
+fn parse_receiver(text: &str, ledger: &Ledger) -> Vec<u8> {
+    return match (Address::parse(text), from_hex(text)) {
+        (Ok(address), _) => match ledger.get_public_key(&address) {
+            None => exit_with(format!("Address {} has not appeared on chain yet, send to its public key instead", text)),
+            Some(public_key) => public_key.clone()
+        },
+        (Err(_), Some(public_key)) => public_key,
+        (Err(why), None) => exit_with(address_error(text, why))
+    };
+}
+
+// Create and sign a tx without sending it, so it can be submitted to any node's REST API.
+// Txs chain through previous_hash, so a tx following one still pending names it with --after.
+fn tx_create(args: &ArgMatches, config: Config) -> () {
+    let wallet = open_wallet(args, &config);
+    let ledger = open_ledger(&config);
+    let receiver = parse_receiver(args.value_of("to").unwrap(), &ledger);
+    let amount = value_t!(args, "amount", u32).unwrap_or_else(|e| e.exit());
+    let reward = value_t!(args, "reward", u32).unwrap_or_else(|e| e.exit());
+    let after: Option<[u8; 32]> = args.value_of("after").map(|text| match from_hex(text) {
+        Some(hash) if hash.len() == 32 => {
+            let mut key = [0; 32];
+            key.copy_from_slice(&hash);
+            key
+        },
+        _ => exit_with(format!("Expected a 64 character tx hash, found: {}", text))
+    });
+
+    let mut tx = FinancialTx {
+        version: TX_VERSION,
+        tx_type: TxType::Financial,
+        scheme: wallet.scheme,
+        owner: wallet.public_key.clone(),
+        receiver: receiver,
+        quantity: amount.to_be_bytes(),
+        reward: reward.to_be_bytes(),
+        previous_hash: after.unwrap_or_else(|| ledger.get_last_tx(&wallet.public_key)),
+        hash: [0; 32],
+        signature: vec![]
+    };
+    tx.generate_hash();
+    tx.generate_signature(&wallet);
+
+    // Check the tx would be accepted against the local chain before printing it.
+    // A tx following a pending tx can only be checked by the node holding it.
+    if after.is_none() {
+        if let Err(why) = Mempool::with_capacity(config.chain.mempool_max_bytes).add_tx(Box::new(tx.clone()), &ledger) {
+            exit_with(format!("Tx rejected: {:?}", why));
+        }
+    }
+
+    if args.is_present("json") {
+        println!("{}", tx.to_json());
+        return;
+    }
+    println!("Tx hash: {}", to_hex(&tx.hash));
+    println!("Tx binary: {}", to_hex(&tx.to_bin()));
+}
+
+fn chain_show(args: &ArgMatches, config: Config) -> () {
+    let store = open_store(&config);
+    let query = args.value_of("block").unwrap();
+
+    let block: Option<Block> = if query.len() == 64 {
+        match from_hex(query) {
+            None => exit_with(format!("Invalid block hash: {}", query)),
+            Some(hash) => {
+                let mut key = [0; 32];
+                key.copy_from_slice(&hash);
+                store.get_by_hash(&key)
+            }
+        }
+    } else {
+        match query.parse::<u32>() {
+            Err(_) => exit_with(format!("Expected a block height or 64 character hash, found: {}", query)),
+            Ok(height) => store.get_by_height(height)
+        }
+    };
+
+    match block {
+        None => exit_with(format!("Block not found: {}", query)),
+        Some(block) if args.is_present("json") => println!("{}", block.to_json()),
+        Some(block) => {
+            println!("Height: {}", block.get_height());
+            println!("Hash: {}", to_hex(&block.hash));
+            println!("Previous hash: {}", to_hex(&block.previous_hash));
+            println!("Timestamp: {}", BigEndian::read_u64(&block.timestamp));
+            println!("Target bits: {:#010x}", block.bits);
+            println!("Nonce: {}", to_hex(&block.nonce));
+            println!("Miner: {}", address_of(&block.miner));
+            println!("Reward: {}", BigEndian::read_u32(&block.reward_amount));
+            println!("Tx merkle root: {}", to_hex(&block.tx_merkle));
+            println!("Txs: {}", block.tx_count);
+            for tx in block.txs.iter() {
+                println!("  - {:?} tx {}", tx.get_type(), to_hex(&tx.get_hash()));
+            }
+        }
+    }
+}
+
+fn chain_verify(_args: &ArgMatches, config: Config) -> () {
+    let store = open_store(&config);
+
+    match (store.tip_height(), chain::verify_chain(&store, &config.chain)) {
+        (None, _) => println!("Block store is empty."),
+        (Some(tip_height), Ok(_)) => println!("Chain valid, verified {} block(s) up to height {}.", tip_height + 1, tip_height),
+        (_, Err((height, why))) => exit_with(format!("Invalid block at height {}: {:?}", height, why))
+    }
+}
+
+fn chain_export(args: &ArgMatches, config: Config) -> () {
+    let store = open_store(&config);
+    let mut blocks: Vec<BlockJson> = vec![];
+    if let Some(tip_height) = store.tip_height() {
+        for height in 0..=tip_height {
+            match store.get_by_height(height) {
+                None => exit_with(format!("Failed to read block at height {}", height)),
+                Some(block) => blocks.push(BlockJson::from_block(&block))
+            }
+        }
+    }
+
+    let export = format(&blocks);
+    match args.value_of("out") {
+        None => println!("{}", export),
+        Some(path) => {
+            if let Err(why) = fs::write(path, export) {
+                exit_with(format!("Failed to write export to {}: {}", path, why));
+            }
+            println!("Exported {} block(s) to {}", blocks.len(), path);
+        }
+    }
+}
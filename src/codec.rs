@@ -0,0 +1,117 @@
+use byteorder::{ByteOrder, BigEndian};
+
+// Errors raised when decoding a binary back into a struct.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum DecodeError {
+    Truncated(usize, usize),    // Binary ended early (read position, bytes required)
+    TrailingBytes(usize),       // Bytes left over once decoding finished
+    UnknownTxType(u8),          // Tx type byte not matching any TxType
+    UnexpectedTxType(u8),       // Tx type byte valid but not the type being decoded
+    UnknownScheme(u8),          // Signature scheme id byte not matching any Scheme
+    UnsupportedVersion(u8),     // Block or tx version byte not matching its binary format
+    TooLong(usize, usize)       // Prefixed field longer than allowed (length, maximum)
+}
+
+// Return the next len bytes of a binary and advance the read position.
+pub fn take<'a>(bin: &'a [u8], pos: &mut usize, len: usize) -> Result<&'a [u8], DecodeError> {
+    let val = pos.checked_add(len)
+        .and_then(|end_pos| bin.get(*pos..end_pos))
+        .ok_or(DecodeError::Truncated(*pos, len))?;
+    *pos += len;
+    return Ok(val);
+}
+
+// Read a fixed size byte array.
+pub fn take_array<const N: usize>(bin: &[u8], pos: &mut usize) -> Result<[u8; N], DecodeError> {
+    let mut array = [0; N];
+    array.copy_from_slice(take(bin, pos, N)?);
+    return Ok(array);
+}
+
+// Read a single byte.
+pub fn take_u8(bin: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    return Ok(take(bin, pos, 1)?[0]);
+}
+
+// Read a big-endian u16.
+pub fn take_u16(bin: &[u8], pos: &mut usize) -> Result<u16, DecodeError> {
+    return Ok(BigEndian::read_u16(take(bin, pos, 2)?));
+}
+
+// Read a big-endian u32.
+pub fn take_u32(bin: &[u8], pos: &mut usize) -> Result<u32, DecodeError> {
+    return Ok(BigEndian::read_u32(take(bin, pos, 4)?));
+}
+
+// Read a variable length field prefixed with its u16 length.
+pub fn take_prefixed(bin: &[u8], pos: &mut usize) -> Result<Vec<u8>, DecodeError> {
+    let len = take_u16(bin, pos)? as usize;
+    return Ok(take(bin, pos, len)?.to_vec());
+}
+
+// Write a length or item count as a big-endian u16.
+// Panics if it does not fit, rather than writing a binary that decodes differently.
+pub fn push_u16_len(binary: &mut Vec<u8>, len: usize) -> () {
+    assert!(len <= u16::MAX as usize, "Length {} does not fit a u16 prefix", len);
+    binary.extend_from_slice(&(len as u16).to_be_bytes());
+}
+
+// Write a variable length field prefixed with its u16 length.
+pub fn push_prefixed(binary: &mut Vec<u8>, field: &[u8]) -> () {
+    push_u16_len(binary, field.len());
+    binary.extend_from_slice(field);
+}
+
+// Check the whole binary has been consumed.
+pub fn check_end(bin: &[u8], pos: usize) -> Result<(), DecodeError> {
+    if pos < bin.len() {
+        return Err(DecodeError::TrailingBytes(bin.len() - pos));
+    }
+
+    return Ok(());
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn take_advances_position() {
+        let bin: Vec<u8> = vec![1, 2, 3, 4, 5];
+        let mut pos: usize = 0;
+
+        assert_eq!(take(&bin, &mut pos, 2), Ok(&bin[0..2]));
+        assert_eq!(take_u16(&bin, &mut pos), Ok(0x0304));
+        assert_eq!(pos, 4);
+        assert_eq!(check_end(&bin, pos), Err(DecodeError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn take_past_end_is_truncated() {
+        let bin: Vec<u8> = vec![1, 2, 3];
+        let mut pos: usize = 2;
+
+        assert_eq!(take_u32(&bin, &mut pos), Err(DecodeError::Truncated(2, 4)));
+        assert_eq!(pos, 2);
+    }
+
+    #[test]
+    fn prefixed_round_trip() {
+        let field: Vec<u8> = vec![9; 300];
+        let mut binary: Vec<u8> = vec![];
+        push_prefixed(&mut binary, &field);
+
+        let mut pos: usize = 0;
+        assert_eq!(take_prefixed(&binary, &mut pos), Ok(field));
+        assert_eq!(check_end(&binary, pos), Ok(()));
+    }
+
+    #[test]
+    #[should_panic(expected = "does not fit a u16 prefix")]
+    fn prefixed_rejects_oversized_field() {
+        let field: Vec<u8> = vec![0; u16::MAX as usize + 1];
+        push_prefixed(&mut vec![], &field);
+    }
+}
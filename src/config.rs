@@ -0,0 +1,415 @@
+use crate::env;
+use crate::util::try_parse_net_address;
+use crate::signature_scheme::{Scheme, MIN_PUB_EXP};
+use crate::keystore::{MIN_ITERATIONS, MAX_ITERATIONS};
+use serde::Deserialize;
+use std::fs::read_to_string;
+use std::net::SocketAddr;
+use std::path::Path;
+
+// Prefix of environment variables overriding config file values.
+// Variables are named <PREFIX><SECTION>_<KEY>, e.g. RUSTY_CHAIN_NODE_PORT.
+pub const ENV_PREFIX: &str = "RUSTY_CHAIN_";
+
+// Reasons a configuration cannot be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+    Io(String),                     // Config file could not be read
+    Parse(String),                  // Config file is not valid TOML for the schema
+    InvalidEnvVar(String, String),  // Environment override could not be parsed (variable, value)
+    Invalid(&'static str, String)   // Value is out of range (field, reason)
+}
+
+/**
+ * Node configuration.
+ * Loaded from a TOML file with one table per section, then overridden
+ * by environment variables. Any value not set falls back to the
+ * defaults defined in env.rs.
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub node: NodeConfig,
+    pub mining: MiningConfig,
+    pub chain: ChainConfig,
+    pub log: LogConfig,
+    pub wallet: WalletConfig
+}
+
+// Network and storage settings of the node.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct NodeConfig {
+    pub port: u16,
+    pub peers: Vec<String>,
+    pub max_peers: usize,
+    pub spawn_chain: bool,
+    pub accept_txs: bool,
+    pub rest_api: bool,
+    pub rest_port: u16,
+    pub block_store: String
+}
+
+// Block production settings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MiningConfig {
+    pub enabled: bool,
+    pub threads: u8,
+    pub reward_key: Option<String>     // Wallet key credited with block rewards, the default key if not set
+}
+
+// Consensus parameters of the chain.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ChainConfig {
+    pub genesis_diff: u8,
+    pub block_time: u32,
+    pub retarget_window: u32,
+    pub block_reward: u32,
+    pub mempool_max_bytes: usize
+}
+
+// Log output settings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub debug: bool,
+    pub save: bool,
+    pub dir: String
+}
+
+// Wallet key settings.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct WalletConfig {
+    pub dir: String,
+    pub key_algo: String,
+    pub key_size: u32,
+    pub key_pub_exp: u32,
+    pub kdf_iterations: u32
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        return Config {
+            node: NodeConfig::default(),
+            mining: MiningConfig::default(),
+            chain: ChainConfig::default(),
+            log: LogConfig::default(),
+            wallet: WalletConfig::default()
+        }
+    }
+}
+
+impl Default for NodeConfig {
+    fn default() -> NodeConfig {
+        return NodeConfig {
+            port: env::DEFAULT_PORT,
+            peers: env::PEERS_LIST.iter().map(|peer| peer.to_string()).collect(),
+            max_peers: env::MAX_PEERS,
+            spawn_chain: env::SPAWN_CHAIN,
+            accept_txs: false,
+            rest_api: false,
+            rest_port: env::REST_PORT,
+            block_store: env::BLOCK_STORE.to_string()
+        }
+    }
+}
+
+impl Default for MiningConfig {
+    fn default() -> MiningConfig {
+        return MiningConfig {
+            enabled: env::MINING_NODE,
+            threads: available_threads(),
+            reward_key: None
+        }
+    }
+}
+
+// Return the number of CPUs available to mine on.
+// Falls back to the default thread count if it cannot be determined.
+fn available_threads() -> u8 {
+    return match std::thread::available_parallelism() {
+        Ok(count) => count.get().min(u8::MAX as usize) as u8,
+        Err(_) => env::MINING_THREADS
+    };
+}
+
+impl Default for ChainConfig {
+    fn default() -> ChainConfig {
+        return ChainConfig {
+            genesis_diff: env::GENESIS_DIFF,
+            block_time: env::BLOCK_TIME,
+            retarget_window: env::RETARGET_WINDOW,
+            block_reward: env::BLOCK_REWARD,
+            mempool_max_bytes: env::MEMPOOL_MAX_BYTES
+        }
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> LogConfig {
+        return LogConfig {
+            debug: env::DEBUG,
+            save: env::SAVE_LOG,
+            dir: env::LOG_DIR.to_string()
+        }
+    }
+}
+
+impl Default for WalletConfig {
+    fn default() -> WalletConfig {
+        return WalletConfig {
+            dir: env::WALLET_DIR.to_string(),
+            key_algo: env::KEY_ALGO.to_string(),
+            key_size: env::KEY_SIZE,
+            key_pub_exp: env::KEY_PUB_EXP,
+            kdf_iterations: env::KDF_ITERATIONS
+        }
+    }
+}
+
+impl NodeConfig {
+    // Return the parsed peer addresses.
+    // Malformed addresses are skipped, they are rejected by Config::validate.
+    pub fn get_peers(&self) -> Vec<SocketAddr> {
+        return self.peers.iter().filter_map(|peer| try_parse_net_address(peer)).collect();
+    }
+}
+
+impl Config {
+    // Load the configuration from a TOML file, then apply environment overrides.
+    // If no path is given the default config file is used when present.
+    // The result is not validated, call validate once all overrides are applied.
+    pub fn load(path: Option<&str>) -> Result<Config, ConfigError> {
+        let mut config = match path {
+            Some(path) => Config::from_file(path)?,
+            None if Path::new(env::CONFIG_FILE).exists() => Config::from_file(env::CONFIG_FILE)?,
+            None => Config::default()
+        };
+        config.apply_env(|var| std::env::var(var).ok())?;
+
+        return Ok(config);
+    }
+
+    // Read and parse a TOML config file.
+    pub fn from_file(path: &str) -> Result<Config, ConfigError> {
+        let contents = read_to_string(path).map_err(|why| ConfigError::Io(format!("{}: {}", path, why)))?;
+        return Config::from_toml(&contents);
+    }
+
+    // Parse a TOML config string.
+    pub fn from_toml(contents: &str) -> Result<Config, ConfigError> {
+        return toml::from_str(contents).map_err(|why| ConfigError::Parse(why.to_string()));
+    }
+
+    // Override values from environment variables.
+    // Takes a lookup function so overrides can be supplied without touching the process environment.
+    pub fn apply_env<F: Fn(&str) -> Option<String>>(&mut self, lookup: F) -> Result<(), ConfigError> {
+        let get = |key: &str| lookup(&format!("{}{}", ENV_PREFIX, key)).map(|value| (format!("{}{}", ENV_PREFIX, key), value));
+
+        if let Some((var, value)) = get("NODE_PORT") { self.node.port = parse_env(var, value)?; }
+        if let Some((_, value)) = get("NODE_PEERS") {
+            self.node.peers = value.split(',').map(|peer| peer.trim().to_string()).filter(|peer| !peer.is_empty()).collect();
+        }
+        if let Some((var, value)) = get("NODE_MAX_PEERS") { self.node.max_peers = parse_env(var, value)?; }
+        if let Some((var, value)) = get("NODE_SPAWN_CHAIN") { self.node.spawn_chain = parse_env(var, value)?; }
+        if let Some((var, value)) = get("NODE_ACCEPT_TXS") { self.node.accept_txs = parse_env(var, value)?; }
+        if let Some((var, value)) = get("NODE_REST_API") { self.node.rest_api = parse_env(var, value)?; }
+        if let Some((var, value)) = get("NODE_REST_PORT") { self.node.rest_port = parse_env(var, value)?; }
+        if let Some((_, value)) = get("NODE_BLOCK_STORE") { self.node.block_store = value; }
+
+        if let Some((var, value)) = get("MINING_ENABLED") { self.mining.enabled = parse_env(var, value)?; }
+        if let Some((var, value)) = get("MINING_THREADS") { self.mining.threads = parse_env(var, value)?; }
+        if let Some((_, value)) = get("MINING_REWARD_KEY") { self.mining.reward_key = Some(value); }
+
+        if let Some((var, value)) = get("CHAIN_GENESIS_DIFF") { self.chain.genesis_diff = parse_env(var, value)?; }
+        if let Some((var, value)) = get("CHAIN_BLOCK_TIME") { self.chain.block_time = parse_env(var, value)?; }
+        if let Some((var, value)) = get("CHAIN_RETARGET_WINDOW") { self.chain.retarget_window = parse_env(var, value)?; }
+        if let Some((var, value)) = get("CHAIN_BLOCK_REWARD") { self.chain.block_reward = parse_env(var, value)?; }
+        if let Some((var, value)) = get("CHAIN_MEMPOOL_MAX_BYTES") { self.chain.mempool_max_bytes = parse_env(var, value)?; }
+
+        if let Some((var, value)) = get("LOG_DEBUG") { self.log.debug = parse_env(var, value)?; }
+        if let Some((var, value)) = get("LOG_SAVE") { self.log.save = parse_env(var, value)?; }
+        if let Some((_, value)) = get("LOG_DIR") { self.log.dir = value; }
+
+        if let Some((_, value)) = get("WALLET_DIR") { self.wallet.dir = value; }
+        if let Some((_, value)) = get("WALLET_KEY_ALGO") { self.wallet.key_algo = value; }
+        if let Some((var, value)) = get("WALLET_KEY_SIZE") { self.wallet.key_size = parse_env(var, value)?; }
+        if let Some((var, value)) = get("WALLET_KEY_PUB_EXP") { self.wallet.key_pub_exp = parse_env(var, value)?; }
+        if let Some((var, value)) = get("WALLET_KDF_ITERATIONS") { self.wallet.kdf_iterations = parse_env(var, value)?; }
+
+        return Ok(());
+    }
+
+    // Check every value is usable before the node starts.
+    pub fn validate(&self) -> Result<(), ConfigError> {
+        if let Some(peer) = self.node.peers.iter().find(|peer| try_parse_net_address(peer).is_none()) {
+            return Err(ConfigError::Invalid("node.peers", format!("malformed address {}", peer)));
+        }
+        if self.node.max_peers == 0 {
+            return Err(ConfigError::Invalid("node.max_peers", "at least one peer is required".to_string()));
+        }
+        if self.node.rest_api && self.node.rest_port == self.node.port {
+            return Err(ConfigError::Invalid("node.rest_port", "must differ from node.port".to_string()));
+        }
+        if self.node.block_store.is_empty() {
+            return Err(ConfigError::Invalid("node.block_store", "path must not be empty".to_string()));
+        }
+        if self.mining.threads == 0 {
+            return Err(ConfigError::Invalid("mining.threads", "at least one thread is required".to_string()));
+        }
+        if self.chain.genesis_diff == 0 || self.chain.genesis_diff > 248 {
+            return Err(ConfigError::Invalid("chain.genesis_diff", "must be between 1 and 248".to_string()));
+        }
+        if self.chain.block_time == 0 {
+            return Err(ConfigError::Invalid("chain.block_time", "must be at least one second".to_string()));
+        }
+        if self.chain.retarget_window == 0 || self.chain.retarget_window > 10_000 {
+            return Err(ConfigError::Invalid("chain.retarget_window", "must be between 1 and 10000 blocks".to_string()));
+        }
+        if self.chain.mempool_max_bytes == 0 {
+            return Err(ConfigError::Invalid("chain.mempool_max_bytes", "must be greater than zero".to_string()));
+        }
+        if self.log.save && self.log.dir.is_empty() {
+            return Err(ConfigError::Invalid("log.dir", "path must not be empty when saving logs".to_string()));
+        }
+        if self.wallet.dir.is_empty() {
+            return Err(ConfigError::Invalid("wallet.dir", "path must not be empty".to_string()));
+        }
+        let scheme = match Scheme::from_name(&self.wallet.key_algo) {
+            None => return Err(ConfigError::Invalid("wallet.key_algo", format!("unsupported algorithm {}, expected RSA or Ed25519", self.wallet.key_algo))),
+            Some(scheme) => scheme
+        };
+        // Tx signatures hold at most 512 bytes, the signature length of a 4096-bit key.
+        if scheme == Scheme::Rsa && ![2048, 3072, 4096].contains(&self.wallet.key_size) {
+            return Err(ConfigError::Invalid("wallet.key_size", "RSA keys must be 2048, 3072 or 4096 bits".to_string()));
+        }
+        if self.wallet.key_pub_exp < MIN_PUB_EXP || self.wallet.key_pub_exp.is_multiple_of(2) {
+            return Err(ConfigError::Invalid("wallet.key_pub_exp", format!("must be an odd number of at least {}", MIN_PUB_EXP)));
+        }
+        if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&self.wallet.kdf_iterations) {
+            return Err(ConfigError::Invalid("wallet.kdf_iterations", format!("must be from {} to {}", MIN_ITERATIONS, MAX_ITERATIONS)));
+        }
+
+        return Ok(());
+    }
+}
+
+// Parse an environment override into the type of the value it replaces.
+fn parse_env<T: std::str::FromStr>(var: String, value: String) -> Result<T, ConfigError> {
+    return match value.trim().parse::<T>() {
+        Err(_) => Err(ConfigError::InvalidEnvVar(var, value)),
+        Ok(parsed) => Ok(parsed)
+    };
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use std::collections::HashMap;
+
+    fn lookup(vars: &[(&str, &str)]) -> impl Fn(&str) -> Option<String> {
+        let vars: HashMap<String, String> = vars.iter().map(|(k, v)| (k.to_string(), v.to_string())).collect();
+        return move |var: &str| vars.get(var).cloned();
+    }
+
+    #[test]
+    fn defaults_match_env() {
+        let config = Config::default();
+
+        assert_eq!(config.node.port, env::DEFAULT_PORT);
+        assert_eq!(config.node.get_peers().len(), env::PEERS_LIST.len());
+        assert_eq!(config.chain.genesis_diff, env::GENESIS_DIFF);
+        assert_eq!(config.wallet.key_size, env::KEY_SIZE);
+        assert_eq!(config.validate(), Ok(()));
+    }
+
+    #[test]
+    fn partial_file_keeps_defaults() {
+        let config = Config::from_toml("
+            [node]
+            port = 6000
+            peers = [\"127.0.0.1:6001\"]
+
+            [mining]
+            threads = 2
+        ").unwrap();
+
+        assert_eq!(config.node.port, 6000);
+        assert_eq!(config.node.get_peers(), vec!["127.0.0.1:6001".parse::<SocketAddr>().unwrap()]);
+        assert_eq!(config.mining.threads, 2);
+        assert_eq!(config.mining.enabled, env::MINING_NODE);
+        assert_eq!(config.chain, ChainConfig::default());
+    }
+
+    #[test]
+    fn rejects_unknown_and_mistyped_keys() {
+        assert!(matches!(Config::from_toml("[node]\nprot = 6000"), Err(ConfigError::Parse(_))));
+        assert!(matches!(Config::from_toml("[node]\nport = \"high\""), Err(ConfigError::Parse(_))));
+        assert!(matches!(Config::from_toml("[minnig]\nthreads = 2"), Err(ConfigError::Parse(_))));
+    }
+
+    #[test]
+    fn env_overrides_file() {
+        let mut config = Config::from_toml("[node]\nport = 6000").unwrap();
+        config.apply_env(lookup(&[
+            ("RUSTY_CHAIN_NODE_PORT", "7000"),
+            ("RUSTY_CHAIN_NODE_PEERS", "127.0.0.1:7001, 127.0.0.1:7002"),
+            ("RUSTY_CHAIN_MINING_ENABLED", "false"),
+            ("RUSTY_CHAIN_MINING_REWARD_KEY", "cold"),
+            ("RUSTY_CHAIN_LOG_DIR", "/tmp/logs")
+        ])).unwrap();
+
+        assert_eq!(config.node.port, 7000);
+        assert_eq!(config.node.peers, vec!["127.0.0.1:7001", "127.0.0.1:7002"]);
+        assert!(!config.mining.enabled);
+        assert_eq!(config.mining.reward_key, Some("cold".to_string()));
+        assert_eq!(config.log.dir, "/tmp/logs");
+    }
+
+    #[test]
+    fn rejects_malformed_env_override() {
+        let mut config = Config::default();
+        let result = config.apply_env(lookup(&[("RUSTY_CHAIN_MINING_THREADS", "many")]));
+
+        assert_eq!(result, Err(ConfigError::InvalidEnvVar("RUSTY_CHAIN_MINING_THREADS".to_string(), "many".to_string())));
+    }
+
+    #[test]
+    fn validate_rejects_out_of_range_values() {
+        let mut config = Config::default();
+        config.node.peers.push("127.0.0.1".to_string());
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("node.peers", _))));
+
+        let mut config = Config::default();
+        config.mining.threads = 0;
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("mining.threads", _))));
+
+        let mut config = Config::default();
+        config.node.rest_api = true;
+        config.node.rest_port = config.node.port;
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("node.rest_port", _))));
+
+        let mut config = Config::default();
+        config.wallet.key_algo = "DSA".to_string();
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("wallet.key_algo", _))));
+
+        // The key size only applies to RSA keys.
+        let mut config = Config::default();
+        config.wallet.key_size = 1024;
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("wallet.key_size", _))));
+        config.wallet.key_algo = "ed25519".to_string();
+        assert_eq!(config.validate(), Ok(()));
+
+        // Exponents ring refuses to sign with are rejected before a key is generated.
+        let mut config = Config::default();
+        config.wallet.key_pub_exp = 3;
+        assert!(matches!(config.validate(), Err(ConfigError::Invalid("wallet.key_pub_exp", _))));
+        config.wallet.key_pub_exp = 65539;
+        assert_eq!(config.validate(), Ok(()));
+    }
+}
@@ -1,3 +1,6 @@
+// Default configuration values.
+// Each can be overridden at runtime by the config file or environment, see config.rs.
+
 // Initial peer(s) list on join
 // To add a new address increase array index and add to list.
 pub const PEERS_LIST: [&str; 3] = [
@@ -10,14 +13,22 @@ pub const DEBUG: bool = true;                       // Flag to output debugging
 pub const SAVE_LOG: bool = true;                    // Flag stating if log should be saved to file
 pub const SPAWN_CHAIN: bool = true;                 // Flag to state if node should start network at genesis block
 pub const MINING_NODE: bool = true;                 // Flag to state if node should mine blocks
-pub const MINING_THREADS: u8 = 1;                   // The number of mining threads to be used
+pub const MINING_THREADS: u8 = 4;                   // Mining threads used when the number of CPUs is unknown
 pub const DEFAULT_PORT: u16 = 55845;                // Default port: chosen after the atomic weight of Iron (Fe) 55.845
+pub const REST_PORT: u16 = 55840;                   // Default port of the REST API, served on localhost only
+pub const MAX_PEERS: usize = 8;                     // Most peer connections held open at once
+pub const BLOCK_STORE: &str = "chain/blocks.dat";   // Path of the append-only block store file
+pub const WALLET_DIR: &str = "wallet";              // Directory holding the named wallet keystores
+pub const CONFIG_FILE: &str = "rusty-chain.toml";   // Config file loaded when no path is given
+pub const LOG_DIR: &str = "logs";                   // Directory log files are saved to
 
 pub const GENESIS_DIFF: u8 = 20;                    // Starting difficulty for the genesis block
 pub const BLOCK_TIME: u32 = 120;                    // Expected block time in seconds
+pub const RETARGET_WINDOW: u32 = 45;                // Number of recent block times each target is calculated from
+pub const BLOCK_REWARD: u32 = 50;                   // Maximum tokens minted for the miner of each block
+pub const MEMPOOL_MAX_BYTES: usize = 4_000_000;     // Memory cap for pending txs, measured in tx binary bytes
 
-pub const KEY_ALGO: &str = "RSA";                   // Asymmetric encryption key protocol used for Wallets
+pub const KEY_ALGO: &str = "RSA";                   // Signature scheme of new wallet keys, RSA or Ed25519
 pub const KEY_SIZE: u32 = 2048;                     // Key-size in bits
 pub const KEY_PUB_EXP: u32 = 65537;                 // Public exponenent used for key generation
-
-pub const MINER_PROCESS: u8 = 4;                    // Number of mining threads to be used
\ No newline at end of file
+pub const KDF_ITERATIONS: u32 = 600_000;            // PBKDF2 rounds deriving a keystore key from its passphrase
\ No newline at end of file
@@ -0,0 +1,257 @@
+use crate::block_struct::Block;
+use crate::store::BlockStore;
+use crate::target::block_work;
+use crate::retarget::BlockTime;
+use std::collections::HashMap;
+
+pub const MAX_FORK_DEPTH: u32 = 100;    // Most blocks below the active tip a side block may be held at
+
+/**
+ * The fields of a known block needed for fork choice and retargeting.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+struct TreeNode {
+    previous_hash: [u8; 32],
+    work: u128,                     // Cumulative work up to and including the block
+    time: BlockTime
+}
+
+/**
+ * Tree of every known block, used for fork choice.
+ * Blocks on the active chain are held by the store, blocks on competing
+ * branches are held here until they are either connected by a reorg or
+ * found to be invalid. The cumulative work of every block is tracked so
+ * the branch with the most work can be chosen, along with its timestamp and
+ * target bits so the next target on any branch can be calculated.
+ * Side blocks and invalid blocks more than MAX_FORK_DEPTH below the tip
+ * are pruned as the active chain grows.
+ */
+pub struct BlockTree {
+    nodes: HashMap<[u8; 32], TreeNode>, // Every known valid block
+    side: HashMap<[u8; 32], Block>,     // Blocks not on the active chain
+    invalid: HashMap<[u8; 32], u32>     // Blocks that failed to connect, and their descendants, with their heights
+}
+
+impl BlockTree {
+    pub fn new() -> BlockTree {
+        return BlockTree {
+            nodes: HashMap::new(),
+            side: HashMap::new(),
+            invalid: HashMap::new()
+        }
+    }
+
+    // Build the tree from the active chain held in a store.
+    pub fn from_store(store: &dyn BlockStore) -> BlockTree {
+        let mut tree = BlockTree::new();
+        if let Some(tip_height) = store.tip_height() {
+            for height in 0..=tip_height {
+                match store.get_by_height(height) {
+                    None => break,
+                    Some(block) => tree.add_active(&block)
+                }
+            }
+        }
+
+        return tree;
+    }
+
+    // Return the cumulative work up to a known block.
+    pub fn get_work(&self, hash: &[u8; 32]) -> Option<u128> {
+        return self.nodes.get(hash).map(|node| node.work);
+    }
+
+    // Return the timestamps and bits of a known block and up to count - 1 of its ancestors, oldest first.
+    pub fn get_ancestry(&self, hash: &[u8; 32], count: usize) -> Vec<BlockTime> {
+        let mut ancestry: Vec<BlockTime> = vec![];
+        let mut next = self.nodes.get(hash);
+        while let Some(node) = next {
+            if ancestry.len() == count {
+                break;
+            }
+            ancestry.push(node.time);
+            next = self.nodes.get(&node.previous_hash);
+        }
+        ancestry.reverse();
+
+        return ancestry;
+    }
+
+    // Return a block held on a side branch.
+    pub fn get_side(&self, hash: &[u8; 32]) -> Option<&Block> {
+        return self.side.get(hash);
+    }
+
+    // Check if a block, or one of its ancestors, failed to connect.
+    pub fn is_invalid(&self, hash: &[u8; 32]) -> bool {
+        return self.invalid.contains_key(hash);
+    }
+
+    // Record the work of a block appended to the active chain.
+    pub fn add_active(&mut self, block: &Block) -> () {
+        let parent_work = self.get_work(&block.previous_hash).unwrap_or(0);
+        self.add_node(block, parent_work.saturating_add(block_work(block.bits)));
+    }
+
+    // Hold a block on a side branch, returning its cumulative work.
+    // The parent must already be known.
+    pub fn add_side(&mut self, block: Block) -> Option<u128> {
+        let work = self.get_work(&block.previous_hash)?.saturating_add(block_work(block.bits));
+        self.add_node(&block, work);
+        self.side.insert(block.hash, block);
+
+        return Some(work);
+    }
+
+    fn add_node(&mut self, block: &Block, work: u128) -> () {
+        self.nodes.insert(block.hash, TreeNode {
+            previous_hash: block.previous_hash,
+            work: work,
+            time: BlockTime::from_block(block)
+        });
+    }
+
+    // Remove a block from the side branches, used once it joins the active chain.
+    pub fn remove_side(&mut self, hash: &[u8; 32]) -> Option<Block> {
+        return self.side.remove(hash);
+    }
+
+    // Walk back from a side block to the active chain.
+    // Returns the hash of the active block the branch forks from,
+    // and the branch block hashes from the fork point up to the given block.
+    pub fn get_branch(&self, hash: &[u8; 32], is_active: &dyn Fn(&[u8; 32]) -> bool) -> Option<([u8; 32], Vec<[u8; 32]>)> {
+        let mut branch: Vec<[u8; 32]> = vec![];
+        let mut next = *hash;
+        while !is_active(&next) {
+            let block = self.side.get(&next)?;
+            branch.push(next);
+            next = block.previous_hash;
+        }
+        branch.reverse();
+
+        return Some((next, branch));
+    }
+
+    // Drop an invalid block at the given height and every side block descending from it.
+    pub fn mark_invalid(&mut self, hash: &[u8; 32], height: u32) -> () {
+        self.invalid.insert(*hash, height);
+        for (hash, height) in self.remove_branch(hash).into_iter() {
+            self.invalid.insert(hash, height);
+        }
+    }
+
+    // Drop side blocks and invalid blocks below the given height, along with every side block descending from them.
+    // Used as the active chain grows, so branches forking too deep are no longer held.
+    pub fn prune(&mut self, min_height: u32) -> () {
+        let old: Vec<[u8; 32]> = self.side.values().filter(|block| block.get_height() < min_height).map(|block| block.hash).collect();
+        for hash in old.iter() {
+            self.remove_branch(hash);
+        }
+        self.invalid.retain(|_, height| *height >= min_height);
+    }
+
+    // Remove a block and every side block descending from it.
+    // Returns the hashes and heights of the side blocks removed.
+    fn remove_branch(&mut self, hash: &[u8; 32]) -> Vec<([u8; 32], u32)> {
+        let mut removed: Vec<([u8; 32], u32)> = vec![];
+        let mut pending: Vec<[u8; 32]> = vec![*hash];
+        while let Some(hash) = pending.pop() {
+            if let Some(block) = self.side.remove(&hash) {
+                removed.push((hash, block.get_height()));
+            }
+            self.nodes.remove(&hash);
+            pending.extend(self.side.values().filter(|block| block.previous_hash == hash).map(|block| block.hash));
+        }
+
+        return removed;
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::target::bits_from_diff;
+
+    fn test_block(height: u32, previous_hash: [u8; 32], diff: u8, seed: u8) -> Block {
+        let mut block = Block::new();
+        block.previous_hash = previous_hash;
+        block.bits = bits_from_diff(diff);
+        block.height = height.to_be_bytes();
+        block.miner = vec![seed];
+        block.generate_hash();
+        return block;
+    }
+
+    #[test]
+    fn tracks_cumulative_work_of_branches() {
+        let genesis = test_block(0, [0; 32], 4, 0);
+        let active = test_block(1, genesis.hash, 4, 1);
+        let mut store = MemoryStore::new();
+        store.put_block(&genesis).unwrap();
+        store.put_block(&active).unwrap();
+        let mut tree = BlockTree::from_store(&store);
+        assert_eq!(tree.get_work(&active.hash), Some(32));
+
+        // A single harder block outweighs a longer chain of easier blocks.
+        let side = test_block(1, genesis.hash, 6, 2);
+        let side_hash = side.hash;
+        assert_eq!(tree.add_side(side), Some(80));
+        assert!(tree.add_side(test_block(2, [9; 32], 4, 3)).is_none());
+
+        assert_eq!(tree.get_ancestry(&side_hash, 5), vec![BlockTime::from_block(&genesis), BlockTime::from_block(tree.get_side(&side_hash).unwrap())]);
+        assert_eq!(tree.get_ancestry(&active.hash, 1), vec![BlockTime::from_block(&active)]);
+
+        let is_active = |hash: &[u8; 32]| store.contains(hash);
+        assert_eq!(tree.get_branch(&side_hash, &is_active), Some((genesis.hash, vec![side_hash])));
+        assert_eq!(tree.get_branch(&[9; 32], &is_active), None);
+    }
+
+    #[test]
+    fn invalid_blocks_drop_descendants() {
+        let genesis = test_block(0, [0; 32], 1, 0);
+        let mut tree = BlockTree::new();
+        tree.add_active(&genesis);
+
+        let bad = test_block(1, genesis.hash, 1, 1);
+        let child = test_block(2, bad.hash, 1, 2);
+        let other = test_block(1, genesis.hash, 1, 3);
+        let (bad_hash, child_hash, other_hash) = (bad.hash, child.hash, other.hash);
+        tree.add_side(bad);
+        tree.add_side(child);
+        tree.add_side(other);
+
+        tree.mark_invalid(&bad_hash, 1);
+        assert!(tree.is_invalid(&child_hash));
+        assert!(tree.get_side(&child_hash).is_none());
+        assert!(tree.get_work(&bad_hash).is_none());
+        assert!(tree.get_side(&other_hash).is_some());
+    }
+
+    #[test]
+    fn prunes_branches_forking_below_height() {
+        let genesis = test_block(0, [0; 32], 1, 0);
+        let mut tree = BlockTree::new();
+        tree.add_active(&genesis);
+
+        let old = test_block(1, genesis.hash, 1, 1);
+        let old_child = test_block(2, old.hash, 1, 2);
+        let recent = test_block(2, old.hash, 1, 3);
+        let (old_hash, old_child_hash, recent_hash) = (old.hash, old_child.hash, recent.hash);
+        tree.add_side(old);
+        tree.add_side(old_child);
+        tree.mark_invalid(&recent_hash, 2);
+        tree.mark_invalid(&[7; 32], 1);
+
+        // Blocks descending from a pruned block go with it, whatever their height.
+        tree.prune(2);
+        assert!(tree.get_side(&old_hash).is_none());
+        assert!(tree.get_side(&old_child_hash).is_none());
+        assert!(tree.get_work(&old_child_hash).is_none());
+        assert!(!tree.is_invalid(&[7; 32]));
+        assert!(tree.is_invalid(&recent_hash));
+        assert_eq!(tree.get_work(&genesis.hash), Some(2));
+    }
+}
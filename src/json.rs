@@ -0,0 +1,414 @@
+use crate::address::{Address, AddressError, address_of};
+use crate::block_struct::Block;
+use crate::signature_scheme::{Scheme, MAX_SIGNATURE_LEN};
+use crate::tx_struct::{Tx, TxType, DataTx, FinancialTx, TX_VERSION};
+use crate::util::{to_hex, from_hex};
+use byteorder::{ByteOrder, BigEndian};
+use serde::{Serialize, Deserialize};
+use std::convert::TryFrom;
+
+// Reasons a JSON block or tx cannot be parsed.
+#[derive(Debug, Clone, PartialEq)]
+pub enum JsonError {
+    Parse(String),                              // Not valid JSON, or not matching the schema
+    InvalidHex(&'static str),                   // Field is not a hex string
+    InvalidLength(&'static str, usize),         // Field does not decode to the expected number of bytes (expected)
+    TooLong(&'static str, usize),               // Field decodes to more than the maximum number of bytes (maximum)
+    InvalidAddress(&'static str, AddressError), // Address field is not a valid address
+    AddressMismatch(&'static str),              // Address field is not the address of the public key beside it
+    UnsupportedVersion(u8),                     // Tx version is not the current tx binary format
+    TxCountMismatch(u8, usize)                  // tx_count differs from the number of txs (tx_count, txs)
+}
+
+/**
+ * JSON schema of a block.
+ * Hashes, the nonce and the miner public key are lower case hex strings,
+ * integer fields are decoded from their big endian binary form.
+ * Address fields are always written, and checked against their public
+ * key when read, but may be left out of submitted JSON.
+ *
+ *  {
+ *      "hash": hex (32 bytes),
+ *      "previous_hash": hex (32 bytes),
+ *      "height": u32,
+ *      "version": u8,
+ *      "bits": u32 (compact proof-of-work target),
+ *      "timestamp": u64 (seconds since the UNIX epoch),
+ *      "nonce": hex (16 bytes),
+ *      "miner": hex (DER public key),
+ *      "miner_address": Base58Check address of miner,
+ *      "reward_amount": u32,
+ *      "tx_merkle": hex (32 bytes),
+ *      "tx_count": u8, equal to the length of txs,
+ *      "txs": [tx, ...]
+ *  }
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BlockJson {
+    pub hash: String,
+    pub previous_hash: String,
+    pub height: u32,
+    pub version: u8,
+    pub bits: u32,
+    pub timestamp: u64,
+    pub nonce: String,
+    pub miner: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub miner_address: Option<String>,
+    pub reward_amount: u32,
+    pub tx_merkle: String,
+    pub tx_count: u8,
+    pub txs: Vec<TxJson>
+}
+
+/**
+ * JSON schema of a tx, tagged by its "type" field.
+ * Hashes, keys, signatures and data are lower case hex strings.
+ *
+ *  {
+ *      "type": "data" | "financial",
+ *      "version": u8,
+ *      "scheme": "rsa" | "ed25519", signature scheme of the owner key,
+ *      "hash": hex (32 bytes),
+ *      "owner": hex (public key, DER for RSA),
+ *      "owner_address": Base58Check address of owner,
+ *      "data": hex (up to 255 bytes), data txs only,
+ *      "receiver": hex (public key), financial txs only,
+ *      "receiver_address": Base58Check address of receiver, financial txs only,
+ *      "quantity": u32, financial txs only,
+ *      "reward": u32,
+ *      "previous_hash": hex (32 bytes),
+ *      "signature": hex (length set by the scheme, up to 512 bytes)
+ *  }
+ */
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "lowercase", deny_unknown_fields)]
+pub enum TxJson {
+    Data {
+        version: u8,
+        scheme: Scheme,
+        hash: String,
+        owner: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner_address: Option<String>,
+        data: String,
+        reward: u32,
+        previous_hash: String,
+        signature: String
+    },
+    Financial {
+        version: u8,
+        scheme: Scheme,
+        hash: String,
+        owner: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        owner_address: Option<String>,
+        receiver: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        receiver_address: Option<String>,
+        quantity: u32,
+        reward: u32,
+        previous_hash: String,
+        signature: String
+    }
+}
+
+// Decode a hex field of any length.
+fn hex_vec(value: &str, field: &'static str) -> Result<Vec<u8>, JsonError> {
+    return from_hex(value).ok_or(JsonError::InvalidHex(field));
+}
+
+// Decode a hex field of a fixed length.
+fn hex_array<const N: usize>(value: &str, field: &'static str) -> Result<[u8; N], JsonError> {
+    let bytes = hex_vec(value, field)?;
+    return <[u8; N]>::try_from(bytes.as_slice()).map_err(|_| JsonError::InvalidLength(field, N));
+}
+
+// Check a tx version matches the binary format this build decodes, so an accepted tx can always be stored.
+fn tx_version(version: u8) -> Result<u8, JsonError> {
+    if version != TX_VERSION {
+        return Err(JsonError::UnsupportedVersion(version));
+    }
+    return Ok(version);
+}
+
+// Decode a hex signature field, which has a length set by its scheme.
+fn hex_signature(value: &str) -> Result<Vec<u8>, JsonError> {
+    let signature = hex_vec(value, "signature")?;
+    if signature.len() > MAX_SIGNATURE_LEN {
+        return Err(JsonError::TooLong("signature", MAX_SIGNATURE_LEN));
+    }
+    return Ok(signature);
+}
+
+// Decode a hex public key field, checking it against its address field if one is given.
+fn key_with_address(key: &str, address: &Option<String>, field: &'static str, address_field: &'static str) -> Result<Vec<u8>, JsonError> {
+    let public_key = hex_vec(key, field)?;
+    if let Some(address) = address {
+        let address = Address::parse(address).map_err(|why| JsonError::InvalidAddress(address_field, why))?;
+        if !address.is_for(&public_key) {
+            return Err(JsonError::AddressMismatch(address_field));
+        }
+    }
+    return Ok(public_key);
+}
+
+impl BlockJson {
+    pub fn from_block(block: &Block) -> BlockJson {
+        return BlockJson {
+            hash: to_hex(&block.hash),
+            previous_hash: to_hex(&block.previous_hash),
+            height: block.get_height(),
+            version: block.version,
+            bits: block.bits,
+            timestamp: BigEndian::read_u64(&block.timestamp),
+            nonce: to_hex(&block.nonce),
+            miner: to_hex(&block.miner),
+            miner_address: Some(address_of(&block.miner)),
+            reward_amount: BigEndian::read_u32(&block.reward_amount),
+            tx_merkle: to_hex(&block.tx_merkle),
+            tx_count: block.tx_count,
+            txs: block.txs.iter().map(|tx| TxJson::from_tx(tx.as_ref())).collect()
+        }
+    }
+
+    // Convert back to a block, checking each field decodes to its binary form.
+    #[cfg(test)]
+    pub fn to_block(&self) -> Result<Block, JsonError> {
+        if self.tx_count as usize != self.txs.len() {
+            return Err(JsonError::TxCountMismatch(self.tx_count, self.txs.len()));
+        }
+
+        return Ok(Block {
+            nonce: hex_array(&self.nonce, "nonce")?,
+            previous_hash: hex_array(&self.previous_hash, "previous_hash")?,
+            version: self.version,
+            bits: self.bits,
+            height: self.height.to_be_bytes(),
+            timestamp: self.timestamp.to_be_bytes(),
+            miner: key_with_address(&self.miner, &self.miner_address, "miner", "miner_address")?,
+            reward_amount: self.reward_amount.to_be_bytes(),
+            tx_count: self.tx_count,
+            tx_merkle: hex_array(&self.tx_merkle, "tx_merkle")?,
+            txs: self.txs.iter().map(|tx| tx.to_tx()).collect::<Result<Vec<Box<dyn Tx>>, JsonError>>()?,
+            hash: hex_array(&self.hash, "hash")?
+        });
+    }
+}
+
+impl TxJson {
+    pub fn from_tx(tx: &dyn Tx) -> TxJson {
+        if let Some(tx) = tx.as_financial() {
+            return TxJson::from_financial(tx);
+        }
+        return match tx.as_data() {
+            Some(tx) => TxJson::from_data(tx),
+            None => unreachable!("Every tx is either a data or financial tx.")
+        };
+    }
+
+    pub fn from_data(tx: &DataTx) -> TxJson {
+        return TxJson::Data {
+            version: tx.version,
+            scheme: tx.scheme,
+            hash: to_hex(&tx.hash),
+            owner: to_hex(&tx.owner),
+            owner_address: Some(address_of(&tx.owner)),
+            data: to_hex(&tx.data),
+            reward: BigEndian::read_u32(&tx.reward),
+            previous_hash: to_hex(&tx.previous_hash),
+            signature: to_hex(&tx.signature)
+        }
+    }
+
+    pub fn from_financial(tx: &FinancialTx) -> TxJson {
+        return TxJson::Financial {
+            version: tx.version,
+            scheme: tx.scheme,
+            hash: to_hex(&tx.hash),
+            owner: to_hex(&tx.owner),
+            owner_address: Some(address_of(&tx.owner)),
+            receiver: to_hex(&tx.receiver),
+            receiver_address: Some(address_of(&tx.receiver)),
+            quantity: tx.get_quantity(),
+            reward: BigEndian::read_u32(&tx.reward),
+            previous_hash: to_hex(&tx.previous_hash),
+            signature: to_hex(&tx.signature)
+        }
+    }
+
+    // Convert back to a boxed tx of the matching type.
+    pub fn to_tx(&self) -> Result<Box<dyn Tx>, JsonError> {
+        return match self {
+            TxJson::Data { .. } => Ok(Box::new(self.to_data()?)),
+            TxJson::Financial { .. } => Ok(Box::new(self.to_financial()?))
+        };
+    }
+
+    // Convert back to a data tx, failing if the JSON describes another tx type.
+    pub fn to_data(&self) -> Result<DataTx, JsonError> {
+        let (version, scheme, hash, owner, owner_address, data, reward, previous_hash, signature) = match self {
+            TxJson::Data { version, scheme, hash, owner, owner_address, data, reward, previous_hash, signature } =>
+                (version, scheme, hash, owner, owner_address, data, reward, previous_hash, signature),
+            _ => return Err(JsonError::Parse("expected a data tx".to_string()))
+        };
+        let data = hex_vec(data, "data")?;
+        if data.len() > u8::MAX as usize {
+            return Err(JsonError::TooLong("data", u8::MAX as usize));
+        }
+
+        return Ok(DataTx {
+            version: tx_version(*version)?,
+            tx_type: TxType::Data,
+            scheme: *scheme,
+            owner: key_with_address(owner, owner_address, "owner", "owner_address")?,
+            data_len: data.len() as u8,
+            data: data,
+            reward: reward.to_be_bytes(),
+            previous_hash: hex_array(previous_hash, "previous_hash")?,
+            hash: hex_array(hash, "hash")?,
+            signature: hex_signature(signature)?
+        });
+    }
+
+    // Convert back to a financial tx, failing if the JSON describes another tx type.
+    pub fn to_financial(&self) -> Result<FinancialTx, JsonError> {
+        let (version, scheme, hash, owner, owner_address, receiver, receiver_address, quantity, reward, previous_hash, signature) = match self {
+            TxJson::Financial { version, scheme, hash, owner, owner_address, receiver, receiver_address, quantity, reward, previous_hash, signature } =>
+                (version, scheme, hash, owner, owner_address, receiver, receiver_address, quantity, reward, previous_hash, signature),
+            _ => return Err(JsonError::Parse("expected a financial tx".to_string()))
+        };
+
+        return Ok(FinancialTx {
+            version: tx_version(*version)?,
+            tx_type: TxType::Financial,
+            scheme: *scheme,
+            owner: key_with_address(owner, owner_address, "owner", "owner_address")?,
+            receiver: key_with_address(receiver, receiver_address, "receiver", "receiver_address")?,
+            quantity: quantity.to_be_bytes(),
+            reward: reward.to_be_bytes(),
+            previous_hash: hex_array(previous_hash, "previous_hash")?,
+            hash: hex_array(hash, "hash")?,
+            signature: hex_signature(signature)?
+        });
+    }
+}
+
+// Parse a JSON string into a schema type.
+pub fn parse<T: serde::de::DeserializeOwned>(json: &str) -> Result<T, JsonError> {
+    return serde_json::from_str(json).map_err(|why| JsonError::Parse(why.to_string()));
+}
+
+// Format a schema type as a pretty printed JSON string.
+pub fn format<T: Serialize>(value: &T) -> String {
+    return serde_json::to_string_pretty(value).expect("JSON schema types always serialise.");
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_financial() -> FinancialTx {
+        let mut tx = FinancialTx::new();
+        tx.owner = vec![0xA1; 8];
+        tx.receiver = vec![0xB0; 8];
+        tx.quantity = 1_000_000u32.to_be_bytes();
+        tx.reward = 3u32.to_be_bytes();
+        tx.signature = vec![0x5A; 256];
+        tx.generate_hash();
+        return tx;
+    }
+
+    fn test_data() -> DataTx {
+        let mut tx = DataTx::new();
+        tx.data = b"hello".to_vec();
+        tx.data_len = 5;
+        tx.generate_hash();
+        return tx;
+    }
+
+    #[test]
+    fn block_round_trip() {
+        let mut block = Block::new();
+        block.height = 42u32.to_be_bytes();
+        block.timestamp = 1_600_000_000u64.to_be_bytes();
+        block.miner = vec![0xC0; 12];
+        block.txs.push(Box::new(test_financial()));
+        block.txs.push(Box::new(test_data()));
+        block.tx_count = 2;
+        block.generate_merkle();
+        block.generate_hash();
+
+        let json = format(&BlockJson::from_block(&block));
+        let parsed: BlockJson = parse(&json).unwrap();
+        assert_eq!(parsed.height, 42);
+        assert_eq!(parsed.timestamp, 1_600_000_000);
+        assert_eq!(parsed.to_block().unwrap().to_bin(), block.to_bin());
+    }
+
+    #[test]
+    fn tx_schema_fields() {
+        let value = serde_json::to_value(TxJson::from_tx(&test_financial())).unwrap();
+        assert_eq!(value["type"], "financial");
+        assert_eq!(value["scheme"], "rsa");
+        assert_eq!(value["quantity"], 1_000_000);
+        assert_eq!(value["reward"], 3);
+        assert_eq!(value["receiver"], "b0b0b0b0b0b0b0b0");
+        assert_eq!(value["receiver_address"], address_of(&[0xB0; 8]));
+
+        let value = serde_json::to_value(TxJson::from_tx(&test_data())).unwrap();
+        assert_eq!(value["type"], "data");
+        assert_eq!(value["data"], "68656c6c6f");
+        assert!(value.get("quantity").is_none());
+
+        let tx = test_data();
+        let parsed: TxJson = parse(&format(&TxJson::from_data(&tx))).unwrap();
+        assert_eq!(parsed.to_tx().unwrap().to_bin(), tx.to_bin());
+        assert!(parsed.to_financial().is_err());
+    }
+
+    #[test]
+    fn rejects_invalid_json() {
+        let mut json = serde_json::to_value(TxJson::from_financial(&test_financial())).unwrap();
+        json["hash"] = "abcd".into();
+        let tx: TxJson = parse(&json.to_string()).unwrap();
+        assert_eq!(tx.to_tx().err(), Some(JsonError::InvalidLength("hash", 32)));
+
+        json["hash"] = "zz".into();
+        let tx: TxJson = parse(&json.to_string()).unwrap();
+        assert_eq!(tx.to_tx().err(), Some(JsonError::InvalidHex("hash")));
+
+        // Signatures may have any length up to the longest a scheme produces, and the scheme must be known.
+        let mut json = serde_json::to_value(TxJson::from_financial(&test_financial())).unwrap();
+        json["signature"] = "ab".repeat(64).into();
+        assert_eq!(parse::<TxJson>(&json.to_string()).unwrap().to_financial().unwrap().signature, vec![0xAB; 64]);
+        json["signature"] = "ab".repeat(MAX_SIGNATURE_LEN + 1).into();
+        assert_eq!(parse::<TxJson>(&json.to_string()).unwrap().to_tx().err(), Some(JsonError::TooLong("signature", MAX_SIGNATURE_LEN)));
+        json["scheme"] = "dsa".into();
+        assert!(matches!(parse::<TxJson>(&json.to_string()), Err(JsonError::Parse(_))));
+
+        // Addresses may be left out, but must match their public key when given.
+        let mut json = serde_json::to_value(TxJson::from_financial(&test_financial())).unwrap();
+        json.as_object_mut().unwrap().remove("owner_address");
+        assert!(parse::<TxJson>(&json.to_string()).unwrap().to_tx().is_ok());
+        json["receiver_address"] = address_of(&[0xA1; 8]).into();
+        assert_eq!(parse::<TxJson>(&json.to_string()).unwrap().to_tx().err(), Some(JsonError::AddressMismatch("receiver_address")));
+        json["receiver_address"] = "R0".into();
+        assert_eq!(parse::<TxJson>(&json.to_string()).unwrap().to_tx().err(),
+            Some(JsonError::InvalidAddress("receiver_address", AddressError::InvalidCharacter('0', 1))));
+
+        json["version"] = 7.into();
+        assert_eq!(parse::<TxJson>(&json.to_string()).unwrap().to_tx().err(), Some(JsonError::UnsupportedVersion(7)));
+
+        json["colour"] = "red".into();
+        assert!(matches!(parse::<TxJson>(&json.to_string()), Err(JsonError::Parse(_))));
+        assert!(matches!(parse::<TxJson>("{\"type\": \"mint\"}"), Err(JsonError::Parse(_))));
+
+        let mut block = BlockJson::from_block(&Block::new());
+        block.tx_count = 1;
+        assert_eq!(block.to_block().err(), Some(JsonError::TxCountMismatch(1, 0)));
+    }
+}
@@ -32,7 +32,7 @@ pub fn get_public_der(key_data: &Vec<u8>) -> Vec<u8> {
 fn parse_separator(data: &Vec<u8>, pos: usize) -> (usize, u32) {
     assert!(data[pos] == 0x02);
 
-    let multibyte_seq: bool = if data[pos + 1] >= 128 { true } else { false };
+    let multibyte_seq: bool = data[pos + 1] >= 128;
     let mut total_bytes = 0;
 
     let data_length: u32 = if !multibyte_seq {
@@ -42,7 +42,7 @@ fn parse_separator(data: &Vec<u8>, pos: usize) -> (usize, u32) {
         total_bytes = data[pos + 1] - 128;
         for i in 1..(total_bytes + 1) {
             let val: u32 = data[pos + 1 + i as usize] as u32;
-            acc = acc << 8;
+            acc <<= 8;
             acc += val;
         }
         acc
@@ -90,9 +90,9 @@ fn export_public_der(modulus: &Vec<u8>, pub_exp: &Vec<u8>) -> Vec<u8> {
     public_der.extend_from_slice(&header);
     public_der.extend_from_slice(&total_size_seg);
     public_der.extend_from_slice(&modulus_seg);
-    public_der.extend_from_slice(&modulus);
+    public_der.extend_from_slice(modulus);
     public_der.extend_from_slice(&pub_exp_seg);
-    public_der.extend_from_slice(&pub_exp);
+    public_der.extend_from_slice(pub_exp);
 
     return public_der;
 }
\ No newline at end of file
@@ -0,0 +1,286 @@
+use crate::codec::{take_array, take_u8, take_u32, take_prefixed, push_prefixed, DecodeError};
+use crate::wallet_struct::Wallet;
+use crate::signature_scheme::Scheme;
+use ring::aead::{Aad, LessSafeKey, Nonce, UnboundKey, AES_256_GCM};
+use ring::pbkdf2;
+use ring::rand::{SecureRandom, SystemRandom};
+use std::num::NonZeroU32;
+
+pub const MAGIC: [u8; 4] = *b"RCKS";        // Leading bytes marking a keystore file
+pub const KEYSTORE_VERSION: u8 = 2;         // Version of the header layout below
+const KDF_PBKDF2_SHA256: u8 = 1;            // Key derivation function id: PBKDF2-HMAC-SHA256
+const CIPHER_AES_256_GCM: u8 = 1;           // Cipher id: AES-256-GCM
+pub const MIN_ITERATIONS: u32 = 10_000;     // Fewest KDF rounds accepted, fewer make a passphrase cheap to guess
+pub const MAX_ITERATIONS: u32 = 10_000_000; // Most KDF rounds accepted, so a crafted file cannot stall unlocking
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+
+// Reasons a keystore cannot be created or unlocked.
+#[derive(Debug, Clone, PartialEq)]
+pub enum KeystoreError {
+    Io(String),                 // Keystore file could not be read or written
+    NotAKeystore,               // Data does not start with the keystore magic bytes, e.g. an unencrypted key file
+    UnsupportedVersion(u8),     // Header version this build cannot read
+    UnsupportedKdf(u8),         // Key derivation function id this build does not know
+    UnsupportedCipher(u8),      // Cipher id this build does not know
+    UnsupportedScheme(u8),      // Signature scheme id this build does not know
+    Malformed(DecodeError),     // Header or ciphertext cut short or followed by extra bytes
+    InvalidIterations(u32),     // KDF iteration count outside MIN_ITERATIONS to MAX_ITERATIONS
+    EmptyPassphrase,            // Passphrase must not be empty
+    WrongPassphrase,            // Decryption failed, the passphrase is wrong or the file was modified
+    InvalidKey                  // Decrypted private key is not a key of the keystore's scheme
+}
+
+/**
+ * A wallet private key of any signature scheme encrypted under a passphrase.
+ * The key is derived from the passphrase with PBKDF2 and a random salt,
+ * then the private key is sealed with AES-256-GCM. The scheme and public
+ * key are kept in the clear so balances can be shown without unlocking, and
+ * the whole header is authenticated along with the ciphertext.
+ *
+ * Binary layout, integers big-endian:
+ * magic (4) | version (1) | kdf id (1) | iterations (4) | salt (16)
+ * | cipher id (1) | nonce (12) | scheme id (1) | public key length (2)
+ * | public key | ciphertext with 16 byte tag
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct Keystore {
+    pub scheme: Scheme,
+    pub iterations: u32,
+    pub salt: [u8; SALT_LEN],
+    pub nonce: [u8; NONCE_LEN],
+    pub public_key: Vec<u8>,
+    pub ciphertext: Vec<u8>
+}
+
+// Check a KDF iteration count is within the accepted bounds.
+fn check_iterations(iterations: u32) -> Result<NonZeroU32, KeystoreError> {
+    if !(MIN_ITERATIONS..=MAX_ITERATIONS).contains(&iterations) {
+        return Err(KeystoreError::InvalidIterations(iterations));
+    }
+
+    return Ok(NonZeroU32::new(iterations).unwrap());
+}
+
+impl Keystore {
+    // Encrypt a wallet's private key under a passphrase, with a fresh random salt and nonce.
+    pub fn encrypt(wallet: &Wallet, passphrase: &str, iterations: u32) -> Result<Keystore, KeystoreError> {
+        let rng = SystemRandom::new();
+        let mut salt = [0; SALT_LEN];
+        let mut nonce = [0; NONCE_LEN];
+        rng.fill(&mut salt).map_err(|_| KeystoreError::Io("Failed to generate a random salt".to_string()))?;
+        rng.fill(&mut nonce).map_err(|_| KeystoreError::Io("Failed to generate a random nonce".to_string()))?;
+
+        let mut keystore = Keystore {
+            scheme: wallet.scheme,
+            iterations: iterations,
+            salt: salt,
+            nonce: nonce,
+            public_key: wallet.public_key.clone(),
+            ciphertext: wallet.private_key.clone()
+        };
+        let key = keystore.derive_key(passphrase)?;
+        let header = keystore.header();
+        key.seal_in_place_append_tag(Nonce::assume_unique_for_key(nonce), Aad::from(&header), &mut keystore.ciphertext)
+            .map_err(|_| KeystoreError::Io("Failed to encrypt the private key".to_string()))?;
+
+        return Ok(keystore);
+    }
+
+    // Decrypt the private key, checking the header has not been modified.
+    pub fn decrypt(&self, passphrase: &str) -> Result<Wallet, KeystoreError> {
+        let key = self.derive_key(passphrase)?;
+        let mut in_out = self.ciphertext.clone();
+        let private_key = key.open_in_place(Nonce::assume_unique_for_key(self.nonce), Aad::from(&self.header()), &mut in_out)
+            .map_err(|_| KeystoreError::WrongPassphrase)?;
+
+        return Wallet::load(self.scheme, private_key.to_vec()).map_err(|_| KeystoreError::InvalidKey);
+    }
+
+    // Derive the AES key from a passphrase with the keystore's salt and iteration count.
+    fn derive_key(&self, passphrase: &str) -> Result<LessSafeKey, KeystoreError> {
+        if passphrase.is_empty() {
+            return Err(KeystoreError::EmptyPassphrase);
+        }
+        let iterations = check_iterations(self.iterations)?;
+        let mut key = [0; 32];
+        pbkdf2::derive(pbkdf2::PBKDF2_HMAC_SHA256, iterations, &self.salt, passphrase.as_bytes(), &mut key);
+
+        return Ok(LessSafeKey::new(UnboundKey::new(&AES_256_GCM, &key).unwrap()));
+    }
+
+    // Binary of every field before the ciphertext, authenticated as additional data.
+    fn header(&self) -> Vec<u8> {
+        let mut header: Vec<u8> = vec![];
+        header.extend_from_slice(&MAGIC);
+        header.push(KEYSTORE_VERSION);
+        header.push(KDF_PBKDF2_SHA256);
+        header.extend_from_slice(&self.iterations.to_be_bytes());
+        header.extend_from_slice(&self.salt);
+        header.push(CIPHER_AES_256_GCM);
+        header.extend_from_slice(&self.nonce);
+        header.push(self.scheme as u8);
+        push_prefixed(&mut header, &self.public_key);
+        return header;
+    }
+
+    pub fn to_bin(&self) -> Vec<u8> {
+        let mut binary = self.header();
+        binary.extend_from_slice(&self.ciphertext);
+        return binary;
+    }
+
+    pub fn from_bin(bin: &[u8]) -> Result<Keystore, KeystoreError> {
+        if !bin.starts_with(&MAGIC) {
+            return Err(KeystoreError::NotAKeystore);
+        }
+        let mut pos = MAGIC.len();
+        let version = take_u8(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        if version != KEYSTORE_VERSION {
+            return Err(KeystoreError::UnsupportedVersion(version));
+        }
+        let kdf = take_u8(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        if kdf != KDF_PBKDF2_SHA256 {
+            return Err(KeystoreError::UnsupportedKdf(kdf));
+        }
+        let iterations = take_u32(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        check_iterations(iterations)?;
+        let salt = take_array::<SALT_LEN>(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        let cipher = take_u8(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        if cipher != CIPHER_AES_256_GCM {
+            return Err(KeystoreError::UnsupportedCipher(cipher));
+        }
+        let nonce = take_array::<NONCE_LEN>(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        let id = take_u8(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+        let scheme = Scheme::from_u8(id).map_err(|_| KeystoreError::UnsupportedScheme(id))?;
+        let public_key = take_prefixed(bin, &mut pos).map_err(KeystoreError::Malformed)?;
+
+        // The ciphertext runs to the end, and holds at least the tag.
+        if bin.len() - pos < AES_256_GCM.tag_len() {
+            return Err(KeystoreError::Malformed(DecodeError::Truncated(pos, AES_256_GCM.tag_len())));
+        }
+
+        return Ok(Keystore {
+            scheme: scheme,
+            iterations: iterations,
+            salt: salt,
+            nonce: nonce,
+            public_key: public_key,
+            ciphertext: bin[pos..].to_vec()
+        });
+    }
+
+    // Re-encrypt under a new passphrase, with a fresh salt and nonce, after unlocking with the old one.
+    pub fn change_passphrase(&self, old: &str, new: &str, iterations: u32) -> Result<Keystore, KeystoreError> {
+        let wallet = self.decrypt(old)?;
+        return Keystore::encrypt(&wallet, new, iterations);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::wallet_struct::{test_wallet, test_ed25519_wallet};
+
+    const TEST_ITERATIONS: u32 = MIN_ITERATIONS;
+
+    #[test]
+    fn encrypt_and_unlock() {
+        let wallet = test_wallet(1);
+        let keystore = Keystore::encrypt(&wallet, "correct horse", TEST_ITERATIONS).unwrap();
+        assert_eq!(keystore.public_key, wallet.public_key);
+        assert!(!keystore.to_bin().windows(wallet.private_key.len()).any(|window| window == &wallet.private_key[..]));
+
+        let decoded = Keystore::from_bin(&keystore.to_bin()).unwrap();
+        assert_eq!(decoded, keystore);
+        assert_eq!(decoded.decrypt("correct horse").unwrap().private_key, wallet.private_key);
+        assert!(matches!(decoded.decrypt("battery staple"), Err(KeystoreError::WrongPassphrase)));
+        assert!(matches!(decoded.decrypt(""), Err(KeystoreError::EmptyPassphrase)));
+
+        // Each encryption draws a new salt and nonce.
+        let again = Keystore::encrypt(&wallet, "correct horse", TEST_ITERATIONS).unwrap();
+        assert_ne!(again.salt, keystore.salt);
+        assert_ne!(again.ciphertext, keystore.ciphertext);
+    }
+
+    #[test]
+    fn detects_tampering() {
+        let keystore = Keystore::encrypt(&test_wallet(1), "pass", TEST_ITERATIONS).unwrap();
+        let bin = keystore.to_bin();
+
+        // Flipping a bit of the authenticated header or the ciphertext fails to unlock.
+        for index in [10, 50, bin.len() - 1].iter() {
+            let mut tampered = bin.clone();
+            tampered[*index] ^= 1;
+            assert!(matches!(Keystore::from_bin(&tampered).unwrap().decrypt("pass"), Err(KeystoreError::WrongPassphrase)));
+        }
+        let mut other_key = keystore.clone();
+        other_key.public_key = test_wallet(2).public_key;
+        assert!(matches!(other_key.decrypt("pass"), Err(KeystoreError::WrongPassphrase)));
+    }
+
+    #[test]
+    fn rejects_unknown_formats() {
+        let keystore = Keystore::encrypt(&test_wallet(1), "pass", TEST_ITERATIONS).unwrap();
+        let bin = keystore.to_bin();
+        let with = |index: usize, val: u8| {
+            let mut changed = bin.clone();
+            changed[index] = val;
+            return Keystore::from_bin(&changed);
+        };
+
+        assert_eq!(Keystore::from_bin(&test_wallet(1).private_key), Err(KeystoreError::NotAKeystore));
+        assert_eq!(with(4, 3), Err(KeystoreError::UnsupportedVersion(3)));
+        assert_eq!(with(4, 1), Err(KeystoreError::UnsupportedVersion(1)));
+        assert_eq!(with(5, 9), Err(KeystoreError::UnsupportedKdf(9)));
+        assert_eq!(with(26, 0), Err(KeystoreError::UnsupportedCipher(0)));
+        assert_eq!(with(39, 7), Err(KeystoreError::UnsupportedScheme(7)));
+        assert!(matches!(Keystore::from_bin(&bin[..30]), Err(KeystoreError::Malformed(DecodeError::Truncated(_, _)))));
+        let tag_cut = keystore.header().len() + 15;
+        assert_eq!(Keystore::from_bin(&bin[..tag_cut]), Err(KeystoreError::Malformed(DecodeError::Truncated(tag_cut - 15, 16))));
+    }
+
+    #[test]
+    fn bounds_iterations() {
+        let wallet = test_wallet(1);
+        assert_eq!(Keystore::encrypt(&wallet, "pass", MIN_ITERATIONS - 1), Err(KeystoreError::InvalidIterations(MIN_ITERATIONS - 1)));
+        assert_eq!(Keystore::encrypt(&wallet, "pass", MAX_ITERATIONS + 1), Err(KeystoreError::InvalidIterations(MAX_ITERATIONS + 1)));
+
+        // Counts read from a file are checked before any key is derived.
+        let mut bin = Keystore::encrypt(&wallet, "pass", TEST_ITERATIONS).unwrap().to_bin();
+        bin[6..10].copy_from_slice(&u32::MAX.to_be_bytes());
+        assert_eq!(Keystore::from_bin(&bin), Err(KeystoreError::InvalidIterations(u32::MAX)));
+        bin[6..10].copy_from_slice(&1u32.to_be_bytes());
+        assert_eq!(Keystore::from_bin(&bin), Err(KeystoreError::InvalidIterations(1)));
+    }
+
+    #[test]
+    fn changes_passphrase() {
+        let keystore = Keystore::encrypt(&test_wallet(1), "old", TEST_ITERATIONS).unwrap();
+
+        assert_eq!(keystore.change_passphrase("wrong", "new", TEST_ITERATIONS), Err(KeystoreError::WrongPassphrase));
+        let changed = keystore.change_passphrase("old", "new", TEST_ITERATIONS).unwrap();
+        assert!(matches!(changed.decrypt("old"), Err(KeystoreError::WrongPassphrase)));
+        assert_eq!(changed.decrypt("new").unwrap().private_key, test_wallet(1).private_key);
+    }
+
+    #[test]
+    fn stores_ed25519_keys() {
+        let wallet = test_ed25519_wallet(1);
+        let keystore = Keystore::encrypt(&wallet, "pass", TEST_ITERATIONS).unwrap();
+        let decoded = Keystore::from_bin(&keystore.to_bin()).unwrap();
+        assert_eq!(decoded.scheme, Scheme::Ed25519);
+        assert_eq!(decoded.public_key, wallet.public_key);
+
+        let unlocked = decoded.decrypt("pass").unwrap();
+        assert_eq!(unlocked.scheme, Scheme::Ed25519);
+        assert_eq!(unlocked.private_key, wallet.private_key);
+
+        // The scheme id is authenticated, so relabelling the key fails to unlock.
+        let mut relabelled = decoded.clone();
+        relabelled.scheme = Scheme::Rsa;
+        assert_eq!(relabelled.decrypt("pass").err(), Some(KeystoreError::WrongPassphrase));
+    }
+}
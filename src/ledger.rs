@@ -0,0 +1,333 @@
+use crate::address::Address;
+use crate::block_struct::Block;
+#[cfg(test)]
+use crate::env::BLOCK_REWARD;
+use crate::store::BlockStore;
+use byteorder::{ByteOrder, BigEndian};
+use std::collections::HashMap;
+
+// Reasons a block cannot be applied to, or removed from, the ledger.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum LedgerError {
+    InvalidHeight(u32, u32),        // Block is not the next to apply (expected, found)
+    InvalidPreviousHash,            // Block does not build on the last applied block
+    InvalidBlockReward(u32),        // Block reward_amount above the maximum block reward
+    InvalidTxChain(usize),          // Tx at index does not follow the owner's previous tx
+    Overdraft(usize),               // Tx at index spends more than the owner holds
+    NothingToRollback               // No applied blocks left to roll back
+}
+
+// Balances changed by an applied block, used to roll it back.
+struct BlockUndo {
+    hash: [u8; 32],
+    previous_hash: [u8; 32],
+    balances: Vec<(Vec<u8>, u64)>,          // (public key, balance before the block)
+    last_txs: Vec<(Vec<u8>, [u8; 32])>      // (public key, last tx hash before the block)
+}
+
+/**
+ * Account balance ledger.
+ * Derived by applying blocks in height order, crediting each
+ * miner and moving FinancialTx quantities between public keys.
+ * Also tracks the last confirmed tx of each owner, which the
+ * next tx from that owner must reference as its previous_hash.
+ * Every public key seen is indexed by its address, so accounts
+ * can be looked up by address.
+ */
+pub struct Ledger {
+    balances: HashMap<Vec<u8>, u64>,
+    last_txs: HashMap<Vec<u8>, [u8; 32]>,
+    addresses: HashMap<Address, Vec<u8>>,   // Public key of each address seen, kept on rollback as a key stays valid
+    undo: Vec<BlockUndo>,
+    tip_hash: [u8; 32],
+    max_reward: u32                         // Most tokens a block may mint for its miner
+}
+
+impl Ledger {
+    #[cfg(test)]
+    pub fn new() -> Ledger {
+        return Ledger::with_max_reward(BLOCK_REWARD);
+    }
+
+    // Create a ledger accepting blocks minting at most max_reward tokens.
+    pub fn with_max_reward(max_reward: u32) -> Ledger {
+        return Ledger {
+            balances: HashMap::new(),
+            last_txs: HashMap::new(),
+            addresses: HashMap::new(),
+            undo: vec![],
+            tip_hash: [0; 32],
+            max_reward: max_reward
+        }
+    }
+
+    // Build a ledger by replaying every block held in a store.
+    pub fn from_store(store: &dyn BlockStore, max_reward: u32) -> Result<Ledger, LedgerError> {
+        let mut ledger = Ledger::with_max_reward(max_reward);
+        if let Some(tip_height) = store.tip_height() {
+            for height in 0..=tip_height {
+                match store.get_by_height(height) {
+                    Some(block) => ledger.apply_block(&block)?,
+                    None => return Err(LedgerError::InvalidHeight(height, tip_height + 1))
+                }
+            }
+        }
+
+        return Ok(ledger);
+    }
+
+    // Return the balance held by a public key.
+    pub fn get_balance(&self, public_key: &[u8]) -> u64 {
+        return *self.balances.get(public_key).unwrap_or(&0);
+    }
+
+    // Return the hash of the last confirmed tx made by a public key.
+    // Zeroed if the key has not made a tx.
+    pub fn get_last_tx(&self, public_key: &[u8]) -> [u8; 32] {
+        return *self.last_txs.get(public_key).unwrap_or(&[0; 32]);
+    }
+
+    // Return the public key of an address, if the key has appeared in an applied block.
+    pub fn get_public_key(&self, address: &Address) -> Option<&Vec<u8>> {
+        return self.addresses.get(address);
+    }
+
+    // Return the number of blocks applied to the ledger.
+    pub fn get_height(&self) -> u32 {
+        return self.undo.len() as u32;
+    }
+
+    // Return the hash of the last applied block.
+    #[cfg(test)]
+    pub fn get_tip_hash(&self) -> [u8; 32] {
+        return self.tip_hash;
+    }
+
+    // Apply a block on top of the ledger.
+    // Each tx must reference its owner's previous tx, owners pay quantity and
+    // reward, receivers are credited the quantity, then the miner is credited
+    // reward_amount plus all tx rewards.
+    // The block is applied in full or not at all.
+    pub fn apply_block(&mut self, block: &Block) -> Result<(), LedgerError> {
+        if block.get_height() != self.get_height() {
+            return Err(LedgerError::InvalidHeight(self.get_height(), block.get_height()));
+        }
+        if block.previous_hash != self.tip_hash {
+            return Err(LedgerError::InvalidPreviousHash);
+        }
+
+        let block_reward = BigEndian::read_u32(&block.reward_amount);
+        if block_reward > self.max_reward {
+            return Err(LedgerError::InvalidBlockReward(block_reward));
+        }
+
+        // Balances touched by the block, applied once every tx is checked.
+        let mut changed: HashMap<Vec<u8>, u64> = HashMap::new();
+        let mut chained: HashMap<Vec<u8>, [u8; 32]> = HashMap::new();
+        let mut miner_credit: u64 = block_reward as u64;
+
+        for (i, tx) in block.txs.iter().enumerate() {
+            let owner = tx.get_owner();
+            let last_tx = *chained.get(owner).unwrap_or(&self.get_last_tx(owner));
+            if tx.get_previous_hash() != last_tx {
+                return Err(LedgerError::InvalidTxChain(i));
+            }
+            chained.insert(owner.clone(), tx.get_hash());
+
+            let quantity = tx.as_financial().map(|fin| fin.get_quantity()).unwrap_or(0) as u64;
+            let spend = quantity + tx.get_reward() as u64;
+
+            let owner_balance = *changed.get(owner).unwrap_or(&self.get_balance(owner));
+            if owner_balance < spend {
+                return Err(LedgerError::Overdraft(i));
+            }
+            changed.insert(owner.clone(), owner_balance - spend);
+
+            if let Some(fin) = tx.as_financial() {
+                let receiver_balance = *changed.get(&fin.receiver).unwrap_or(&self.get_balance(&fin.receiver));
+                changed.insert(fin.receiver.clone(), receiver_balance + quantity);
+            }
+            miner_credit += tx.get_reward() as u64;
+        }
+
+        let miner_balance = *changed.get(&block.miner).unwrap_or(&self.get_balance(&block.miner));
+        changed.insert(block.miner.clone(), miner_balance + miner_credit);
+
+        // Record previous balances then commit the new ones.
+        let mut undo = BlockUndo {
+            hash: block.hash,
+            previous_hash: block.previous_hash,
+            balances: vec![],
+            last_txs: vec![]
+        };
+        for (public_key, balance) in changed.into_iter() {
+            undo.balances.push((public_key.clone(), self.get_balance(&public_key)));
+            self.balances.insert(public_key, balance);
+        }
+        for (public_key, tx_hash) in chained.into_iter() {
+            undo.last_txs.push((public_key.clone(), self.get_last_tx(&public_key)));
+            self.last_txs.insert(public_key, tx_hash);
+        }
+        for public_key in undo.balances.iter().map(|(public_key, _)| public_key) {
+            self.addresses.entry(Address::from_public_key(public_key)).or_insert_with(|| public_key.clone());
+        }
+        self.undo.push(undo);
+        self.tip_hash = block.hash;
+
+        return Ok(());
+    }
+
+    // Roll back the last applied block, returning its hash.
+    pub fn rollback_block(&mut self) -> Result<[u8; 32], LedgerError> {
+        let undo = match self.undo.pop() {
+            None => return Err(LedgerError::NothingToRollback),
+            Some(undo) => undo
+        };
+
+        for (public_key, balance) in undo.balances.into_iter() {
+            if balance == 0 {
+                self.balances.remove(&public_key);
+            } else {
+                self.balances.insert(public_key, balance);
+            }
+        }
+        for (public_key, tx_hash) in undo.last_txs.into_iter() {
+            if tx_hash == [0; 32] {
+                self.last_txs.remove(&public_key);
+            } else {
+                self.last_txs.insert(public_key, tx_hash);
+            }
+        }
+        self.tip_hash = undo.previous_hash;
+
+        return Ok(undo.hash);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tx_struct::{Tx, DataTx, FinancialTx};
+
+    const ALICE: [u8; 4] = [0xA1; 4];
+    const BOB: [u8; 4] = [0xB0; 4];
+    const CAROL: [u8; 4] = [0xC0; 4];
+
+    fn transfer(owner: &[u8], receiver: &[u8], quantity: u32, reward: u32, previous_hash: [u8; 32]) -> FinancialTx {
+        let mut tx = FinancialTx::new();
+        tx.owner = owner.to_vec();
+        tx.previous_hash = previous_hash;
+        tx.receiver = receiver.to_vec();
+        tx.quantity = quantity.to_be_bytes();
+        tx.reward = reward.to_be_bytes();
+        tx.generate_hash();
+        return tx;
+    }
+
+    fn block_on(ledger: &Ledger, miner: &[u8], reward: u32, txs: Vec<Box<dyn Tx>>) -> Block {
+        let mut block = Block::new();
+        block.previous_hash = ledger.get_tip_hash();
+        block.height = ledger.get_height().to_be_bytes();
+        block.miner = miner.to_vec();
+        block.reward_amount = reward.to_be_bytes();
+        block.tx_count = txs.len() as u8;
+        block.txs = txs;
+        block.generate_merkle();
+        block.generate_hash();
+        return block;
+    }
+
+    #[test]
+    fn credit_miner_and_transfer() {
+        let mut ledger = Ledger::new();
+        let genesis = block_on(&ledger, &ALICE, BLOCK_REWARD, vec![]);
+        ledger.apply_block(&genesis).unwrap();
+
+        let block = block_on(&ledger, &CAROL, 10, vec![Box::new(transfer(&ALICE, &BOB, 30, 5, [0; 32]))]);
+        ledger.apply_block(&block).unwrap();
+
+        assert_eq!(ledger.get_balance(&ALICE), BLOCK_REWARD as u64 - 35);
+        assert_eq!(ledger.get_balance(&BOB), 30);
+        assert_eq!(ledger.get_balance(&CAROL), 15);
+        assert_eq!(ledger.get_height(), 2);
+
+        // Receivers are found by address once they appear on chain.
+        assert_eq!(ledger.get_public_key(&Address::from_public_key(&BOB)), Some(&BOB.to_vec()));
+        assert_eq!(ledger.get_public_key(&Address::from_public_key(&[0xD0; 4])), None);
+    }
+
+    #[test]
+    fn data_tx_pays_reward() {
+        let mut ledger = Ledger::new();
+        ledger.apply_block(&block_on(&ledger, &ALICE, 20, vec![])).unwrap();
+
+        let mut tx = DataTx::new();
+        tx.owner = ALICE.to_vec();
+        tx.reward = 4u32.to_be_bytes();
+        ledger.apply_block(&block_on(&ledger, &BOB, 0, vec![Box::new(tx)])).unwrap();
+
+        assert_eq!(ledger.get_balance(&ALICE), 16);
+        assert_eq!(ledger.get_balance(&BOB), 4);
+    }
+
+    #[test]
+    fn reject_overdraft_atomically() {
+        let mut ledger = Ledger::new();
+        ledger.apply_block(&block_on(&ledger, &ALICE, 20, vec![])).unwrap();
+
+        let first = transfer(&ALICE, &BOB, 15, 0, [0; 32]);
+        let second = transfer(&ALICE, &BOB, 10, 0, first.hash);
+        let block = block_on(&ledger, &CAROL, 10, vec![Box::new(first), Box::new(second)]);
+
+        assert_eq!(ledger.apply_block(&block), Err(LedgerError::Overdraft(1)));
+        assert_eq!(ledger.get_balance(&ALICE), 20);
+        assert_eq!(ledger.get_balance(&BOB), 0);
+        assert_eq!(ledger.get_height(), 1);
+    }
+
+    #[test]
+    fn reject_unchained_or_replayed_tx() {
+        let mut ledger = Ledger::new();
+        ledger.apply_block(&block_on(&ledger, &ALICE, 40, vec![])).unwrap();
+        let tx = transfer(&ALICE, &BOB, 5, 0, [0; 32]);
+        ledger.apply_block(&block_on(&ledger, &CAROL, 0, vec![Box::new(tx.clone())])).unwrap();
+
+        assert_eq!(ledger.get_last_tx(&ALICE), tx.hash);
+        let replay = block_on(&ledger, &CAROL, 0, vec![Box::new(tx)]);
+        assert_eq!(ledger.apply_block(&replay), Err(LedgerError::InvalidTxChain(0)));
+    }
+
+    #[test]
+    fn reject_out_of_order_blocks() {
+        let mut ledger = Ledger::new();
+        let genesis = block_on(&ledger, &ALICE, 1, vec![]);
+        let mut unlinked = block_on(&ledger, &ALICE, 1, vec![]);
+        unlinked.previous_hash = [9; 32];
+
+        assert_eq!(ledger.apply_block(&unlinked), Err(LedgerError::InvalidPreviousHash));
+        assert_eq!(ledger.apply_block(&block_on(&ledger, &ALICE, BLOCK_REWARD + 1, vec![])), Err(LedgerError::InvalidBlockReward(BLOCK_REWARD + 1)));
+        ledger.apply_block(&genesis).unwrap();
+        assert_eq!(ledger.apply_block(&genesis), Err(LedgerError::InvalidHeight(1, 0)));
+    }
+
+    #[test]
+    fn rollback_restores_balances() {
+        let mut ledger = Ledger::new();
+        let genesis = block_on(&ledger, &ALICE, 20, vec![]);
+        ledger.apply_block(&genesis).unwrap();
+        let block = block_on(&ledger, &ALICE, 10, vec![Box::new(transfer(&ALICE, &BOB, 20, 0, [0; 32]))]);
+        ledger.apply_block(&block).unwrap();
+
+        assert_eq!(ledger.rollback_block(), Ok(block.hash));
+        assert_eq!(ledger.get_balance(&ALICE), 20);
+        assert_eq!(ledger.get_balance(&BOB), 0);
+        assert_eq!(ledger.get_last_tx(&ALICE), [0; 32]);
+        assert_eq!(ledger.get_tip_hash(), genesis.hash);
+
+        assert_eq!(ledger.rollback_block(), Ok(genesis.hash));
+        assert_eq!(ledger.rollback_block(), Err(LedgerError::NothingToRollback));
+        assert_eq!(ledger.get_height(), 0);
+    }
+}
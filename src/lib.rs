@@ -0,0 +1,56 @@
+// Lint allowances for this crate's house style: explicit returns,
+// `field: field` initialisers, UPPER_CASE command variants and
+// `impl ToString` on the data structs.
+#![allow(non_camel_case_types)]
+#![allow(
+    clippy::needless_return,
+    clippy::redundant_field_names,
+    clippy::unused_unit,
+    clippy::upper_case_acronyms,
+    clippy::inherent_to_string,
+    clippy::to_string_trait_impl,
+    clippy::new_without_default,
+    clippy::single_match,
+    clippy::match_bool,
+    clippy::ptr_arg,
+    clippy::same_item_push
+)]
+
+mod env;
+mod util;
+mod tx_struct;
+mod block_struct;
+mod wallet_struct;
+mod key_parser;
+mod wallet;
+mod keystore;
+mod wallet_manager;
+mod address;
+mod signature_scheme;
+mod retarget;
+mod target;
+pub mod simulator;
+mod chain;
+mod miner;
+mod log;
+mod store;
+mod codec;
+mod json;
+mod validation;
+mod merkle;
+mod ledger;
+mod mempool;
+pub mod cli;
+pub mod config;
+mod net;
+mod sync;
+mod fork;
+mod rest;
+#[cfg(test)]
+mod test_util;
+
+use lazy_static::lazy_static;
+
+lazy_static! {
+    static ref LOGFILE: String = format!("{}-logfile.txt", util::get_datetime());
+}
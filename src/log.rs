@@ -3,13 +3,40 @@ extern crate chrono;
 use std::time::SystemTime;
 use chrono::DateTime;
 use chrono::offset::Utc;
-use std::fs::{File, write, create_dir, read};
-use std::io::{Write, Error, ErrorKind};
+use std::fs::create_dir_all;
+use std::io::Write;
 use std::fs::OpenOptions;
-use crate::env::{DEBUG, SAVE_LOG};
+use std::path::Path;
+use std::sync::RwLock;
+use crate::config::LogConfig;
 use crate::LOGFILE;
+use lazy_static::lazy_static;
+
+lazy_static! {
+    // Log settings, replaced by the loaded config at startup.
+    static ref SETTINGS: RwLock<LogConfig> = RwLock::new(default_settings());
+}
 
 
+// Return the log settings used before init.
+// Tests never call init, so they print without writing log files.
+fn default_settings() -> LogConfig {
+    return LogConfig {
+        save: !cfg!(test),
+        ..LogConfig::default()
+    };
+}
+
+// Apply the log settings from the node config.
+pub fn init(config: &LogConfig) -> () {
+    *SETTINGS.write().unwrap() = config.clone();
+}
+
+// Check if debug data should be output.
+pub fn is_debug() -> bool {
+    return SETTINGS.read().unwrap().debug;
+}
+
 // Add string to log
 pub fn log(line: String) -> () {
     let text = format!("\n> {}", line);
@@ -32,7 +59,7 @@ pub fn tlog(title: &str, data: &[String]) -> () {
 
 // Add debug data to the log.
 pub fn dlog(module: &str, desc: &str, data: &[String]) -> () {
-    if DEBUG {
+    if is_debug() {
         let mut debug = String::from("");
         let ts: DateTime<Utc> = SystemTime::now().into();
         debug.push_str("\n== DEBUG ==");
@@ -51,23 +78,21 @@ pub fn dlog(module: &str, desc: &str, data: &[String]) -> () {
 // Append data string to log file on disk.
 // Create logs directory if does not exist.
 fn append_to_logfile(mut data: String) -> () {
-    if SAVE_LOG {
-        match create_dir("logs/") {
-            Err(why) => if Error::last_os_error().kind() != ErrorKind::AlreadyExists {
-                    panic!("Failed to create logs directory: {}", why);
-                },
-            Ok(_) => ()
-        };
+    let settings = SETTINGS.read().unwrap();
+    if settings.save {
+        if let Err(why) = create_dir_all(&settings.dir) {
+            panic!("Failed to create logs directory: {}", why);
+        }
 
         {
-            let file_url = format!("logs/{}", LOGFILE.clone());
-            data.push_str("\n");
+            let file_url = Path::new(&settings.dir).join(LOGFILE.clone());
+            data.push('\n');
             let mut file = OpenOptions::new()
                 .append(true)
                 .create(true)
                 .open(file_url)
                 .expect("Cannot open file.");
-            file.write_all(&data.as_bytes()).expect("Failed to write to file");
+            file.write_all(data.as_bytes()).expect("Failed to write to file");
         }
     }
 }
\ No newline at end of file
@@ -0,0 +1,424 @@
+use crate::block_struct::Block;
+#[cfg(test)]
+use crate::env::MEMPOOL_MAX_BYTES;
+use crate::ledger::Ledger;
+use crate::tx_struct::{Tx, TX_VERSION};
+use crate::util::hash;
+use crate::wallet_struct::Wallet;
+use std::collections::HashMap;
+
+// Reasons a tx can be refused by the mempool.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum MempoolError {
+    UnsupportedVersion,     // Tx version is not the current tx binary format
+    InvalidHash,            // Tx hash does not match its contents
+    Duplicate,              // Tx already held in the mempool
+    InvalidSignature,       // Signature not valid for the tx owner
+    Conflict,               // Another tx already spends from the same previous_hash
+    InvalidPreviousHash,    // previous_hash does not follow the owner's last tx
+    InsufficientBalance,    // Owner cannot cover the tx alongside their pending txs
+    MempoolFull             // No room, and no lower reward tx to evict
+}
+
+// Return the tokens a tx takes from its owner.
+fn get_spend(tx: &dyn Tx) -> u64 {
+    let quantity = tx.as_financial().map(|fin| fin.get_quantity()).unwrap_or(0);
+    return quantity as u64 + tx.get_reward() as u64;
+}
+
+/**
+ * Pool of validated txs waiting to be mined.
+ * Pending txs from an owner form a chain through previous_hash,
+ * starting from the owner's last confirmed tx in the ledger.
+ */
+pub struct Mempool {
+    txs: HashMap<[u8; 32], Box<dyn Tx>>,
+    owners: HashMap<Vec<u8>, Vec<[u8; 32]>>,    // Pending tx hashes of each owner, oldest first
+    size: usize,
+    max_size: usize
+}
+
+impl Mempool {
+    #[cfg(test)]
+    pub fn new() -> Mempool {
+        return Mempool::with_capacity(MEMPOOL_MAX_BYTES);
+    }
+
+    // Create a mempool holding at most max_size bytes of tx binaries.
+    pub fn with_capacity(max_size: usize) -> Mempool {
+        return Mempool {
+            txs: HashMap::new(),
+            owners: HashMap::new(),
+            size: 0,
+            max_size: max_size
+        }
+    }
+
+    // Return the number of pending txs.
+    #[cfg(test)]
+    pub fn len(&self) -> usize {
+        return self.txs.len();
+    }
+
+    // Return the total size of pending tx binaries in bytes.
+    pub fn get_size(&self) -> usize {
+        return self.size;
+    }
+
+    // Fetch a pending tx by its hash.
+    pub fn get_tx(&self, hash: &[u8; 32]) -> Option<&dyn Tx> {
+        return self.txs.get(hash).map(|tx| tx.as_ref());
+    }
+
+    // Return the hashes of all pending txs.
+    #[cfg(test)]
+    pub fn get_hashes(&self) -> Vec<[u8; 32]> {
+        return self.txs.keys().cloned().collect();
+    }
+
+    // Validate a tx against the ledger and pending txs, then add it to the pool.
+    // Lower reward txs are evicted if the pool is full.
+    pub fn add_tx(&mut self, tx: Box<dyn Tx>, ledger: &Ledger) -> Result<(), MempoolError> {
+        if tx.get_version() != TX_VERSION {
+            return Err(MempoolError::UnsupportedVersion);
+        }
+        if hash(&tx.to_hashable_bin()) != tx.get_hash() {
+            return Err(MempoolError::InvalidHash);
+        }
+        if self.txs.contains_key(&tx.get_hash()) {
+            return Err(MempoolError::Duplicate);
+        }
+        if !Wallet::verify(tx.get_scheme(), tx.get_owner(), &tx.to_signable_bin(), tx.get_signature()) {
+            return Err(MempoolError::InvalidSignature);
+        }
+
+        let owner = tx.get_owner().clone();
+        let pending: &[[u8; 32]] = self.owners.get(&owner).map(|chain| chain.as_slice()).unwrap_or(&[]);
+        let confirmed = ledger.get_last_tx(&owner);
+        let expected = *pending.last().unwrap_or(&confirmed);
+        if tx.get_previous_hash() != expected {
+            if tx.get_previous_hash() == confirmed || pending.contains(&tx.get_previous_hash()) {
+                return Err(MempoolError::Conflict);
+            }
+            return Err(MempoolError::InvalidPreviousHash);
+        }
+
+        let pending_spend: u64 = pending.iter().map(|hash| get_spend(self.txs[hash].as_ref())).sum();
+        if ledger.get_balance(&owner) < pending_spend + get_spend(tx.as_ref()) {
+            return Err(MempoolError::InsufficientBalance);
+        }
+
+        let tx_size = tx.to_bin().len();
+        let victims = self.find_evictions(&owner, tx.get_reward(), tx_size)?;
+        for victim in victims.iter() {
+            self.remove_tx(victim);
+        }
+
+        self.size += tx_size;
+        self.owners.entry(owner).or_default().push(tx.get_hash());
+        self.txs.insert(tx.get_hash(), tx);
+
+        return Ok(());
+    }
+
+    // Find the txs to evict to make room for a new tx.
+    // Only the newest pending tx of another owner can be evicted,
+    // and only if it offers a lower reward than the new tx.
+    fn find_evictions(&self, owner: &[u8], reward: u32, tx_size: usize) -> Result<Vec<[u8; 32]>, MempoolError> {
+        let mut chains: HashMap<&Vec<u8>, &[[u8; 32]]> = self.owners.iter()
+            .filter(|(key, _)| key.as_slice() != owner)
+            .map(|(key, chain)| (key, chain.as_slice()))
+            .collect();
+        let mut size = self.size;
+        let mut victims: Vec<[u8; 32]> = vec![];
+
+        while size + tx_size > self.max_size {
+            let victim = chains.iter()
+                .filter_map(|(key, chain)| chain.last().map(|hash| (*key, *hash)))
+                .min_by_key(|(_, hash)| self.txs[hash].get_reward());
+
+            let (key, victim_hash) = match victim {
+                Some(victim) if self.txs[&victim.1].get_reward() < reward => victim,
+                _ => return Err(MempoolError::MempoolFull)
+            };
+            let chain = chains[key];
+            chains.insert(key, &chain[0..(chain.len() - 1)]);
+            size -= self.txs[&victim_hash].to_bin().len();
+            victims.push(victim_hash);
+        }
+
+        return Ok(victims);
+    }
+
+    // Remove a pending tx, along with any later txs chained from it.
+    fn remove_tx(&mut self, hash: &[u8; 32]) -> () {
+        let owner = match self.txs.get(hash) {
+            None => return,
+            Some(tx) => tx.get_owner().clone()
+        };
+
+        let chain = self.owners.entry(owner.clone()).or_default();
+        let pos = chain.iter().position(|pending| pending == hash).unwrap_or(0);
+        let removed: Vec<[u8; 32]> = chain.split_off(pos);
+        if chain.is_empty() {
+            self.owners.remove(&owner);
+        }
+
+        for pending in removed.iter() {
+            if let Some(tx) = self.txs.remove(pending) {
+                self.size -= tx.to_bin().len();
+            }
+        }
+    }
+
+    // Select up to max_count txs, of at most max_bytes in total, to mine, highest reward first.
+    // Txs from the same owner are kept in chain order.
+    pub fn select_txs(&self, max_count: usize, max_bytes: usize) -> Vec<Box<dyn Tx>> {
+        let mut heads: HashMap<&Vec<u8>, usize> = self.owners.keys().map(|owner| (owner, 0)).collect();
+        let mut selected: Vec<Box<dyn Tx>> = vec![];
+        let mut bytes: usize = 0;
+
+        while selected.len() < max_count {
+            let next = heads.iter()
+                .filter_map(|(owner, pos)| self.owners[*owner].get(*pos).map(|hash| (*owner, hash)))
+                .max_by_key(|(_, hash)| (self.txs[*hash].get_reward(), **hash));
+
+            match next {
+                None => break,
+                Some((owner, hash)) => {
+                    // Later txs of the owner build on this one, so none of them can be selected.
+                    let len = self.txs[hash].to_bin().len();
+                    if bytes + len > max_bytes {
+                        heads.remove(owner);
+                        continue;
+                    }
+                    bytes += len;
+                    selected.push(self.txs[hash].clone());
+                    *heads.get_mut(owner).unwrap() += 1;
+                }
+            }
+        }
+
+        return selected;
+    }
+
+    // Remove txs included in a block, then drop any pending txs
+    // no longer valid against the updated ledger.
+    pub fn remove_block_txs(&mut self, block: &Block, ledger: &Ledger) -> () {
+        for tx in block.txs.iter() {
+            if let Some(tx) = self.txs.remove(&tx.get_hash()) {
+                self.size -= tx.to_bin().len();
+            }
+        }
+        self.revalidate(ledger);
+    }
+
+    // Re-add every pending tx against the ledger, dropping those now invalid.
+    pub fn revalidate(&mut self, ledger: &Ledger) -> () {
+        let mut pending: Vec<Box<dyn Tx>> = vec![];
+        for (_, chain) in self.owners.drain() {
+            for hash in chain.iter() {
+                if let Some(tx) = self.txs.remove(hash) {
+                    pending.push(tx);
+                }
+            }
+        }
+        self.txs.clear();
+        self.size = 0;
+
+        for tx in pending.into_iter() {
+            let _ = self.add_tx(tx, ledger);
+        }
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::signature_scheme::Scheme;
+    use crate::env::BLOCK_REWARD;
+    use crate::test_util::signed_transfer;
+    use crate::wallet_struct::{test_wallet, test_ed25519_wallet};
+
+    fn fund(ledger: &mut Ledger, wallets: &[&Wallet]) -> () {
+        for wallet in wallets.iter() {
+            let mut block = Block::new();
+            block.previous_hash = ledger.get_tip_hash();
+            block.height = ledger.get_height().to_be_bytes();
+            block.miner = wallet.public_key.clone();
+            block.reward_amount = BLOCK_REWARD.to_be_bytes();
+            block.generate_hash();
+            ledger.apply_block(&block).unwrap();
+        }
+    }
+
+    #[test]
+    fn accept_chained_txs() {
+        let wallet = test_wallet(1);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&wallet]);
+        let mut pool = Mempool::new();
+
+        let first = signed_transfer(&wallet, 10, 1, [0; 32]);
+        let second = signed_transfer(&wallet, 10, 1, first.hash);
+        pool.add_tx(Box::new(first.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(second), &ledger).unwrap();
+
+        assert_eq!(pool.len(), 2);
+        assert_eq!(pool.add_tx(Box::new(first), &ledger), Err(MempoolError::Duplicate));
+    }
+
+    #[test]
+    fn reject_invalid_txs() {
+        let wallet = test_wallet(1);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&wallet]);
+        let mut pool = Mempool::new();
+
+        let mut bad_hash = signed_transfer(&wallet, 1, 0, [0; 32]);
+        bad_hash.quantity = [0, 0, 0, 2];
+        let mut bad_sig = signed_transfer(&wallet, 1, 0, [0; 32]);
+        bad_sig.signature[10] ^= 0x01;
+        let unchained = signed_transfer(&wallet, 1, 0, [7; 32]);
+        let overdraft = signed_transfer(&wallet, BLOCK_REWARD, 1, [0; 32]);
+        let mut future = signed_transfer(&wallet, 1, 0, [0; 32]);
+        future.version = 7;
+        future.generate_hash();
+        future.generate_signature(&wallet);
+
+        assert_eq!(pool.add_tx(Box::new(bad_hash), &ledger), Err(MempoolError::InvalidHash));
+        assert_eq!(pool.add_tx(Box::new(bad_sig), &ledger), Err(MempoolError::InvalidSignature));
+        assert_eq!(pool.add_tx(Box::new(unchained), &ledger), Err(MempoolError::InvalidPreviousHash));
+        assert_eq!(pool.add_tx(Box::new(overdraft), &ledger), Err(MempoolError::InsufficientBalance));
+        assert_eq!(pool.add_tx(Box::new(future), &ledger), Err(MempoolError::UnsupportedVersion));
+        assert_eq!(pool.len(), 0);
+    }
+
+    #[test]
+    fn accept_txs_of_each_scheme() {
+        let rsa = test_wallet(1);
+        let ed25519 = test_ed25519_wallet(1);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&rsa, &ed25519]);
+        let mut pool = Mempool::new();
+
+        let mut relabelled = signed_transfer(&ed25519, 1, 0, [0; 32]);
+        relabelled.scheme = Scheme::Rsa;
+        relabelled.generate_hash();
+        assert_eq!(pool.add_tx(Box::new(relabelled), &ledger), Err(MempoolError::InvalidSignature));
+
+        pool.add_tx(Box::new(signed_transfer(&rsa, 1, 0, [0; 32])), &ledger).unwrap();
+        pool.add_tx(Box::new(signed_transfer(&ed25519, 1, 0, [0; 32])), &ledger).unwrap();
+        assert_eq!(pool.len(), 2);
+    }
+
+    #[test]
+    fn reject_conflicting_and_pending_overdraft() {
+        let wallet = test_wallet(1);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&wallet]);
+        let mut pool = Mempool::new();
+
+        let first = signed_transfer(&wallet, 30, 0, [0; 32]);
+        let double_spend = signed_transfer(&wallet, 29, 0, [0; 32]);
+        let overdraft = signed_transfer(&wallet, 30, 0, first.hash);
+        pool.add_tx(Box::new(first), &ledger).unwrap();
+
+        assert_eq!(pool.add_tx(Box::new(double_spend), &ledger), Err(MempoolError::Conflict));
+        assert_eq!(pool.add_tx(Box::new(overdraft), &ledger), Err(MempoolError::InsufficientBalance));
+    }
+
+    #[test]
+    fn select_by_reward_in_chain_order() {
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&alice, &bob]);
+        let mut pool = Mempool::new();
+
+        let alice_first = signed_transfer(&alice, 1, 1, [0; 32]);
+        let alice_second = signed_transfer(&alice, 1, 9, alice_first.hash);
+        let bob_first = signed_transfer(&bob, 1, 5, [0; 32]);
+        pool.add_tx(Box::new(alice_first.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(alice_second.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(bob_first.clone()), &ledger).unwrap();
+
+        let selected: Vec<[u8; 32]> = pool.select_txs(10, usize::MAX).iter().map(|tx| tx.get_hash()).collect();
+        assert_eq!(selected, vec![bob_first.hash, alice_first.hash, alice_second.hash]);
+        assert_eq!(pool.select_txs(1, usize::MAX).len(), 1);
+    }
+
+    #[test]
+    fn select_within_byte_limit() {
+        let alice = test_wallet(1);
+        let bob = test_ed25519_wallet(2);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&alice, &bob]);
+        let mut pool = Mempool::new();
+
+        let alice_first = signed_transfer(&alice, 1, 9, [0; 32]);
+        let alice_second = signed_transfer(&alice, 1, 8, alice_first.hash);
+        let bob_first = signed_transfer(&bob, 1, 1, [0; 32]);
+        pool.add_tx(Box::new(alice_first.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(alice_second.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(bob_first.clone()), &ledger).unwrap();
+
+        // Bob's smaller Ed25519 tx still fits once alice's second tx no longer does.
+        let alice_len = alice_first.to_bin().len();
+        let bob_len = bob_first.to_bin().len();
+        let selected: Vec<[u8; 32]> = pool.select_txs(10, alice_len * 2 + bob_len).iter().map(|tx| tx.get_hash()).collect();
+        assert_eq!(selected, vec![alice_first.hash, alice_second.hash, bob_first.hash]);
+        let selected: Vec<[u8; 32]> = pool.select_txs(10, alice_len + bob_len).iter().map(|tx| tx.get_hash()).collect();
+        assert_eq!(selected, vec![alice_first.hash, bob_first.hash]);
+        assert!(pool.select_txs(10, bob_len - 1).is_empty());
+    }
+
+    #[test]
+    fn evict_lowest_reward_when_full() {
+        let alice = test_wallet(1);
+        let bob = test_wallet(2);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&alice, &bob]);
+
+        let cheap = signed_transfer(&alice, 1, 1, [0; 32]);
+        let rich = signed_transfer(&bob, 1, 5, [0; 32]);
+        let mut pool = Mempool::with_capacity(cheap.to_bin().len());
+        pool.add_tx(Box::new(cheap.clone()), &ledger).unwrap();
+
+        let cheaper = signed_transfer(&bob, 1, 0, [0; 32]);
+        assert_eq!(pool.add_tx(Box::new(cheaper), &ledger), Err(MempoolError::MempoolFull));
+
+        pool.add_tx(Box::new(rich.clone()), &ledger).unwrap();
+        assert!(pool.get_tx(&cheap.hash).is_none());
+        assert!(pool.get_tx(&rich.hash).is_some());
+        assert_eq!(pool.get_size(), rich.to_bin().len());
+    }
+
+    #[test]
+    fn remove_txs_once_mined() {
+        let wallet = test_wallet(1);
+        let mut ledger = Ledger::new();
+        fund(&mut ledger, &[&wallet]);
+        let mut pool = Mempool::new();
+
+        let first = signed_transfer(&wallet, 5, 1, [0; 32]);
+        let second = signed_transfer(&wallet, 5, 1, first.hash);
+        pool.add_tx(Box::new(first.clone()), &ledger).unwrap();
+        pool.add_tx(Box::new(second.clone()), &ledger).unwrap();
+
+        let mut block = Block::new();
+        block.previous_hash = ledger.get_tip_hash();
+        block.height = ledger.get_height().to_be_bytes();
+        block.miner = vec![1; 4];
+        block.txs = vec![Box::new(first.clone())];
+        block.tx_count = 1;
+        block.generate_hash();
+        ledger.apply_block(&block).unwrap();
+        pool.remove_block_txs(&block, &ledger);
+
+        assert_eq!(pool.get_hashes(), vec![second.hash]);
+        assert_eq!(pool.get_size(), second.to_bin().len());
+    }
+}
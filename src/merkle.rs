@@ -0,0 +1,208 @@
+use crate::util::hash;
+
+// Prefixes hashed ahead of leaf and inner nodes, so an inner node can never pass as a leaf.
+const LEAF_PREFIX: u8 = 0x00;
+const NODE_PREFIX: u8 = 0x01;
+
+/**
+ * Merkle inclusion proof.
+ * Lists the sibling hash at each level from the leaf up to the root,
+ * along with a flag stating if the sibling sits on the left.
+ * The flags must match the leaf index, and a tree of n leaves has
+ * exactly one sibling per level above its leaves.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MerkleProof {
+    pub index: usize,
+    pub siblings: Vec<([u8; 32], bool)>
+}
+
+// Hash a leaf value into its node at the bottom of the tree.
+fn hash_leaf(leaf: &[u8; 32]) -> [u8; 32] {
+    let mut bin: Vec<u8> = vec![LEAF_PREFIX];
+    bin.extend_from_slice(leaf);
+    return hash(&bin);
+}
+
+// Hash two child nodes into their parent node.
+fn hash_pair(left: &[u8; 32], right: &[u8; 32]) -> [u8; 32] {
+    let mut bin: Vec<u8> = vec![NODE_PREFIX];
+    bin.extend_from_slice(left);
+    bin.extend_from_slice(right);
+    return hash(&bin);
+}
+
+// Build the next level of the tree.
+// An odd node at the end of a level is paired with itself.
+fn next_level(level: &[[u8; 32]]) -> Vec<[u8; 32]> {
+    return level.chunks(2).map(|pair| {
+        let right = if pair.len() == 2 { &pair[1] } else { &pair[0] };
+        hash_pair(&pair[0], right)
+    }).collect();
+}
+
+// Return the number of levels above the leaves in a tree of the given number of leaves.
+fn tree_depth(leaf_count: usize) -> usize {
+    let mut depth = 0;
+    let mut level_len = leaf_count;
+    while level_len > 1 {
+        level_len = level_len.div_ceil(2);
+        depth += 1;
+    }
+
+    return depth;
+}
+
+// Calculate the merkle root of a list of leaf hashes.
+// An empty list has a zeroed root.
+pub fn merkle_root(leaves: &[[u8; 32]]) -> [u8; 32] {
+    if leaves.is_empty() {
+        return [0; 32];
+    }
+
+    let mut level: Vec<[u8; 32]> = leaves.iter().map(hash_leaf).collect();
+    while level.len() > 1 {
+        level = next_level(&level);
+    }
+
+    return level[0];
+}
+
+// Generate a proof that the leaf at index is included in the tree.
+pub fn generate_proof(leaves: &[[u8; 32]], index: usize) -> Option<MerkleProof> {
+    if index >= leaves.len() {
+        return None;
+    }
+
+    let mut siblings: Vec<([u8; 32], bool)> = vec![];
+    let mut level: Vec<[u8; 32]> = leaves.iter().map(hash_leaf).collect();
+    let mut pos = index;
+    while level.len() > 1 {
+        let sibling = if pos.is_multiple_of(2) {
+            (*level.get(pos + 1).unwrap_or(&level[pos]), false)
+        } else {
+            (level[pos - 1], true)
+        };
+        siblings.push(sibling);
+        level = next_level(&level);
+        pos /= 2;
+    }
+
+    return Some(MerkleProof {
+        index: index,
+        siblings: siblings
+    });
+}
+
+// Verify a leaf hash is included at the proof index of a tree of leaf_count leaves under the given root.
+// The side of each sibling must follow from the index, and the last node of an odd level
+// must be paired with itself, so a proof only verifies for a single position.
+// The node only serves proofs; this is the check a light client runs against a block header.
+#[allow(dead_code)]
+pub fn verify_proof(leaf: &[u8; 32], proof: &MerkleProof, root: &[u8; 32], leaf_count: usize) -> bool {
+    if proof.index >= leaf_count || proof.siblings.len() != tree_depth(leaf_count) {
+        return false;
+    }
+
+    let mut node = hash_leaf(leaf);
+    let mut pos = proof.index;
+    let mut level_len = leaf_count;
+    for (sibling, is_left) in proof.siblings.iter() {
+        let right_child = !pos.is_multiple_of(2);
+        if *is_left != right_child {
+            return false;
+        }
+        if pos + 1 == level_len && !right_child && *sibling != node {
+            return false;
+        }
+
+        node = if *is_left { hash_pair(sibling, &node) } else { hash_pair(&node, sibling) };
+        pos /= 2;
+        level_len = level_len.div_ceil(2);
+    }
+
+    return node == *root;
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn leaves(count: u8) -> Vec<[u8; 32]> {
+        return (0..count).map(|i| hash(&vec![i])).collect();
+    }
+
+    #[test]
+    fn root_of_empty_and_single() {
+        let single = leaves(1);
+
+        assert_eq!(merkle_root(&[]), [0; 32]);
+        assert_eq!(merkle_root(&single), hash_leaf(&single[0]));
+    }
+
+    #[test]
+    fn root_of_odd_leaves() {
+        let odd: Vec<[u8; 32]> = leaves(3).iter().map(hash_leaf).collect();
+        let expected = hash_pair(&hash_pair(&odd[0], &odd[1]), &hash_pair(&odd[2], &odd[2]));
+
+        assert_eq!(merkle_root(&leaves(3)), expected);
+    }
+
+    #[test]
+    fn proofs_verify_for_every_leaf() {
+        for count in 1..10 {
+            let tree = leaves(count);
+            let root = merkle_root(&tree);
+            for (i, leaf) in tree.iter().enumerate() {
+                let proof = generate_proof(&tree, i).unwrap();
+                assert!(verify_proof(leaf, &proof, &root, tree.len()));
+            }
+        }
+    }
+
+    #[test]
+    fn proof_rejects_wrong_leaf_or_root() {
+        let tree = leaves(5);
+        let root = merkle_root(&tree);
+        let proof = generate_proof(&tree, 2).unwrap();
+
+        assert!(!verify_proof(&tree[3], &proof, &root, 5));
+        assert!(!verify_proof(&tree[2], &proof, &[0; 32], 5));
+        assert!(generate_proof(&tree, 5).is_none());
+    }
+
+    #[test]
+    fn proof_binds_position_and_depth() {
+        let tree = leaves(5);
+        let root = merkle_root(&tree);
+        let proof = generate_proof(&tree, 2).unwrap();
+        assert!(verify_proof(&tree[2], &proof, &root, 5));
+
+        // The index must match the sibling sides, and the length the leaf count.
+        assert!(!verify_proof(&tree[2], &MerkleProof { index: 3, ..proof.clone() }, &root, 5));
+        assert!(!verify_proof(&tree[2], &proof, &root, 4));
+        assert!(!verify_proof(&tree[2], &proof, &root, 9));
+        assert!(!verify_proof(&tree[2], &MerkleProof { index: 5, ..proof.clone() }, &root, 5));
+
+        // The duplicated last node of an odd level cannot stand in for a real sibling.
+        let last = generate_proof(&tree, 4).unwrap();
+        assert!(verify_proof(&tree[4], &last, &root, 5));
+        let mut forged = last.clone();
+        forged.siblings[0].0 = hash_leaf(&tree[3]);
+        assert!(!verify_proof(&tree[4], &forged, &root, 5));
+    }
+
+    #[test]
+    fn inner_node_is_not_a_leaf() {
+        let tree = leaves(4);
+        let root = merkle_root(&tree);
+        let proof = generate_proof(&tree, 0).unwrap();
+
+        // An inner node with a shortened proof does not verify as a leaf of a smaller tree.
+        let inner = hash_pair(&hash_leaf(&tree[0]), &hash_leaf(&tree[1]));
+        let short = MerkleProof { index: 0, siblings: proof.siblings[1..].to_vec() };
+        assert!(!verify_proof(&inner, &short, &root, 2));
+    }
+}
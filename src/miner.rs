@@ -1,157 +1,425 @@
 use std::thread;
 use std::sync::mpsc;
 use std::sync::mpsc::{RecvTimeoutError};
-use std::time::Duration;
-use std::u128;
+use std::time::{Duration, Instant};
 use byteorder::ByteOrder;
-use crate::env::{MINER_PROCESS, DEBUG};
 use crate::util::{hash};
-use crate::log::{log, tlog, dlog};
+use crate::target::{U256, target_from_bits, block_work};
+use crate::log::{log, dlog};
 
-// Commands accepted by mining workers
+const REPORT_INTERVAL: Duration = Duration::from_secs(1);   // Delay between worker hash count reports
+const REPORT_CHECK: u64 = 4096;                             // Hashes tried between checks if a report is due
+const STATS_INTERVAL: Duration = Duration::from_secs(60);   // Delay between hashrate log lines while mining
+
+// Commands accepted by the mining server
 #[derive(Debug)]
 pub enum MinerCommand {
     KILL,
-    UPDATE_DIFF(u8),
-    UPDATE_DATA(Vec<u8>),
-    START
+    NEW_JOB(MiningJob),                 // Replace the current job, discarding solutions to older jobs
+    STOP,                               // Drop the current job, leaving workers idle
+    PAUSE,                              // Suspend work on the current job
+    RESUME,                             // Continue work on the current job from where it was paused
+    SET_THREADS(u8),                    // Resize the worker pool, re-partitioning the nonce space of the current job
+    STATS(mpsc::Sender<MinerStats>)     // Reply with the current mining statistics
 }
 
 // Commands accepted by mining workers
 #[derive(Debug)]
 enum WorkerCommand {
-    KILL
+    KILL,
+    JOB(u64, Vec<u8>, [u8; 32], u128), // Job id, data, target and the nonce to start at
+    IDLE,
+    PAUSE,
+    RESUME
+}
+
+// Messages sent from mining workers to the server
+#[derive(Debug)]
+enum WorkerReport {
+    SOLVED(u64, u128, [u8; 32]),        // Job id, and the nonce and hash meeting its target
+    HASHES(usize, u64, Duration)        // Worker index, hashes tried and the time taken to try them
+}
+
+// Events handled by the mining server, received on a single channel
+#[derive(Debug)]
+enum ServerEvent {
+    COMMAND(MinerCommand),              // Command from the parent process
+    REPORT(WorkerReport)                // Message from a mining worker
+}
+
+/**
+ * A unit of work for the mining server: the data to hash and the
+ * compact bits of the target to meet. Solutions are returned to the chain tagged
+ * with the job id, so solutions to replaced jobs can be told apart.
+ */
+#[derive(Debug, Clone)]
+pub struct MiningJob {
+    pub id: u64,
+    pub data: Vec<u8>,
+    pub bits: u32
+}
+
+/**
+ * Mining statistics, as returned by the STATS command.
+ * Hashrates are in hashes per second, taken from the latest report of each worker.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct MinerStats {
+    pub paused: bool,
+    pub threads: usize,                         // Size of the worker pool
+    pub worker_hashrates: Vec<f64>,
+    pub hashrate: f64,
+    pub total_hashes: u64,                      // Hashes tried since the server started
+    pub block_work: u128,                       // Expected hashes needed to meet the current target
+    pub expected_block_time: Option<Duration>,  // Expected time to solve a block at the current target and hashrate
+    pub blocks_solved: u32,
+    pub last_block_time: Option<Duration>,      // Time taken to solve the last block
+    pub average_block_time: Option<Duration>
+}
+
+/**
+ * Hash counters aggregated from worker reports,
+ * along with the time taken to solve each block.
+ */
+struct HashCounter {
+    rates: Vec<f64>,
+    total: u64,
+    job_started: Option<Instant>,
+    solved: u32,
+    solve_time: Duration,
+    last_solve: Option<Duration>
 }
 
 // Mining server state
 struct State {
-    diff: u8,
-    data: Vec<u8>,
-    chain_tx: mpsc::Sender<([u8; 16], [u8; 32])>,
+    job: Option<MiningJob>,
+    paused: bool,
+    chain_tx: mpsc::Sender<(u64, [u8; 16], [u8; 32])>,
     workers: Vec<mpsc::Sender<WorkerCommand>>,
-    diff_mask: Vec<u8>,
-    master_tx: mpsc::Sender<(u128, [u8; 32])>,
-    master_rx: mpsc::Receiver<(u128, [u8; 32])>
+    counter: HashCounter,
+    last_stats: Instant,
+    master_tx: mpsc::Sender<ServerEvent>,
+    master_rx: mpsc::Receiver<ServerEvent>
+}
+
+impl HashCounter {
+    fn new() -> HashCounter {
+        return HashCounter {
+            rates: vec![],
+            total: 0,
+            job_started: None,
+            solved: 0,
+            solve_time: Duration::from_secs(0),
+            last_solve: None
+        }
+    }
+
+    // Start timing a new block with the given number of workers.
+    fn start_job(&mut self, workers: usize, now: Instant) -> () {
+        self.rates = vec![0.0; workers];
+        self.job_started = Some(now);
+    }
+
+    // Resize the per-worker rates, keeping the timing of the current block.
+    fn set_workers(&mut self, workers: usize) -> () {
+        if self.job_started.is_some() {
+            self.rates.resize(workers, 0.0);
+        }
+    }
+
+    // Stop timing the current block, as its workers have been stopped.
+    fn stop_job(&mut self) -> () {
+        self.rates.clear();
+        self.job_started = None;
+    }
+
+    // Add a hash count reported by a worker.
+    fn add_report(&mut self, worker: usize, hashes: u64, elapsed: Duration) -> () {
+        self.total += hashes;
+        if let Some(rate) = self.rates.get_mut(worker) {
+            *rate = hashes as f64 / elapsed.as_secs_f64().max(f64::EPSILON);
+        }
+    }
+
+    // Record a solved block, returning the time taken to solve it.
+    fn add_solution(&mut self, now: Instant) -> Option<Duration> {
+        let elapsed = now.duration_since(self.job_started?);
+        self.solved += 1;
+        self.solve_time += elapsed;
+        self.last_solve = Some(elapsed);
+        self.stop_job();
+
+        return Some(elapsed);
+    }
+
+    fn get_hashrate(&self) -> f64 {
+        return self.rates.iter().sum();
+    }
+
+    fn get_stats(&self, work: u128) -> MinerStats {
+        let hashrate = self.get_hashrate();
+        return MinerStats {
+            paused: false,
+            threads: self.rates.len(),
+            worker_hashrates: self.rates.clone(),
+            hashrate: hashrate,
+            total_hashes: self.total,
+            block_work: work,
+            expected_block_time: expected_block_time(work, hashrate),
+            blocks_solved: self.solved,
+            last_block_time: self.last_solve,
+            average_block_time: self.solve_time.checked_div(self.solved)
+        }
+    }
+}
+
+// Return the expected time to find a hash meeting a target, given the work it represents.
+// Each hash meets the target with probability 1 / work.
+pub fn expected_block_time(work: u128, hashrate: f64) -> Option<Duration> {
+    if hashrate <= 0.0 {
+        return None;
+    }
+    return Duration::try_from_secs_f64(work as f64 / hashrate).ok();
+}
+
+// Format a hashrate with a unit prefix, e.g. 1.50 MH/s.
+pub fn format_hashrate(hashrate: f64) -> String {
+    let units = ["H/s", "kH/s", "MH/s", "GH/s", "TH/s"];
+    let mut rate = hashrate;
+    let mut unit = 0;
+    while rate >= 1000.0 && unit < units.len() - 1 {
+        rate /= 1000.0;
+        unit += 1;
+    }
+
+    return format!("{:.2} {}", rate, units[unit]);
 }
 
 
 // Start mining server.
-// This process manages the set of mining workers trying to solve the hashing puzzle.
-pub fn start_mining_server(chain_tx: mpsc::Sender<([u8; 16], [u8; 32])>, miner_rx: mpsc::Receiver<MinerCommand>) {
+// This process manages a pool of long-lived mining workers trying to solve the hashing puzzle,
+// spreading the nonce space of each job across the worker threads. The pool starts with the given number
+// of threads and is resized with SET_THREADS.
+// Commands are forwarded onto the channel workers report on, so the server blocks on a single channel.
+// Solutions are sent to the chain along with the id of the job they solve.
+pub fn start_mining_server(chain_tx: mpsc::Sender<(u64, [u8; 16], [u8; 32])>, miner_rx: mpsc::Receiver<MinerCommand>, threads: u8) {
+    let (tx, rx) = mpsc::channel();
+    forward_commands(miner_rx, tx.clone());
+
     thread::spawn(move || {
 
         // Process state.
-        let (tx, rx) = mpsc::channel();
         let mut state = State {
-            diff: 0,
-            data: vec![],
+            job: None,
+            paused: false,
             chain_tx: chain_tx,
             workers: vec![],
-            diff_mask: vec![],
+            counter: HashCounter::new(),
+            last_stats: Instant::now(),
             master_tx: tx,
             master_rx: rx
         };
 
-        // Handle message from parent process.
-        // Processes values from MinerCommand enum.
+        log(format!("Mining server spawning {} worker thread(s).", threads));
+        resize_workers(&mut state, threads as usize);
+
+        // Handle commands from the parent process, and hash count reports and
+        // valid nonce messages from worker threads.
         loop {
-            let recv = miner_rx.recv_timeout(Duration::new(0, 0));
-            match recv {
-                Ok(cmnd) => {
+            let event = match state.master_rx.recv() {
+                Err(_) => break,
+                Ok(event) => event
+            };
+            match event {
+                ServerEvent::COMMAND(cmnd) => {
                     match cmnd {
-                        MinerCommand::START => {
-                            log(format!("Mining server spawning {} worker thread(s).", MINER_PROCESS));
-                            let nonce_range: u128 = u128::MAX / MINER_PROCESS as u128;
-                            for multiplier in 0..MINER_PROCESS {
-                                let (thread_tx, thread_rx) = mpsc::channel();
-                                mining_worker(state.master_tx.clone(), thread_rx, nonce_range * multiplier as u128, state.data.clone(), state.diff_mask.clone());
-                                state.workers.push(thread_tx);
-                            }
+                        MinerCommand::NEW_JOB(job) => {
+                            dlog(module_path!(), "Mining server starting new job", &[
+                                format!("Job: {}", job.id),
+                                format!("Target bits: {:#010x}", job.bits)
+                            ]);
+                            state.job = Some(job);
+                            send_job(&state);
+                            state.counter.start_job(state.workers.len(), Instant::now());
                         },
-                        MinerCommand::UPDATE_DIFF(val) => {
-                            state.diff = val;
-                            state.diff_mask = parse_diff_to_mask(val)
+                        MinerCommand::STOP => {
+                            send_workers(&state, || WorkerCommand::IDLE);
+                            state.counter.stop_job();
+                            state.job = None;
+                        },
+                        MinerCommand::PAUSE => {
+                            send_workers(&state, || WorkerCommand::PAUSE);
+                            state.paused = true;
+                        },
+                        MinerCommand::RESUME => {
+                            send_workers(&state, || WorkerCommand::RESUME);
+                            state.paused = false;
+                        },
+                        MinerCommand::SET_THREADS(0) => {
+                            log("Mining server ignored request for zero worker threads.".to_string());
+                        },
+                        MinerCommand::SET_THREADS(threads) => {
+                            log(format!("Mining server resizing from {} to {} worker thread(s).", state.workers.len(), threads));
+                            resize_workers(&mut state, threads as usize);
+                            send_job(&state);
+                            state.counter.set_workers(state.workers.len());
+                        },
+                        MinerCommand::STATS(reply_tx) => {
+                            let _ = reply_tx.send(get_stats(&state));
                         },
-                        MinerCommand::UPDATE_DATA(bin) => {
-                            state.data = bin
-                        }
                         MinerCommand::KILL => {
+                            send_workers(&state, || WorkerCommand::KILL);
                             break
                         }
                     }
                 },
-                Err(e) => {
-                    match e {
-                        RecvTimeoutError::Timeout => (),
-                        RecvTimeoutError::Disconnected => break
-                    }
-                }
-            }
+                ServerEvent::REPORT(WorkerReport::HASHES(worker, hashes, elapsed)) => {
+                    state.counter.add_report(worker, hashes, elapsed);
+                },
+                ServerEvent::REPORT(WorkerReport::SOLVED(job_id, nonce, hash)) => {
+                    let job = match state.job.take() {
+                        Some(job) if job.id == job_id => job,
+                        current => {
+                            dlog(module_path!(), "Discarded stale solution", &[ format!("Job: {}", job_id) ]);
+                            state.job = current;
+                            continue;
+                        }
+                    };
 
-            // Handle message from worker.
-            // Processes valid nonce message from worker threads.
-            let recv = state.master_rx.recv_timeout(Duration::new(0, 0));
-            match recv {
-                Ok(res) => {
-                    let (nonce, hash) = res;
-                    log("Mining server found valid hash for block.".to_string());
+                    match state.counter.add_solution(Instant::now()) {
+                        Some(elapsed) => log(format!("Mining server found valid hash for block in {:.1}s.", elapsed.as_secs_f64())),
+                        None => log("Mining server found valid hash for block.".to_string())
+                    }
                     dlog(module_path!(), "Valid hash found", &[
                         format!("Hash (hex): {:x?}", hash),
                         format!("Nonce: {}", nonce),
-                        format!("Difficulty: {}", state.diff),
-                        format!("Difficulty Mask (hex): {:x?}", state.diff_mask)
+                        format!("Job: {}", job.id),
+                        format!("Target bits: {:#010x}", job.bits)
                     ]);
 
-                    dlog(module_path!(), &format!("Killed {} active mining worker process", state.workers.len()), &[]);
-                    for tx in state.workers.iter() {
-                        tx.send(WorkerCommand::KILL).unwrap();
-                    }
+                    // Leave workers idle until the chain hands over the next job.
+                    send_workers(&state, || WorkerCommand::IDLE);
 
                     let mut buf = [0; 16];
                     byteorder::BigEndian::write_u128(&mut buf, nonce);
-                    state.chain_tx.send((buf, hash)).unwrap();
-                },
-                Err(e) => {
-                    match e {
-                        RecvTimeoutError::Timeout => (),
-                        RecvTimeoutError::Disconnected => break
-                    }
+                    state.chain_tx.send((job.id, buf, hash)).unwrap();
                 }
             }
+
+            // Periodically log the hashrate while workers are active.
+            // Workers report about once a second, so this is checked often enough while mining.
+            if state.job.is_some() && !state.paused && state.last_stats.elapsed() >= STATS_INTERVAL {
+                state.last_stats = Instant::now();
+                let stats = get_stats(&state);
+                log(format!("Mining at {} over {} worker(s), expected time to block of {} hashes: {}.",
+                    format_hashrate(stats.hashrate),
+                    stats.worker_hashrates.len(),
+                    stats.block_work,
+                    stats.expected_block_time.map(|time| format!("{:.0}s", time.as_secs_f64())).unwrap_or("unknown".to_string())
+                ));
+            }
         }
 
     });
 }
 
-// A simple miner worker process.
-// Takes a transmitter to talk to managing process, a nonce interval to begin at,
-// binary data to be worked on, and a difficulty level to meet.
-fn mining_worker(tx: mpsc::Sender<(u128, [u8; 32])>, rx: mpsc::Receiver<WorkerCommand>, mut nonce: u128, binary: Vec<u8>, diff_mask: Vec<u8>) {
+// Forward commands from the parent process to the mining server.
+// Once the parent drops its sender, the server is told to kill its workers and stop.
+fn forward_commands(miner_rx: mpsc::Receiver<MinerCommand>, server_tx: mpsc::Sender<ServerEvent>) -> () {
     thread::spawn(move || {
+        for cmnd in miner_rx.iter() {
+            if server_tx.send(ServerEvent::COMMAND(cmnd)).is_err() {
+                return;
+            }
+        }
+        let _ = server_tx.send(ServerEvent::COMMAND(MinerCommand::KILL));
+    });
+}
 
-        loop {
-            let mut data: Vec<u8> = vec![];
-            data.extend_from_slice(&nonce.to_be_bytes().to_vec());
-            data.extend_from_slice(&binary);
+// Return the statistics of the mining server.
+fn get_stats(state: &State) -> MinerStats {
+    return MinerStats {
+        paused: state.paused,
+        threads: state.workers.len(),
+        ..state.counter.get_stats(get_work(state))
+    };
+}
 
-            let hashed = hash(&data);
+// Spawn or kill workers until the pool holds the given number.
+// New workers start paused if the pool is paused.
+fn resize_workers(state: &mut State, count: usize) -> () {
+    while state.workers.len() > count {
+        if let Some(tx) = state.workers.pop() {
+            let _ = tx.send(WorkerCommand::KILL);
+        }
+    }
+    while state.workers.len() < count {
+        let (thread_tx, thread_rx) = mpsc::channel();
+        mining_worker(state.workers.len(), state.master_tx.clone(), thread_rx);
+        if state.paused {
+            let _ = thread_tx.send(WorkerCommand::PAUSE);
+        }
+        state.workers.push(thread_tx);
+    }
+}
 
-            let mut temp: Vec<u8> = vec![];
-            for i in 0..diff_mask.len() {
-                temp.push(hashed[i] | diff_mask[i]);
-            }
-            if temp == diff_mask {
-                tx.send((nonce.clone(), hashed)).unwrap();
-            }
+// Hand the current job to every worker, each starting at its own share of the nonce space.
+fn send_job(state: &State) -> () {
+    if let Some(job) = state.job.as_ref() {
+        let target = target_from_bits(job.bits).unwrap_or(U256::ZERO).to_bytes();
+        for (worker, tx) in state.workers.iter().enumerate() {
+            let _ = tx.send(WorkerCommand::JOB(job.id, job.data.clone(), target, nonce_start(worker, state.workers.len())));
+        }
+    }
+}
 
-            nonce += 1;
+// Return the first nonce tried by a worker, splitting the nonce space evenly across the pool.
+fn nonce_start(worker: usize, workers: usize) -> u128 {
+    return (u128::MAX / workers.max(1) as u128) * worker as u128;
+}
 
-            let recv = rx.recv_timeout(Duration::new(0, 0));
+// Return the work represented by the target of the current job.
+fn get_work(state: &State) -> u128 {
+    return state.job.as_ref().map(|job| block_work(job.bits)).unwrap_or(0);
+}
+
+// Send the same command to every worker in the pool.
+fn send_workers(state: &State, cmnd: impl Fn() -> WorkerCommand) -> () {
+    for tx in state.workers.iter() {
+        let _ = tx.send(cmnd());
+    }
+}
+
+// A long-lived miner worker process.
+// Takes its worker index, a transmitter to talk to managing process and a receiver for commands.
+// Each job gives binary data to be worked on, a target to meet and a nonce interval to begin at.
+// Workers block while idle or paused, and stop working on a job once they solve it.
+// The number of hashes tried is reported to the managing process about once a second.
+fn mining_worker(id: usize, tx: mpsc::Sender<ServerEvent>, rx: mpsc::Receiver<WorkerCommand>) {
+    thread::spawn(move || {
+        let mut job: Option<(u64, Vec<u8>, [u8; 32], u128)> = None;
+        let mut paused = false;
+        let mut hashes: u64 = 0;
+        let mut last_report = Instant::now();
+
+        loop {
+            // Wait for a command while there is nothing to work on, otherwise poll.
+            let recv = match job.is_none() || paused {
+                true => rx.recv().map_err(|_| RecvTimeoutError::Disconnected),
+                false => rx.recv_timeout(Duration::new(0, 0))
+            };
             match recv {
                 Ok(cmnd) => {
                     match cmnd {
+                        WorkerCommand::JOB(job_id, binary, target, nonce) => job = Some((job_id, binary, target, nonce)),
+                        WorkerCommand::IDLE => job = None,
+                        WorkerCommand::PAUSE => paused = true,
+                        WorkerCommand::RESUME => paused = false,
                         WorkerCommand::KILL => break
                     }
+                    hashes = 0;
+                    last_report = Instant::now();
+                    continue;
                 },
                 Err(e) => {
                     match e {
@@ -160,27 +428,154 @@ fn mining_worker(tx: mpsc::Sender<(u128, [u8; 32])>, rx: mpsc::Receiver<WorkerCo
                     }
                 }
             }
+
+            let (job_id, binary, target, nonce) = match job.as_mut() {
+                None => continue,
+                Some(job) => job
+            };
+
+            let mut data: Vec<u8> = vec![];
+            data.extend_from_slice(&nonce.to_be_bytes());
+            data.extend_from_slice(binary);
+
+            let hashed = hash(&data);
+
+            // Big-endian hashes and targets compare as integers.
+            if hashed <= *target {
+                let _ = tx.send(ServerEvent::REPORT(WorkerReport::SOLVED(*job_id, *nonce, hashed)));
+                job = None;
+                continue;
+            }
+
+            *nonce += 1;
+            hashes += 1;
+            if hashes.is_multiple_of(REPORT_CHECK) && last_report.elapsed() >= REPORT_INTERVAL {
+                let _ = tx.send(ServerEvent::REPORT(WorkerReport::HASHES(id, hashes, last_report.elapsed())));
+                hashes = 0;
+                last_report = Instant::now();
+            }
         }
 
     });
 }
 
-// Given a difficulty level returns the equivalent Vec<u8>
-// mask to apply and check against hash.
-fn parse_diff_to_mask(diff: u8) -> Vec<u8> {
-    let mut bin_str: Vec<char> = vec![];
 
-    // Append difficulty mask and pad
-    // to nearest byte multiple.
-    for _ in 0..diff { bin_str.push('0'); }
-    for _ in 0..(8 - (diff % 8)) { bin_str.push('1'); }
 
-    // Separate char string into segments of 8 and convert
-    // to equivalent u8 binary value.
-    let split = &bin_str.chunks(8).map(|chunk| {
-        let byte = chunk.iter().collect::<String>();
-        u8::from_str_radix(&byte, 2).unwrap()
-    }).collect::<Vec<u8>>();
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::target::{MAX_BITS, bits_from_diff, meets_target};
+
+    #[test]
+    fn aggregates_worker_reports() {
+        let start = Instant::now();
+        let mut counter = HashCounter::new();
+        counter.start_job(2, start);
+        counter.add_report(0, 3000, Duration::from_secs(2));
+        counter.add_report(1, 1000, Duration::from_secs(1));
+        counter.add_report(5, 1000, Duration::from_secs(1));
 
-    return split.clone();
-}
\ No newline at end of file
+        let stats = counter.get_stats(1024);
+        assert_eq!(stats.worker_hashrates, vec![1500.0, 1000.0]);
+        assert_eq!(stats.hashrate, 2500.0);
+        assert_eq!(stats.total_hashes, 5000);
+        assert_eq!(stats.expected_block_time, Some(Duration::from_secs_f64(1024.0 / 2500.0)));
+        assert_eq!(stats.average_block_time, None);
+
+        assert_eq!(counter.add_solution(start + Duration::from_secs(4)), Some(Duration::from_secs(4)));
+        counter.start_job(2, start + Duration::from_secs(4));
+        counter.add_solution(start + Duration::from_secs(6));
+        let stats = counter.get_stats(1024);
+        assert_eq!(stats.blocks_solved, 2);
+        assert_eq!(stats.last_block_time, Some(Duration::from_secs(2)));
+        assert_eq!(stats.average_block_time, Some(Duration::from_secs(3)));
+        assert_eq!(stats.hashrate, 0.0);
+        assert_eq!(stats.expected_block_time, None);
+    }
+
+    #[test]
+    fn partitions_nonce_space() {
+        assert_eq!(nonce_start(0, 1), 0);
+        assert_eq!(nonce_start(1, 2), u128::MAX / 2);
+        assert_eq!(nonce_start(2, 3), u128::MAX / 3 * 2);
+        assert_eq!(nonce_start(0, 0), 0);
+    }
+
+    #[test]
+    fn resizes_worker_pool() {
+        let (chain_tx, chain_rx) = mpsc::channel();
+        let (miner_tx, miner_rx) = mpsc::channel();
+        start_mining_server(chain_tx, miner_rx, 1);
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 1, data: vec![7], bits: 0x0300_0001 })).unwrap();
+        miner_tx.send(MinerCommand::SET_THREADS(3)).unwrap();
+        miner_tx.send(MinerCommand::SET_THREADS(0)).unwrap();
+
+        let (stats_tx, stats_rx) = mpsc::channel();
+        miner_tx.send(MinerCommand::STATS(stats_tx)).unwrap();
+        let stats = stats_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(stats.threads, 3);
+        assert_eq!(stats.worker_hashrates.len(), 3);
+
+        // The resized pool keeps mining new jobs.
+        miner_tx.send(MinerCommand::SET_THREADS(2)).unwrap();
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 2, data: vec![7], bits: MAX_BITS })).unwrap();
+        let (job_id, _, _) = chain_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(job_id, 2);
+        miner_tx.send(MinerCommand::KILL).unwrap();
+    }
+
+    #[test]
+    fn formats_hashrates() {
+        assert_eq!(format_hashrate(950.0), "950.00 H/s");
+        assert_eq!(format_hashrate(1_500_000.0), "1.50 MH/s");
+        assert_eq!(format_hashrate(0.0), "0.00 H/s");
+    }
+
+    #[test]
+    fn reports_stats_on_request() {
+        let (chain_tx, chain_rx) = mpsc::channel();
+        let (miner_tx, miner_rx) = mpsc::channel();
+        start_mining_server(chain_tx, miner_rx, 2);
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 1, data: vec![1, 2, 3], bits: bits_from_diff(4) })).unwrap();
+        chain_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+
+        let (stats_tx, stats_rx) = mpsc::channel();
+        miner_tx.send(MinerCommand::STATS(stats_tx)).unwrap();
+        let stats = stats_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(stats.blocks_solved, 1);
+        assert!(stats.last_block_time.is_some());
+        miner_tx.send(MinerCommand::KILL).unwrap();
+    }
+
+    #[test]
+    fn replaces_pauses_and_resumes_jobs() {
+        let (chain_tx, chain_rx) = mpsc::channel();
+        let (miner_tx, miner_rx) = mpsc::channel();
+        start_mining_server(chain_tx, miner_rx, 2);
+
+        // Solutions are tagged with the job they solve.
+        let data = vec![4, 5, 6];
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 1, data: data.clone(), bits: bits_from_diff(6) })).unwrap();
+        let (job_id, nonce, hashed) = chain_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(job_id, 1);
+        let mut solved = nonce.to_vec();
+        solved.extend_from_slice(&data);
+        assert_eq!(hash(&solved), hashed);
+        assert!(meets_target(&hashed, bits_from_diff(6)));
+
+        // A job too hard to solve is replaced without restarting the workers,
+        // and no work is done on the replacement while paused.
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 2, data: data.clone(), bits: 0x0300_0001 })).unwrap();
+        miner_tx.send(MinerCommand::PAUSE).unwrap();
+        miner_tx.send(MinerCommand::NEW_JOB(MiningJob { id: 3, data: data.clone(), bits: MAX_BITS })).unwrap();
+        assert!(chain_rx.recv_timeout(Duration::from_millis(200)).is_err());
+        let (stats_tx, stats_rx) = mpsc::channel();
+        miner_tx.send(MinerCommand::STATS(stats_tx)).unwrap();
+        assert!(stats_rx.recv_timeout(Duration::from_secs(10)).unwrap().paused);
+
+        miner_tx.send(MinerCommand::RESUME).unwrap();
+        let (job_id, _, _) = chain_rx.recv_timeout(Duration::from_secs(10)).unwrap();
+        assert_eq!(job_id, 3);
+        miner_tx.send(MinerCommand::KILL).unwrap();
+    }
+}
@@ -0,0 +1,754 @@
+use crate::block_struct::{Block, BlockHeader};
+use crate::codec::{DecodeError, take, take_array, take_u8, take_u16, take_u32, push_u16_len, check_end};
+use crate::config::NodeConfig;
+use crate::log::{log, dlog};
+use crate::tx_struct::{Tx, tx_from_bin};
+use crate::util::hash;
+use byteorder::{ByteOrder, BigEndian};
+use rand::Rng;
+use std::collections::{HashMap, HashSet};
+use std::io::{Read, Write, ErrorKind};
+use std::net::{SocketAddr, TcpListener, TcpStream, Shutdown};
+use std::sync::mpsc;
+use std::sync::mpsc::TryRecvError;
+use std::thread;
+use std::time::{Duration, Instant};
+
+pub const PROTOCOL_VERSION: u32 = 2;                        // Version sent in handshakes, peers must match
+const MAGIC: [u8; 4] = *b"RUST";                            // Bytes starting every frame
+const HEADER_LEN: usize = 13;                               // Magic, message type, payload length and checksum
+const MAX_PAYLOAD: usize = 8_000_000;                       // Largest payload accepted from a peer, above MAX_BLOCK_BYTES
+const MAX_HANDSHAKE_PAYLOAD: usize = 256;                   // Largest payload accepted before the peer sends VERACK
+const DIAL_INTERVAL: Duration = Duration::from_secs(10);    // Delay between attempts to reach configured peers
+const DIAL_TIMEOUT: Duration = Duration::from_secs(5);      // Time allowed to open an outbound connection
+const HANDSHAKE_TIMEOUT: Duration = Duration::from_secs(10); // Time allowed to complete the version handshake
+const PING_INTERVAL: Duration = Duration::from_secs(30);    // Delay between pings to an idle peer
+const PEER_TIMEOUT: Duration = Duration::from_secs(90);     // Silence after which a peer is dropped
+
+// Identifier of a connection, unique for the life of the node.
+pub type PeerId = u64;
+
+// Errors raised when reading a frame from a peer.
+#[derive(Debug, Clone, PartialEq)]
+pub enum NetError {
+    Io(ErrorKind),              // Connection failed or closed
+    BadMagic([u8; 4]),          // Frame did not start with the protocol magic
+    BadChecksum,                // Payload does not match the frame checksum
+    PayloadTooLarge(usize),     // Payload length above the limit for the connection
+    UnknownMessage(u8),         // Message type byte not matching any Message
+    UnknownInvType(u8),         // Inv type byte not matching any InvType
+    Decode(DecodeError)         // Payload could not be decoded
+}
+
+impl From<DecodeError> for NetError {
+    fn from(why: DecodeError) -> NetError {
+        return NetError::Decode(why);
+    }
+}
+
+// Kinds of object announced in inv, getdata and notfound messages.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub enum InvType {
+    Block = 0x01,
+    Tx = 0x02
+}
+
+// An announced object, identified by its hash.
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash)]
+pub struct InvItem {
+    pub inv_type: InvType,
+    pub hash: [u8; 32]
+}
+
+// Handshake sent by both sides when a connection opens.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct Version {
+    pub version: u32,           // Protocol version of the sender
+    pub block_count: u32,       // Number of blocks held by the sender
+    pub port: u16,              // Port the sender listens on
+    pub nonce: u64              // Random per-node value used to detect connections to self
+}
+
+// Messages exchanged between peers.
+pub enum Message {
+    VERSION(Version),
+    VERACK,
+    PING(u64),
+    PONG(u64),
+    INV(Vec<InvItem>),
+    GETDATA(Vec<InvItem>),
+    NOTFOUND(Vec<InvItem>),
+    BLOCK(Block),
+    TX(Box<dyn Tx>),
+    GETHEADERS(Vec<[u8; 32]>),      // Block locator, hashes of known blocks from the tip backwards
+    HEADERS(Vec<BlockHeader>)
+}
+
+// Events raised by the network server for the chain.
+pub enum NetEvent {
+    PEER_READY(PeerId, u32),    // Handshake completed (peer, block count announced)
+    PEER_LOST(PeerId),          // A ready peer disconnected
+    MESSAGE(PeerId, Message)    // Any non-handshake, non-ping message from a ready peer
+}
+
+// Commands accepted by the network server.
+pub enum NetCommand {
+    SEND(PeerId, Message),
+    BROADCAST(Message, Option<PeerId>),     // Send to every ready peer, except the given one
+    UPDATE_BLOCK_COUNT(u32),                // Block count announced in future handshakes
+    #[cfg(test)]
+    KILL                                    // Stop the server, which otherwise stops once every sender is dropped
+}
+
+// Events sent to the network server by listener, dialer and connection threads.
+enum PeerEvent {
+    CONNECTED(TcpStream, Option<SocketAddr>),   // New connection, with the dialed address if outbound
+    DIAL_FAILED(SocketAddr),
+    MESSAGE(PeerId, Message),
+    CLOSED(PeerId, NetError)
+}
+
+// A connected peer.
+struct Peer {
+    stream: TcpStream,
+    dialed: Option<SocketAddr>,         // Configured address if the connection is outbound
+    writer: mpsc::Sender<Vec<u8>>,      // Frames queued for the writer thread
+    version: Option<Version>,
+    verack: bool,
+    opened: Instant,
+    last_seen: Instant,
+    last_ping: Instant
+}
+
+impl Peer {
+    // A peer is ready once both sides have sent and acknowledged a version.
+    fn is_ready(&self) -> bool {
+        return self.version.is_some() && self.verack;
+    }
+}
+
+// Network server state.
+struct State {
+    nonce: u64,
+    port: u16,
+    block_count: u32,
+    max_peers: usize,
+    peers: HashMap<PeerId, Peer>,
+    next_id: PeerId,
+    dial_peers: Vec<SocketAddr>,
+    dialing: HashSet<SocketAddr>,
+    last_dial: Option<Instant>,
+    event_tx: mpsc::Sender<NetEvent>,
+    peer_tx: mpsc::Sender<PeerEvent>
+}
+
+impl InvType {
+    // Parse an inv type byte.
+    fn from_u8(val: u8) -> Result<InvType, NetError> {
+        match val {
+            0x01 => Ok(InvType::Block),
+            0x02 => Ok(InvType::Tx),
+            _ => Err(NetError::UnknownInvType(val))
+        }
+    }
+}
+
+impl Message {
+    // Return the message type byte.
+    pub fn get_type(&self) -> u8 {
+        return match self {
+            Message::VERSION(_) => 0x00,
+            Message::VERACK => 0x01,
+            Message::PING(_) => 0x02,
+            Message::PONG(_) => 0x03,
+            Message::INV(_) => 0x04,
+            Message::GETDATA(_) => 0x05,
+            Message::NOTFOUND(_) => 0x06,
+            Message::BLOCK(_) => 0x07,
+            Message::TX(_) => 0x08,
+            Message::GETHEADERS(_) => 0x09,
+            Message::HEADERS(_) => 0x0a
+        };
+    }
+
+    // Return the message name, used when logging.
+    pub fn get_name(&self) -> &'static str {
+        return match self {
+            Message::VERSION(_) => "version",
+            Message::VERACK => "verack",
+            Message::PING(_) => "ping",
+            Message::PONG(_) => "pong",
+            Message::INV(_) => "inv",
+            Message::GETDATA(_) => "getdata",
+            Message::NOTFOUND(_) => "notfound",
+            Message::BLOCK(_) => "block",
+            Message::TX(_) => "tx",
+            Message::GETHEADERS(_) => "getheaders",
+            Message::HEADERS(_) => "headers"
+        };
+    }
+
+    // Convert the message body to a binary payload.
+    pub fn to_bin(&self) -> Vec<u8> {
+        let mut bin: Vec<u8> = vec![];
+        match self {
+            Message::VERSION(version) => {
+                bin.extend_from_slice(&version.version.to_be_bytes());
+                bin.extend_from_slice(&version.block_count.to_be_bytes());
+                bin.extend_from_slice(&version.port.to_be_bytes());
+                bin.extend_from_slice(&version.nonce.to_be_bytes());
+            },
+            Message::VERACK => (),
+            Message::PING(nonce) | Message::PONG(nonce) => bin.extend_from_slice(&nonce.to_be_bytes()),
+            Message::INV(items) | Message::GETDATA(items) | Message::NOTFOUND(items) => {
+                push_u16_len(&mut bin, items.len());
+                for item in items.iter() {
+                    bin.push(item.inv_type as u8);
+                    bin.extend_from_slice(&item.hash);
+                }
+            },
+            Message::BLOCK(block) => bin.extend(block.to_bin()),
+            Message::TX(tx) => bin.extend(tx.to_bin()),
+            Message::GETHEADERS(locator) => {
+                push_u16_len(&mut bin, locator.len());
+                for hash in locator.iter() {
+                    bin.extend_from_slice(hash);
+                }
+            },
+            Message::HEADERS(headers) => {
+                push_u16_len(&mut bin, headers.len());
+                for header in headers.iter() {
+                    bin.extend(header.to_bin());
+                }
+            }
+        }
+
+        return bin;
+    }
+
+    // Parse a payload of the given message type.
+    pub fn from_bin(msg_type: u8, bin: &[u8]) -> Result<Message, NetError> {
+        let mut pos: usize = 0;
+        let message = match msg_type {
+            0x00 => Message::VERSION(Version {
+                version: take_u32(bin, &mut pos)?,
+                block_count: take_u32(bin, &mut pos)?,
+                port: take_u16(bin, &mut pos)?,
+                nonce: BigEndian::read_u64(take(bin, &mut pos, 8)?)
+            }),
+            0x01 => Message::VERACK,
+            0x02 => Message::PING(BigEndian::read_u64(take(bin, &mut pos, 8)?)),
+            0x03 => Message::PONG(BigEndian::read_u64(take(bin, &mut pos, 8)?)),
+            0x04 => Message::INV(take_inv_items(bin, &mut pos)?),
+            0x05 => Message::GETDATA(take_inv_items(bin, &mut pos)?),
+            0x06 => Message::NOTFOUND(take_inv_items(bin, &mut pos)?),
+            0x07 => return Ok(Message::BLOCK(Block::from_bin(bin)?)),
+            0x08 => return Ok(Message::TX(tx_from_bin(bin)?)),
+            0x09 => {
+                let count = take_u16(bin, &mut pos)?;
+                let mut locator: Vec<[u8; 32]> = vec![];
+                for _ in 0..count {
+                    locator.push(take_array(bin, &mut pos)?);
+                }
+                Message::GETHEADERS(locator)
+            },
+            0x0a => {
+                let count = take_u16(bin, &mut pos)?;
+                let mut headers: Vec<BlockHeader> = vec![];
+                for _ in 0..count {
+                    headers.push(BlockHeader::take(bin, &mut pos)?);
+                }
+                Message::HEADERS(headers)
+            },
+            _ => return Err(NetError::UnknownMessage(msg_type))
+        };
+        check_end(bin, pos)?;
+
+        return Ok(message);
+    }
+
+    // Convert the message to a frame ready to be written to a peer.
+    // Frame layout: magic | message type | payload length (u32) | checksum | payload
+    pub fn to_frame(&self) -> Vec<u8> {
+        let payload = self.to_bin();
+        let mut frame: Vec<u8> = Vec::with_capacity(HEADER_LEN + payload.len());
+        frame.extend_from_slice(&MAGIC);
+        frame.push(self.get_type());
+        frame.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        frame.extend_from_slice(&checksum(&payload));
+        frame.extend(payload);
+
+        return frame;
+    }
+}
+
+// Read a u16 counted list of inv items.
+fn take_inv_items(bin: &[u8], pos: &mut usize) -> Result<Vec<InvItem>, NetError> {
+    let count = take_u16(bin, pos)?;
+    let mut items: Vec<InvItem> = vec![];
+    for _ in 0..count {
+        let inv_type = InvType::from_u8(take_u8(bin, pos)?)?;
+        items.push(InvItem {
+            inv_type: inv_type,
+            hash: take_array(bin, pos)?
+        });
+    }
+
+    return Ok(items);
+}
+
+// First four bytes of the payload hash.
+fn checksum(payload: &Vec<u8>) -> [u8; 4] {
+    let mut sum = [0; 4];
+    sum.copy_from_slice(&hash(payload)[0..4]);
+    return sum;
+}
+
+// Read the next frame from a stream and decode its message.
+// Frames with a payload longer than max_len are refused before the payload is read.
+pub fn read_message(stream: &mut dyn Read, max_len: usize) -> Result<Message, NetError> {
+    let mut header = [0; HEADER_LEN];
+    stream.read_exact(&mut header).map_err(|why| NetError::Io(why.kind()))?;
+
+    let mut pos: usize = 0;
+    let magic: [u8; 4] = take_array(&header, &mut pos)?;
+    if magic != MAGIC {
+        return Err(NetError::BadMagic(magic));
+    }
+    let msg_type = take_u8(&header, &mut pos)?;
+    let len = take_u32(&header, &mut pos)? as usize;
+    if len > max_len {
+        return Err(NetError::PayloadTooLarge(len));
+    }
+    let sum: [u8; 4] = take_array(&header, &mut pos)?;
+
+    let mut payload = vec![0; len];
+    stream.read_exact(&mut payload).map_err(|why| NetError::Io(why.kind()))?;
+    if checksum(&payload) != sum {
+        return Err(NetError::BadChecksum);
+    }
+
+    return Message::from_bin(msg_type, &payload);
+}
+
+
+// Start the network server.
+// Accepts connections on the listener, keeps dialing the configured peers,
+// performs the version handshake and keeps connections alive with pings.
+// Messages from ready peers are passed to the chain through event_tx.
+pub fn start_net_interface(listener: TcpListener, config: &NodeConfig, block_count: u32, event_tx: mpsc::Sender<NetEvent>) -> mpsc::Sender<NetCommand> {
+    let (net_tx, net_rx) = mpsc::channel();
+    let (peer_tx, peer_rx) = mpsc::channel();
+    let port = listener.local_addr().map(|addr| addr.port()).unwrap_or(config.port);
+
+    // Accept inbound connections.
+    let accept_tx = peer_tx.clone();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            match stream {
+                Ok(stream) => if accept_tx.send(PeerEvent::CONNECTED(stream, None)).is_err() { break },
+                Err(why) => dlog(module_path!(), "Failed to accept connection", &[ format!("Reason: {}", why) ])
+            }
+        }
+    });
+
+    let mut state = State {
+        nonce: rand::thread_rng().gen(),
+        port: port,
+        block_count: block_count,
+        max_peers: config.max_peers,
+        peers: HashMap::new(),
+        next_id: 0,
+        dial_peers: config.get_peers(),
+        dialing: HashSet::new(),
+        last_dial: None,
+        event_tx: event_tx,
+        peer_tx: peer_tx
+    };
+
+    thread::spawn(move || {
+        log(format!("Network server listening on port {}.", state.port));
+
+        'server: loop {
+            // Handle every command queued by the chain.
+            loop {
+                match net_rx.try_recv() {
+                    Ok(NetCommand::SEND(id, message)) => send(&state, id, &message),
+                    Ok(NetCommand::BROADCAST(message, except)) => {
+                        let frame = message.to_frame();
+                        for (id, peer) in state.peers.iter() {
+                            if peer.is_ready() && Some(*id) != except {
+                                let _ = peer.writer.send(frame.clone());
+                            }
+                        }
+                    },
+                    Ok(NetCommand::UPDATE_BLOCK_COUNT(count)) => state.block_count = count,
+                    #[cfg(test)]
+                    Ok(NetCommand::KILL) => break 'server,
+                    Err(TryRecvError::Disconnected) => break 'server,
+                    Err(TryRecvError::Empty) => break
+                }
+            }
+
+            // Handle events from connection threads.
+            match peer_rx.recv_timeout(Duration::from_millis(50)) {
+                Ok(PeerEvent::CONNECTED(stream, dialed)) => add_peer(&mut state, stream, dialed),
+                Ok(PeerEvent::DIAL_FAILED(addr)) => { state.dialing.remove(&addr); },
+                Ok(PeerEvent::MESSAGE(id, message)) => handle_message(&mut state, id, message),
+                Ok(PeerEvent::CLOSED(id, why)) => remove_peer(&mut state, id, &format!("{:?}", why)),
+                Err(_) => ()
+            }
+
+            dial_peers(&mut state);
+            check_peers(&mut state);
+        }
+
+        for id in state.peers.keys().cloned().collect::<Vec<PeerId>>() {
+            remove_peer(&mut state, id, "Network server stopped");
+        }
+    });
+
+    return net_tx;
+}
+
+// Queue a message for a peer.
+fn send(state: &State, id: PeerId, message: &Message) -> () {
+    if let Some(peer) = state.peers.get(&id) {
+        let _ = peer.writer.send(message.to_frame());
+    }
+}
+
+// Register a new connection, start its reader and writer threads and send our version.
+fn add_peer(state: &mut State, stream: TcpStream, dialed: Option<SocketAddr>) -> () {
+    if let Some(addr) = dialed {
+        state.dialing.remove(&addr);
+    }
+    if state.peers.len() >= state.max_peers {
+        dlog(module_path!(), "Rejected connection, peer limit reached", &[ format!("Peers: {}", state.peers.len()) ]);
+        let _ = stream.shutdown(Shutdown::Both);
+        return;
+    }
+
+    let (mut reader, mut writer) = match (stream.try_clone(), stream.try_clone()) {
+        (Ok(reader), Ok(writer)) => (reader, writer),
+        _ => return
+    };
+    let id = state.next_id;
+    state.next_id += 1;
+
+    // Reader thread, decodes frames until the connection fails.
+    // Only small frames are read until the peer acknowledges the handshake.
+    let peer_tx = state.peer_tx.clone();
+    thread::spawn(move || {
+        let mut max_len = MAX_HANDSHAKE_PAYLOAD;
+        loop {
+            match read_message(&mut reader, max_len) {
+                Ok(message) => {
+                    if let Message::VERACK = message {
+                        max_len = MAX_PAYLOAD;
+                    }
+                    if peer_tx.send(PeerEvent::MESSAGE(id, message)).is_err() { break }
+                },
+                Err(why) => {
+                    let _ = peer_tx.send(PeerEvent::CLOSED(id, why));
+                    break;
+                }
+            }
+        }
+    });
+
+    // Writer thread, writes queued frames until the queue is dropped.
+    let (frame_tx, frame_rx) = mpsc::channel::<Vec<u8>>();
+    thread::spawn(move || {
+        for frame in frame_rx.iter() {
+            if writer.write_all(&frame).is_err() {
+                let _ = writer.shutdown(Shutdown::Both);
+                break;
+            }
+        }
+    });
+
+    dlog(module_path!(), "Opened peer connection", &[
+        format!("Peer: {}", id),
+        format!("Address: {:?}", stream.peer_addr()),
+        format!("Outbound: {}", dialed.is_some())
+    ]);
+
+    let now = Instant::now();
+    state.peers.insert(id, Peer {
+        stream: stream,
+        dialed: dialed,
+        writer: frame_tx,
+        version: None,
+        verack: false,
+        opened: now,
+        last_seen: now,
+        last_ping: now
+    });
+    send(state, id, &Message::VERSION(Version {
+        version: PROTOCOL_VERSION,
+        block_count: state.block_count,
+        port: state.port,
+        nonce: state.nonce
+    }));
+}
+
+// Close a connection, telling the chain if the peer had completed its handshake.
+fn remove_peer(state: &mut State, id: PeerId, reason: &str) -> () {
+    if let Some(peer) = state.peers.remove(&id) {
+        let _ = peer.stream.shutdown(Shutdown::Both);
+        dlog(module_path!(), "Closed peer connection", &[
+            format!("Peer: {}", id),
+            format!("Reason: {}", reason)
+        ]);
+        if peer.is_ready() {
+            let _ = state.event_tx.send(NetEvent::PEER_LOST(id));
+        }
+    }
+}
+
+// Handle a message from a peer.
+// Handshake and keep-alive messages are answered here, everything else is passed to the chain.
+fn handle_message(state: &mut State, id: PeerId, message: Message) -> () {
+    let (ready, has_version) = match state.peers.get_mut(&id) {
+        None => return,
+        Some(peer) => {
+            peer.last_seen = Instant::now();
+            (peer.is_ready(), peer.version.is_some())
+        }
+    };
+
+    match message {
+        Message::VERSION(version) => {
+            if has_version {
+                return remove_peer(state, id, "Duplicate version message");
+            }
+            if version.nonce == state.nonce {
+                // Connected to ourselves, stop dialing the address.
+                if let Some(addr) = state.peers.get(&id).and_then(|peer| peer.dialed) {
+                    state.dial_peers.retain(|peer| *peer != addr);
+                }
+                return remove_peer(state, id, "Connected to self");
+            }
+            if version.version != PROTOCOL_VERSION {
+                return remove_peer(state, id, &format!("Unsupported protocol version {}", version.version));
+            }
+            if state.peers.values().any(|peer| peer.version.map(|v| v.nonce) == Some(version.nonce)) {
+                return remove_peer(state, id, "Already connected to node");
+            }
+
+            state.peers.get_mut(&id).unwrap().version = Some(version);
+            send(state, id, &Message::VERACK);
+            peer_ready(state, id);
+        },
+        Message::VERACK => {
+            state.peers.get_mut(&id).unwrap().verack = true;
+            peer_ready(state, id);
+        },
+        Message::PING(nonce) => send(state, id, &Message::PONG(nonce)),
+        Message::PONG(_) => (),
+        message if ready => {
+            let _ = state.event_tx.send(NetEvent::MESSAGE(id, message));
+        },
+        message => remove_peer(state, id, &format!("Sent {} before completing handshake", message.get_name()))
+    }
+}
+
+// Tell the chain a peer has completed its handshake.
+fn peer_ready(state: &State, id: PeerId) -> () {
+    if let Some(peer) = state.peers.get(&id) {
+        if let (Some(version), true) = (peer.version, peer.is_ready()) {
+            log(format!("Connected to peer {} holding {} block(s).", id, version.block_count));
+            let _ = state.event_tx.send(NetEvent::PEER_READY(id, version.block_count));
+        }
+    }
+}
+
+// Dial configured peers that are not connected.
+fn dial_peers(state: &mut State) -> () {
+    if state.last_dial.map(|last| last.elapsed() < DIAL_INTERVAL).unwrap_or(false) {
+        return;
+    }
+    state.last_dial = Some(Instant::now());
+
+    for addr in state.dial_peers.clone().into_iter() {
+        let connected = state.peers.values().any(|peer| peer.dialed == Some(addr));
+        if connected || state.dialing.contains(&addr) || state.peers.len() + state.dialing.len() >= state.max_peers {
+            continue;
+        }
+
+        state.dialing.insert(addr);
+        let peer_tx = state.peer_tx.clone();
+        thread::spawn(move || {
+            let _ = match TcpStream::connect_timeout(&addr, DIAL_TIMEOUT) {
+                Ok(stream) => peer_tx.send(PeerEvent::CONNECTED(stream, Some(addr))),
+                Err(_) => peer_tx.send(PeerEvent::DIAL_FAILED(addr))
+            };
+        });
+    }
+}
+
+// Ping idle peers and drop peers that stalled during the handshake or stopped responding.
+fn check_peers(state: &mut State) -> () {
+    let mut stale: Vec<(PeerId, &str)> = vec![];
+    for (id, peer) in state.peers.iter_mut() {
+        if !peer.is_ready() && peer.opened.elapsed() > HANDSHAKE_TIMEOUT {
+            stale.push((*id, "Handshake timed out"));
+        }
+        else if peer.last_seen.elapsed() > PEER_TIMEOUT {
+            stale.push((*id, "Peer timed out"));
+        }
+        else if peer.is_ready() && peer.last_ping.elapsed() > PING_INTERVAL {
+            peer.last_ping = Instant::now();
+            let _ = peer.writer.send(Message::PING(rand::thread_rng().gen()).to_frame());
+        }
+    }
+
+    for (id, reason) in stale.into_iter() {
+        remove_peer(state, id, reason);
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tx_struct::DataTx;
+
+    fn round_trip(message: Message) -> Message {
+        let frame = message.to_frame();
+        return read_message(&mut &frame[..], MAX_PAYLOAD).unwrap();
+    }
+
+    fn items() -> Vec<InvItem> {
+        return vec![
+            InvItem { inv_type: InvType::Block, hash: [1; 32] },
+            InvItem { inv_type: InvType::Tx, hash: [2; 32] }
+        ];
+    }
+
+    #[test]
+    fn messages_round_trip() {
+        let version = Version { version: PROTOCOL_VERSION, block_count: 12, port: 55845, nonce: 99 };
+        match round_trip(Message::VERSION(version)) { Message::VERSION(v) => assert_eq!(v, version), _ => panic!() }
+        match round_trip(Message::VERACK) { Message::VERACK => (), _ => panic!() }
+        match round_trip(Message::PING(7)) { Message::PING(n) => assert_eq!(n, 7), _ => panic!() }
+        match round_trip(Message::PONG(7)) { Message::PONG(n) => assert_eq!(n, 7), _ => panic!() }
+        match round_trip(Message::INV(items())) { Message::INV(i) => assert_eq!(i, items()), _ => panic!() }
+        match round_trip(Message::GETDATA(items())) { Message::GETDATA(i) => assert_eq!(i, items()), _ => panic!() }
+        match round_trip(Message::NOTFOUND(vec![])) { Message::NOTFOUND(i) => assert!(i.is_empty()), _ => panic!() }
+        match round_trip(Message::GETHEADERS(vec![[3; 32], [4; 32]])) { Message::GETHEADERS(l) => assert_eq!(l, vec![[3; 32], [4; 32]]), _ => panic!() }
+
+        let mut block = Block::new();
+        block.miner = vec![1, 2, 3];
+        block.generate_hash();
+        match round_trip(Message::BLOCK(Block::from_bin(&block.to_bin()).unwrap())) {
+            Message::BLOCK(b) => assert_eq!(b.to_bin(), block.to_bin()),
+            _ => panic!()
+        }
+        match round_trip(Message::HEADERS(vec![block.get_header(), block.get_header()])) {
+            Message::HEADERS(h) => assert_eq!(h, vec![block.get_header(), block.get_header()]),
+            _ => panic!()
+        }
+
+        let mut tx = DataTx::new();
+        tx.owner = vec![4, 5, 6];
+        tx.generate_hash();
+        match round_trip(Message::TX(Box::new(tx.clone()))) {
+            Message::TX(t) => assert_eq!(t.to_bin(), tx.to_bin()),
+            _ => panic!()
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_frames() {
+        let frame = Message::PING(1).to_frame();
+
+        let mut bad_magic = frame.clone();
+        bad_magic[0] = b'X';
+        assert!(matches!(read_message(&mut &bad_magic[..], MAX_PAYLOAD), Err(NetError::BadMagic(_))));
+
+        let mut bad_sum = frame.clone();
+        *bad_sum.last_mut().unwrap() ^= 0xff;
+        assert_eq!(read_message(&mut &bad_sum[..], MAX_PAYLOAD).err(), Some(NetError::BadChecksum));
+
+        let mut unknown = frame.clone();
+        unknown[4] = 0x7f;
+        assert_eq!(read_message(&mut &unknown[..], MAX_PAYLOAD).err(), Some(NetError::UnknownMessage(0x7f)));
+
+        let mut too_large = frame.clone();
+        too_large[5..9].copy_from_slice(&(MAX_PAYLOAD as u32 + 1).to_be_bytes());
+        assert_eq!(read_message(&mut &too_large[..], MAX_PAYLOAD).err(), Some(NetError::PayloadTooLarge(MAX_PAYLOAD + 1)));
+
+        assert_eq!(read_message(&mut &frame[..frame.len() - 1], MAX_PAYLOAD).err(), Some(NetError::Io(ErrorKind::UnexpectedEof)));
+    }
+
+    #[test]
+    fn handshake_limit_refuses_large_frames() {
+        let version = Version { version: PROTOCOL_VERSION, block_count: 12, port: 55845, nonce: 99 };
+        assert!(read_message(&mut &Message::VERSION(version).to_frame()[..], MAX_HANDSHAKE_PAYLOAD).is_ok());
+        assert!(read_message(&mut &Message::VERACK.to_frame()[..], MAX_HANDSHAKE_PAYLOAD).is_ok());
+
+        let headers = Message::GETHEADERS(vec![[3; 32]; 10]).to_frame();
+        let len = headers.len() - HEADER_LEN;
+        assert_eq!(read_message(&mut &headers[..], MAX_HANDSHAKE_PAYLOAD).err(), Some(NetError::PayloadTooLarge(len)));
+        assert!(read_message(&mut &headers[..], MAX_PAYLOAD).is_ok());
+    }
+
+    #[test]
+    fn peers_handshake_and_relay_messages() {
+        let listener_a = TcpListener::bind("127.0.0.1:0").unwrap();
+        let listener_b = TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr_a = listener_a.local_addr().unwrap();
+
+        let config_a = NodeConfig { peers: vec![], ..NodeConfig::default() };
+        let config_b = NodeConfig { peers: vec![addr_a.to_string()], ..NodeConfig::default() };
+        let (event_a_tx, event_a_rx) = mpsc::channel();
+        let (event_b_tx, event_b_rx) = mpsc::channel();
+        let net_a = start_net_interface(listener_a, &config_a, 3, event_a_tx);
+        let net_b = start_net_interface(listener_b, &config_b, 5, event_b_tx);
+
+        let timeout = Duration::from_secs(5);
+        let peer_of_a = match event_a_rx.recv_timeout(timeout).unwrap() {
+            NetEvent::PEER_READY(id, count) => { assert_eq!(count, 5); id },
+            _ => panic!("Expected peer ready")
+        };
+        let peer_of_b = match event_b_rx.recv_timeout(timeout).unwrap() {
+            NetEvent::PEER_READY(id, count) => { assert_eq!(count, 3); id },
+            _ => panic!("Expected peer ready")
+        };
+
+        net_a.send(NetCommand::SEND(peer_of_a, Message::INV(items()))).unwrap();
+        match event_b_rx.recv_timeout(timeout).unwrap() {
+            NetEvent::MESSAGE(id, Message::INV(i)) => { assert_eq!(id, peer_of_b); assert_eq!(i, items()) },
+            _ => panic!("Expected inv message")
+        }
+
+        net_b.send(NetCommand::BROADCAST(Message::GETDATA(items()), None)).unwrap();
+        match event_a_rx.recv_timeout(timeout).unwrap() {
+            NetEvent::MESSAGE(id, Message::GETDATA(i)) => { assert_eq!(id, peer_of_a); assert_eq!(i, items()) },
+            _ => panic!("Expected getdata message")
+        }
+
+        // Queued commands are all handled without waiting on peer events in between.
+        let started = Instant::now();
+        for n in 0..200 {
+            net_a.send(NetCommand::SEND(peer_of_a, Message::NOTFOUND(vec![InvItem { inv_type: InvType::Tx, hash: [n; 32] }]))).unwrap();
+        }
+        for n in 0..200 {
+            match event_b_rx.recv_timeout(timeout).unwrap() {
+                NetEvent::MESSAGE(_, Message::NOTFOUND(i)) => assert_eq!(i[0].hash, [n; 32]),
+                _ => panic!("Expected notfound message")
+            }
+        }
+        assert!(started.elapsed() < Duration::from_secs(2));
+
+        net_b.send(NetCommand::KILL).unwrap();
+        match event_a_rx.recv_timeout(timeout).unwrap() {
+            NetEvent::PEER_LOST(id) => assert_eq!(id, peer_of_a),
+            _ => panic!("Expected peer lost")
+        }
+        net_a.send(NetCommand::KILL).unwrap();
+    }
+}
@@ -0,0 +1,419 @@
+use crate::address::Address;
+use crate::block_struct::Block;
+use crate::json::{BlockJson, TxJson};
+use crate::log::{log, dlog};
+use crate::mempool::MempoolError;
+use crate::merkle::MerkleProof;
+use crate::miner::MinerStats;
+use crate::tx_struct::Tx;
+use crate::util::{to_hex, from_hex};
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::io::Read;
+use std::sync::mpsc;
+use std::thread;
+use std::time::Duration;
+use tiny_http::{Header, Method, Request, Response, Server};
+
+const REPLY_TIMEOUT: Duration = Duration::from_secs(5);     // Time allowed for the chain to answer a query
+const MAX_BODY: u64 = 64 * 1024;                            // Largest request body read
+
+// Queries the REST server asks the chain thread to answer.
+pub enum RestQuery {
+    BLOCK_BY_HEIGHT(u32),
+    BLOCK_BY_HASH([u8; 32]),
+    TIP,
+    TX([u8; 32]),
+    TX_PROOF([u8; 32]),
+    MEMPOOL,
+    BALANCE(Vec<u8>),
+    ADDRESS_BALANCE(Address),
+    SUBMIT_TX(Box<dyn Tx>),
+    MINER_STATS,
+    PAUSE_MINER,
+    RESUME_MINER,
+    SET_MINER_THREADS(u8)
+}
+
+// Reasons a submitted tx is not accepted.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SubmitError {
+    NotAccepting,               // The node does not accept txs
+    Rejected(MempoolError)      // The tx was refused by the mempool
+}
+
+// Answers from the chain thread to REST queries.
+pub enum RestReply {
+    BLOCK(Option<Block>),
+    TX(Option<Box<dyn Tx>>, Option<(u32, [u8; 32])>),   // Tx, and the height and hash of the block confirming it
+    TX_PROOF(Option<(Block, MerkleProof)>),             // Block confirming a tx, and the proof of the tx under its merkle root
+    MEMPOOL(Vec<Box<dyn Tx>>, usize),                   // Pending txs in mining order, and their size in bytes
+    BALANCE(Address, u64, [u8; 32]),                    // Address of the account, its balance, and the hash of its last confirmed tx
+    SUBMITTED(Result<[u8; 32], SubmitError>),
+    MINER(Option<MinerStats>)                           // Statistics of the mining server after any command, None if the node is not mining
+}
+
+// Body of a request to resize the mining worker pool.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct ThreadsRequest {
+    threads: u8
+}
+
+/**
+ * A query from the REST server, along with the
+ * channel the chain thread sends its reply on.
+ */
+pub struct RestRequest {
+    pub query: RestQuery,
+    pub reply: mpsc::Sender<RestReply>
+}
+
+// Start the REST server on the given localhost port.
+// Each HTTP request is turned into a query for the chain thread, and its reply into a JSON response.
+pub fn start_rest_server(port: u16, chain_tx: mpsc::Sender<RestRequest>) -> () {
+    let server = match Server::http(("127.0.0.1", port)) {
+        Err(why) => panic!("Failed to host REST API on port {}: {}", port, why),
+        Ok(server) => server
+    };
+    log(format!("REST API listening on 127.0.0.1:{}.", port));
+
+    thread::spawn(move || {
+        for request in server.incoming_requests() {
+            handle_request(request, &chain_tx);
+        }
+    });
+}
+
+// Answer a single HTTP request.
+fn handle_request(mut request: Request, chain_tx: &mpsc::Sender<RestRequest>) -> () {
+    let mut body: Vec<u8> = vec![];
+    let read = request.as_reader().take(MAX_BODY).read_to_end(&mut body);
+
+    let (status, json) = match read {
+        Err(why) => (400, error_json(&format!("Failed to read request body: {}", why))),
+        Ok(_) => match route(request.method(), request.url(), &body) {
+            Err((status, message)) => (status, error_json(&message)),
+            Ok(query) => match ask_chain(chain_tx, query) {
+                None => (503, error_json("Chain did not answer in time")),
+                Some(reply) => respond(reply)
+            }
+        }
+    };
+    dlog(module_path!(), "Answered REST request", &[
+        format!("Request: {} {}", request.method(), request.url()),
+        format!("Status: {}", status)
+    ]);
+
+    let content_type = Header::from_bytes(&b"Content-Type"[..], &b"application/json"[..]).unwrap();
+    let response = Response::from_string(json.to_string()).with_status_code(status).with_header(content_type);
+    let _ = request.respond(response);
+}
+
+// Send a query to the chain thread and wait for its reply.
+fn ask_chain(chain_tx: &mpsc::Sender<RestRequest>, query: RestQuery) -> Option<RestReply> {
+    let (reply_tx, reply_rx) = mpsc::channel();
+    chain_tx.send(RestRequest { query: query, reply: reply_tx }).ok()?;
+    return reply_rx.recv_timeout(REPLY_TIMEOUT).ok();
+}
+
+// Parse a request method, url and body into a chain query.
+// Returns the HTTP status and message to send back if the request is not valid.
+fn route(method: &Method, url: &str, body: &[u8]) -> Result<RestQuery, (u16, String)> {
+    let path = url.split('?').next().unwrap_or("");
+    let segments: Vec<&str> = path.split('/').filter(|segment| !segment.is_empty()).collect();
+
+    let query = match segments.as_slice() {
+        ["tip"] => RestQuery::TIP,
+        ["blocks", id] => parse_block_id(id)?,
+        ["txs", hash] => RestQuery::TX(parse_hash(hash)?),
+        ["txs", hash, "proof"] => RestQuery::TX_PROOF(parse_hash(hash)?),
+        ["mempool"] => RestQuery::MEMPOOL,
+        ["miner"] => RestQuery::MINER_STATS,
+        ["accounts", account, "balance"] => parse_account(account)?,
+        ["txs"] => {
+            require_post(method)?;
+            return parse_submission(body);
+        },
+        ["miner", "pause"] => {
+            require_post(method)?;
+            return Ok(RestQuery::PAUSE_MINER);
+        },
+        ["miner", "resume"] => {
+            require_post(method)?;
+            return Ok(RestQuery::RESUME_MINER);
+        },
+        ["miner", "threads"] => {
+            require_post(method)?;
+            return parse_threads(body);
+        },
+        _ => return Err((404, format!("No route for {}", path)))
+    };
+    if *method != Method::Get {
+        return Err((405, format!("Method {} not allowed", method)));
+    }
+
+    return Ok(query);
+}
+
+// Reject requests to POST only routes made with another method.
+fn require_post(method: &Method) -> Result<(), (u16, String)> {
+    if *method != Method::Post {
+        return Err((405, format!("Method {} not allowed", method)));
+    }
+    return Ok(());
+}
+
+// Parse a block height or hex encoded hash.
+fn parse_block_id(id: &str) -> Result<RestQuery, (u16, String)> {
+    if id.len() == 64 {
+        return Ok(RestQuery::BLOCK_BY_HASH(parse_hash(id)?));
+    }
+    return match id.parse::<u32>() {
+        Err(_) => Err((400, format!("Expected a block height or 64 character hash, found: {}", id))),
+        Ok(height) => Ok(RestQuery::BLOCK_BY_HEIGHT(height))
+    };
+}
+
+// Parse an account given by its address or hex encoded public key.
+fn parse_account(account: &str) -> Result<RestQuery, (u16, String)> {
+    return match (Address::parse(account), from_hex(account)) {
+        (Ok(address), _) => Ok(RestQuery::ADDRESS_BALANCE(address)),
+        (Err(_), Some(public_key)) => Ok(RestQuery::BALANCE(public_key)),
+        (Err(why), None) => Err((400, format!("Expected an address or hex public key, found: {} ({:?})", account, why)))
+    };
+}
+
+// Parse a hex encoded 32 byte hash.
+fn parse_hash(hex: &str) -> Result<[u8; 32], (u16, String)> {
+    return match from_hex(hex) {
+        Some(bytes) if bytes.len() == 32 => {
+            let mut hash = [0; 32];
+            hash.copy_from_slice(&bytes);
+            Ok(hash)
+        },
+        _ => Err((400, format!("Expected a 64 character hex hash, found: {}", hex)))
+    };
+}
+
+// Parse a tx submission body, holding a tx in the shared JSON schema.
+fn parse_submission(body: &[u8]) -> Result<RestQuery, (u16, String)> {
+    let submission: TxJson = serde_json::from_slice(body).map_err(|why| (400, format!("Invalid body: {}", why)))?;
+    let tx = submission.to_tx().map_err(|why| (400, format!("Invalid tx: {:?}", why)))?;
+
+    return Ok(RestQuery::SUBMIT_TX(tx));
+}
+
+// Parse a worker pool resize body, holding at least one thread.
+fn parse_threads(body: &[u8]) -> Result<RestQuery, (u16, String)> {
+    let request: ThreadsRequest = serde_json::from_slice(body).map_err(|why| (400, format!("Invalid body: {}", why)))?;
+    if request.threads == 0 {
+        return Err((400, "At least one mining thread is required".to_string()));
+    }
+
+    return Ok(RestQuery::SET_MINER_THREADS(request.threads));
+}
+
+// Convert a chain reply into an HTTP status and JSON body.
+fn respond(reply: RestReply) -> (u16, Value) {
+    return match reply {
+        RestReply::BLOCK(None) => (404, error_json("Block not found")),
+        RestReply::BLOCK(Some(block)) => (200, block_json(&block)),
+        RestReply::TX(None, _) => (404, error_json("Tx not found")),
+        RestReply::TX(Some(tx), None) => (200, json!({ "status": "pending", "tx": tx_json(tx.as_ref()) })),
+        RestReply::TX(Some(tx), Some((height, hash))) => (200, json!({
+            "status": "confirmed",
+            "block_height": height,
+            "block_hash": to_hex(&hash),
+            "tx": tx_json(tx.as_ref())
+        })),
+        RestReply::TX_PROOF(None) => (404, error_json("Tx not confirmed")),
+        RestReply::TX_PROOF(Some((block, proof))) => (200, proof_json(&block, &proof)),
+        RestReply::MEMPOOL(txs, size) => (200, json!({
+            "count": txs.len(),
+            "bytes": size,
+            "txs": txs.iter().map(|tx| tx_json(tx.as_ref())).collect::<Vec<Value>>()
+        })),
+        RestReply::BALANCE(address, balance, last_tx) => (200, json!({
+            "address": address.encode(),
+            "balance": balance,
+            "last_tx": to_hex(&last_tx)
+        })),
+        RestReply::SUBMITTED(Ok(hash)) => (202, json!({ "hash": to_hex(&hash) })),
+        RestReply::SUBMITTED(Err(SubmitError::NotAccepting)) => (403, error_json("Node does not accept txs")),
+        RestReply::SUBMITTED(Err(SubmitError::Rejected(why))) => (400, error_json(&format!("Tx rejected: {:?}", why))),
+        RestReply::MINER(None) => (404, error_json("Node is not mining")),
+        RestReply::MINER(Some(stats)) => (200, miner_json(&stats))
+    };
+}
+
+fn error_json(message: &str) -> Value {
+    return json!({ "error": message });
+}
+
+// Represent a block in the shared JSON schema.
+fn block_json(block: &Block) -> Value {
+    return serde_json::to_value(BlockJson::from_block(block)).expect("JSON schema types always serialise.");
+}
+
+// Represent mining statistics, with hashrates in hashes per second and times in seconds.
+fn miner_json(stats: &MinerStats) -> Value {
+    let secs = |time: Option<Duration>| time.map(|time| time.as_secs_f64());
+    return json!({
+        "paused": stats.paused,
+        "threads": stats.threads,
+        "hashrate": stats.hashrate,
+        "worker_hashrates": stats.worker_hashrates,
+        "total_hashes": stats.total_hashes,
+        "block_work": stats.block_work as f64,
+        "expected_block_time": secs(stats.expected_block_time),
+        "blocks_solved": stats.blocks_solved,
+        "last_block_time": secs(stats.last_block_time),
+        "average_block_time": secs(stats.average_block_time)
+    });
+}
+
+// Represent a tx inclusion proof, with the block header fields needed to check it.
+// Siblings are listed from the leaf up, each flagged if it sits on the left.
+fn proof_json(block: &Block, proof: &MerkleProof) -> Value {
+    return json!({
+        "block_hash": to_hex(&block.hash),
+        "block_height": block.get_height(),
+        "tx_merkle": to_hex(&block.tx_merkle),
+        "tx_count": block.tx_count,
+        "index": proof.index,
+        "siblings": proof.siblings.iter().map(|(hash, left)| json!({ "hash": to_hex(hash), "left": left })).collect::<Vec<Value>>()
+    });
+}
+
+// Represent a tx in the shared JSON schema.
+fn tx_json(tx: &dyn Tx) -> Value {
+    return serde_json::to_value(TxJson::from_tx(tx)).expect("JSON schema types always serialise.");
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::address::address_of;
+    use crate::tx_struct::FinancialTx;
+
+    fn route_get(url: &str) -> Result<RestQuery, (u16, String)> {
+        return route(&Method::Get, url, &[]);
+    }
+
+    #[test]
+    fn routes_queries() {
+        let hash = "ab".repeat(32);
+
+        assert!(matches!(route_get("/tip"), Ok(RestQuery::TIP)));
+        assert!(matches!(route_get("/blocks/12?verbose=1"), Ok(RestQuery::BLOCK_BY_HEIGHT(12))));
+        assert!(matches!(route_get(&format!("/blocks/{}", hash)), Ok(RestQuery::BLOCK_BY_HASH(h)) if h == [0xab; 32]));
+        assert!(matches!(route_get(&format!("/txs/{}/", hash)), Ok(RestQuery::TX(h)) if h == [0xab; 32]));
+        assert!(matches!(route_get(&format!("/txs/{}/proof", hash)), Ok(RestQuery::TX_PROOF(h)) if h == [0xab; 32]));
+        assert!(matches!(route_get("/mempool"), Ok(RestQuery::MEMPOOL)));
+        assert!(matches!(route_get("/miner"), Ok(RestQuery::MINER_STATS)));
+        assert!(matches!(route(&Method::Post, "/miner/pause", &[]), Ok(RestQuery::PAUSE_MINER)));
+        assert!(matches!(route(&Method::Post, "/miner/resume", &[]), Ok(RestQuery::RESUME_MINER)));
+        assert!(matches!(route(&Method::Post, "/miner/threads", b"{\"threads\": 3}"), Ok(RestQuery::SET_MINER_THREADS(3))));
+        assert!(matches!(route_get("/accounts/a1b2/balance"), Ok(RestQuery::BALANCE(key)) if key == vec![0xa1, 0xb2]));
+        let address = Address::from_public_key(&[0xa1, 0xb2]);
+        assert!(matches!(route_get(&format!("/accounts/{}/balance", address_of(&[0xa1, 0xb2]))), Ok(RestQuery::ADDRESS_BALANCE(a)) if a == address));
+    }
+
+    #[test]
+    fn rejects_bad_requests() {
+        assert!(matches!(route_get("/blocks/tip"), Err((400, _))));
+        assert!(matches!(route_get("/txs/abcd"), Err((400, _))));
+        assert!(matches!(route_get("/accounts/xyz/balance"), Err((400, _))));
+        assert!(matches!(route_get("/peers"), Err((404, _))));
+        assert!(matches!(route(&Method::Post, "/tip", &[]), Err((405, _))));
+        assert!(matches!(route_get("/txs"), Err((405, _))));
+        assert!(matches!(route_get("/miner/pause"), Err((405, _))));
+        assert!(matches!(route(&Method::Post, "/miner/threads", b"{\"threads\": 0}"), Err((400, _))));
+        assert!(matches!(route(&Method::Post, "/miner/threads", b"{\"threads\": 256}"), Err((400, _))));
+        assert!(matches!(route(&Method::Post, "/txs", b"{\"type\": \"data\"}"), Err((400, _))));
+        assert!(matches!(route(&Method::Post, "/txs", b"not json"), Err((400, _))));
+    }
+
+    #[test]
+    fn parses_submitted_tx() {
+        let mut tx = FinancialTx::new();
+        tx.quantity = 25u32.to_be_bytes();
+        tx.generate_hash();
+        let body = tx.to_json();
+
+        match route(&Method::Post, "/txs", body.as_bytes()) {
+            Ok(RestQuery::SUBMIT_TX(submitted)) => assert_eq!(submitted.get_hash(), tx.hash),
+            _ => panic!("Expected a tx submission")
+        }
+        let (status, json) = respond(RestReply::SUBMITTED(Ok(tx.hash)));
+        assert_eq!(status, 202);
+        assert_eq!(json["hash"], to_hex(&tx.hash));
+
+        // A tx version no block could be decoded with is refused before reaching the node.
+        tx.version = 7;
+        tx.generate_hash();
+        assert!(matches!(route(&Method::Post, "/txs", tx.to_json().as_bytes()), Err((400, _))));
+    }
+
+    #[test]
+    fn formats_blocks_and_txs() {
+        let mut tx = FinancialTx::new();
+        tx.quantity = 25u32.to_be_bytes();
+        tx.generate_hash();
+        let mut block = Block::new();
+        block.height = 7u32.to_be_bytes();
+        block.txs.push(Box::new(tx.clone()));
+        block.tx_count = 1;
+        block.generate_hash();
+
+        let (status, json) = respond(RestReply::BLOCK(Some(block.clone())));
+        assert_eq!(status, 200);
+        assert_eq!(json["height"], 7);
+        assert_eq!(json["hash"], to_hex(&block.hash));
+        assert_eq!(json["txs"][0]["type"], "financial");
+        assert_eq!(json["txs"][0]["quantity"], 25);
+
+        let (status, json) = respond(RestReply::TX(Some(Box::new(tx)), Some((7, block.hash))));
+        assert_eq!(status, 200);
+        assert_eq!(json["status"], "confirmed");
+        assert_eq!(json["block_height"], 7);
+        assert_eq!(respond(RestReply::BLOCK(None)).0, 404);
+
+        let proof = block.generate_tx_proof(0).unwrap();
+        let (status, json) = respond(RestReply::TX_PROOF(Some((block.clone(), proof))));
+        assert_eq!(status, 200);
+        assert_eq!(json["block_height"], 7);
+        assert_eq!(json["tx_count"], 1);
+        assert_eq!(json["index"], 0);
+        assert_eq!(json["siblings"].as_array().unwrap().len(), 0);
+        assert_eq!(respond(RestReply::TX_PROOF(None)).0, 404);
+    }
+
+    #[test]
+    fn formats_miner_stats() {
+        let stats = MinerStats {
+            paused: true,
+            threads: 2,
+            worker_hashrates: vec![1500.0, 1000.0],
+            hashrate: 2500.0,
+            total_hashes: 5000,
+            block_work: 1024,
+            expected_block_time: Some(Duration::from_millis(400)),
+            blocks_solved: 2,
+            last_block_time: Some(Duration::from_secs(2)),
+            average_block_time: None
+        };
+
+        let (status, json) = respond(RestReply::MINER(Some(stats)));
+        assert_eq!(status, 200);
+        assert_eq!(json["paused"], true);
+        assert_eq!(json["hashrate"], 2500.0);
+        assert_eq!(json["worker_hashrates"][1], 1000.0);
+        assert_eq!(json["expected_block_time"], 0.4);
+        assert_eq!(json["average_block_time"], Value::Null);
+        assert_eq!(respond(RestReply::MINER(None)).0, 404);
+    }
+}
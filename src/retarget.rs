@@ -0,0 +1,217 @@
+use crate::block_struct::{Block, BlockHeader};
+use crate::config::ChainConfig;
+use crate::target::{U256, MAX_BITS, target_from_bits, bits_from_target};
+use byteorder::{ByteOrder, BigEndian};
+
+
+pub const MEDIAN_TIME_SPAN: usize = 11;     // Number of recent blocks the median time past is taken over
+const MAX_SOLVETIME_FACTOR: u64 = 6;        // Longest solve time counted, as a multiple of the block time
+
+/**
+ * The timestamp and target bits of a block,
+ * the only fields retargeting depends on.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct BlockTime {
+    pub timestamp: u64,
+    pub bits: u32
+}
+
+impl BlockTime {
+    pub fn from_block(block: &Block) -> BlockTime {
+        return BlockTime {
+            timestamp: BigEndian::read_u64(&block.timestamp),
+            bits: block.bits
+        }
+    }
+
+    pub fn from_header(header: &BlockHeader) -> BlockTime {
+        return BlockTime {
+            timestamp: BigEndian::read_u64(&header.timestamp),
+            bits: header.bits
+        }
+    }
+}
+
+// Return the number of blocks, ending at the tip, needed to calculate the next target and median time past.
+pub fn ancestry_span(params: &ChainConfig) -> usize {
+    return (params.retarget_window as usize + 1).max(MEDIAN_TIME_SPAN);
+}
+
+// Calculate the target bits of the block following a window of recent blocks, oldest first and ending at the tip.
+// Uses a linearly weighted moving average (LWMA) of the last retarget_window solve times,
+// so recent blocks count the most, scaling the average target of those blocks by
+// how far the weighted solve time is from the block time.
+// Solve times are clamped between one second and MAX_SOLVETIME_FACTOR block times,
+// and each target moves at most a factor of two from the tip's target.
+pub fn next_bits(window: &[BlockTime], params: &ChainConfig) -> u32 {
+    let tip = match window.last() {
+        None => return MAX_BITS,
+        Some(tip) => tip
+    };
+    let count = (window.len() - 1).min(params.retarget_window as usize);
+    if count == 0 {
+        return tip.bits;
+    }
+
+    let max_target = target_from_bits(MAX_BITS).unwrap();
+    let blocks = &window[window.len() - count - 1..];
+    let block_time = params.block_time.max(1) as u64;
+
+    // Sum of solve times weighted 1 for the oldest up to count for the newest.
+    let mut weighted_time: u64 = 0;
+    for (weight, pair) in blocks.windows(2).enumerate() {
+        let solvetime = pair[1].timestamp.saturating_sub(pair[0].timestamp).clamp(1, MAX_SOLVETIME_FACTOR * block_time);
+        weighted_time += (weight as u64 + 1) * solvetime;
+    }
+    let expected_time = U256::from_u64(block_time * (count * (count + 1) / 2) as u64);
+
+    // next = (sum of targets / count) * weighted_time / expected_time.
+    // Targets are shifted down only as far as needed for the products to fit in 256 bits,
+    // so a steady chain keeps exactly the same target.
+    let targets: Vec<U256> = blocks[1..].iter().map(|block| target_from_bits(block.bits).unwrap_or(max_target)).collect();
+    let headroom = (64 - weighted_time.leading_zeros()) + (64 - (count as u64).leading_zeros());
+    let shift = (targets.iter().map(|target| target.bits()).max().unwrap_or(0) + headroom).saturating_sub(256);
+    let mut sum = U256::ZERO;
+    for target in targets.iter() {
+        sum = sum.overflowing_add(&target.shr(shift)).0;
+    }
+    let next = sum.checked_mul_u64(weighted_time)
+        .and_then(|scaled| scaled.checked_div(&expected_time.checked_mul_u64(count as u64)?))
+        .map(|next| next.shl(shift))
+        .unwrap_or(max_target);
+
+    let prev = target_from_bits(tip.bits).unwrap_or(max_target);
+    let next = next.clamp(prev.shr(1).max(U256::ONE), prev.shl(1)).min(max_target);
+
+    return bits_from_target(&next);
+}
+
+// Return the median timestamp of the last MEDIAN_TIME_SPAN blocks of a window, oldest first.
+// A new block must have a timestamp after this median, so a single miner cannot move time backwards.
+pub fn median_time_past(window: &[BlockTime]) -> u64 {
+    let start = window.len().saturating_sub(MEDIAN_TIME_SPAN);
+    let mut timestamps: Vec<u64> = window[start..].iter().map(|block| block.timestamp).collect();
+    if timestamps.is_empty() {
+        return 0;
+    }
+    timestamps.sort_unstable();
+
+    return timestamps[timestamps.len() / 2];
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::target::{block_work, bits_from_diff};
+    use crate::simulator::solve_time;
+    use rand::{Rng, SeedableRng};
+    use rand::rngs::StdRng;
+
+    const PREV_BITS: u32 = 0x1d00_ffff;
+
+    fn params() -> ChainConfig {
+        return ChainConfig { block_time: 120, retarget_window: 45, ..ChainConfig::default() }
+    }
+
+    // Build a window of blocks at the given bits, each solved in the given number of seconds.
+    fn steady_window(len: usize, bits: u32, solvetime: u64) -> Vec<BlockTime> {
+        return (0..len as u64).map(|i| BlockTime { timestamp: 1_000_000 + i * solvetime, bits: bits }).collect();
+    }
+
+    // Mine blocks at a hashrate changing in phases of (blocks, hashes per second),
+    // drawing each solve time with the simulator's model.
+    // Returns the window of every block mined, starting from the genesis target.
+    fn simulate(phases: &[(usize, f64)], genesis_bits: u32, params: &ChainConfig, seed: u64) -> Vec<BlockTime> {
+        let mut rng = StdRng::seed_from_u64(seed);
+        let mut blocks = vec![ BlockTime { timestamp: 0, bits: genesis_bits } ];
+        let mut time: f64 = 0.0;
+        for (count, hashrate) in phases.iter() {
+            for _ in 0..*count {
+                let start = blocks.len().saturating_sub(ancestry_span(params));
+                let bits = next_bits(&blocks[start..], params);
+                time += solve_time(block_work(bits) as f64, *hashrate, rng.gen::<f64>());
+                blocks.push(BlockTime { timestamp: time as u64, bits: bits });
+            }
+        }
+
+        return blocks;
+    }
+
+    // Mean solve time of a run of simulated blocks.
+    fn mean_solvetime(blocks: &[BlockTime]) -> f64 {
+        return (blocks[blocks.len() - 1].timestamp - blocks[0].timestamp) as f64 / (blocks.len() - 1) as f64;
+    }
+
+    #[test]
+    fn keeps_target_at_block_time() {
+        assert_eq!(next_bits(&steady_window(60, PREV_BITS, 120), &params()), PREV_BITS);
+        assert_eq!(next_bits(&steady_window(3, PREV_BITS, 120), &params()), PREV_BITS);
+        assert_eq!(next_bits(&steady_window(1, PREV_BITS, 1), &params()), PREV_BITS);
+        assert_eq!(next_bits(&steady_window(60, MAX_BITS, 120), &params()), MAX_BITS);
+        assert_eq!(next_bits(&[], &params()), MAX_BITS);
+    }
+
+    #[test]
+    fn scales_target_with_solve_time() {
+        let prev = target_from_bits(PREV_BITS).unwrap();
+
+        let fast = next_bits(&steady_window(60, PREV_BITS, 90), &params());
+        assert_eq!(fast, bits_from_target(&prev.checked_mul_u64(3).unwrap().shr(2)));
+        let slow = next_bits(&steady_window(60, PREV_BITS, 150), &params());
+        assert_eq!(slow, bits_from_target(&prev.checked_mul_u64(5).unwrap().shr(2)));
+    }
+
+    #[test]
+    fn clamps_solve_times_and_target_changes() {
+        let prev = target_from_bits(PREV_BITS).unwrap();
+
+        // Timestamps going backwards count as one second solve times.
+        let mut window = steady_window(60, PREV_BITS, 120);
+        window.last_mut().unwrap().timestamp -= 1000;
+        assert!(target_from_bits(next_bits(&window, &params())).unwrap() < prev);
+
+        // A single huge gap counts as six block times, and the target at most doubles.
+        let mut window = steady_window(60, PREV_BITS, 120);
+        window.last_mut().unwrap().timestamp += 1_000_000;
+        let capped = target_from_bits(next_bits(&window, &params())).unwrap();
+        assert!(capped > prev && capped < prev.shl(1));
+        assert_eq!(next_bits(&steady_window(60, PREV_BITS, 100_000), &params()), bits_from_target(&prev.shl(1)));
+        assert_eq!(next_bits(&steady_window(60, PREV_BITS, 0), &params()), bits_from_target(&prev.shr(1)));
+    }
+
+    #[test]
+    fn median_time_past_of_recent_blocks() {
+        let times = |timestamps: &[u64]| timestamps.iter().map(|timestamp| BlockTime { timestamp: *timestamp, bits: PREV_BITS }).collect::<Vec<BlockTime>>();
+
+        assert_eq!(median_time_past(&[]), 0);
+        assert_eq!(median_time_past(&times(&[5])), 5);
+        assert_eq!(median_time_past(&times(&[9, 1, 5])), 5);
+        assert_eq!(median_time_past(&times(&[100, 1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11])), 6);
+        assert_eq!(median_time_past(&steady_window(60, PREV_BITS, 10)), 1_000_000 + 54 * 10);
+    }
+
+    #[test]
+    fn simulation_tracks_changing_hashrate() {
+        let params = params();
+        let phases = [(400, 1_000.0), (400, 10_000.0), (400, 500.0)];
+        let blocks = simulate(&phases, bits_from_diff(10), &params, 7);
+        assert_eq!(blocks.len(), 1201);
+
+        // Once settled, each phase averages close to the block time.
+        for phase in 0..phases.len() {
+            let settled = &blocks[phase * 400 + 200..=(phase + 1) * 400];
+            let mean = mean_solvetime(settled);
+            assert!((96.0..144.0).contains(&mean), "phase {} averaged {:.1}s per block", phase, mean);
+        }
+
+        // The work per block follows the hashrate.
+        let work = |height: usize| block_work(blocks[height].bits) as f64;
+        let ratio = work(800) / work(400);
+        assert!((5.0..20.0).contains(&ratio), "work grew by {:.2}x for a 10x hashrate", ratio);
+        let ratio = work(1200) / work(800);
+        assert!((0.025..0.1).contains(&ratio), "work fell by {:.3}x for a 0.05x hashrate", ratio);
+    }
+}
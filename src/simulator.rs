@@ -0,0 +1,480 @@
+use crate::config::ChainConfig;
+use crate::retarget::{self, BlockTime, ancestry_span, median_time_past};
+use crate::target::{U256, MAX_BITS, target_from_bits, bits_from_target, bits_from_diff, block_work};
+use crate::validation::MAX_FUTURE_TIME;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use serde::Deserialize;
+use std::fs::read_to_string;
+
+const CSV_HEADER: &str = "height,time,interval,timestamp,solvetime,bits,work,hashrate,miner";
+
+// Reasons a hashrate schedule cannot be loaded.
+#[derive(Debug, Clone, PartialEq)]
+pub enum ScheduleError {
+    Io(String),                     // Schedule file could not be read
+    Parse(String),                  // Schedule file is not valid TOML for the schema
+    Invalid(&'static str, String)   // Value is out of range (field, reason)
+}
+
+/**
+ * A hashrate schedule to simulate mining against.
+ * Step changes in hashrate are modelled as miners joining and leaving,
+ * and timestamp manipulation as miners offsetting their block timestamps.
+ */
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Schedule {
+    pub blocks: usize,
+    pub seed: u64,
+    pub miner: Vec<MinerSchedule>
+}
+
+// A miner taking part in a simulation, active from join up to leave seconds into it.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MinerSchedule {
+    pub name: String,
+    pub hashrate: f64,              // Hashes per second
+    pub join: u64,
+    pub leave: Option<u64>,
+    pub timestamp_offset: i64       // Seconds added to the timestamps of blocks it mines, negative to backdate
+}
+
+/**
+ * A block mined by the simulator.
+ * The time is when it was found, while the timestamp is what its
+ * miner recorded, kept within the consensus timestamp rules.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct SimBlock {
+    pub height: u32,
+    pub time: f64,
+    pub timestamp: u64,
+    pub bits: u32,
+    pub hashrate: f64,              // Total hashrate active when the block was found
+    pub miner: String
+}
+
+// A rule for setting the target of the next block from a window of recent blocks, oldest first.
+pub trait RetargetAlgorithm {
+    fn name(&self) -> &'static str;
+    fn next_bits(&self, window: &[BlockTime], params: &ChainConfig) -> u32;
+}
+
+// The consensus rule, a linearly weighted moving average, see retarget.rs.
+pub struct Lwma;
+
+// The original rule, adding a leading zero bit when the last block took under 75% of the block time
+// and removing one when it took over 125%.
+pub struct Step;
+
+// No retargeting, every block keeps the genesis target.
+pub struct Fixed;
+
+impl RetargetAlgorithm for Lwma {
+    fn name(&self) -> &'static str {
+        return "lwma";
+    }
+
+    fn next_bits(&self, window: &[BlockTime], params: &ChainConfig) -> u32 {
+        return retarget::next_bits(window, params);
+    }
+}
+
+impl RetargetAlgorithm for Step {
+    fn name(&self) -> &'static str {
+        return "step";
+    }
+
+    fn next_bits(&self, window: &[BlockTime], params: &ChainConfig) -> u32 {
+        let (prev, tip) = match window {
+            [.., prev, tip] => (prev, tip),
+            [tip] => return tip.bits,
+            [] => return MAX_BITS
+        };
+        let max_target = target_from_bits(MAX_BITS).unwrap();
+        let target = target_from_bits(tip.bits).unwrap_or(max_target);
+        let solvetime = tip.timestamp.saturating_sub(prev.timestamp);
+        let block_time = params.block_time as u64;
+
+        let next = if solvetime * 4 < block_time * 3 {
+            target.shr(1).max(U256::ONE)
+        }
+        else if solvetime * 4 > block_time * 5 {
+            target.shl(1).min(max_target)
+        }
+        else {
+            target
+        };
+
+        return bits_from_target(&next);
+    }
+}
+
+impl RetargetAlgorithm for Fixed {
+    fn name(&self) -> &'static str {
+        return "fixed";
+    }
+
+    fn next_bits(&self, window: &[BlockTime], _params: &ChainConfig) -> u32 {
+        return match window.last() {
+            None => MAX_BITS,
+            Some(tip) => tip.bits
+        };
+    }
+}
+
+// Names of the algorithms accepted by algorithm_by_name.
+pub const ALGORITHMS: [&str; 3] = ["lwma", "step", "fixed"];
+
+// Look up a retarget algorithm by name.
+pub fn algorithm_by_name(name: &str) -> Option<Box<dyn RetargetAlgorithm>> {
+    return match name {
+        "lwma" => Some(Box::new(Lwma)),
+        "step" => Some(Box::new(Step)),
+        "fixed" => Some(Box::new(Fixed)),
+        _ => None
+    };
+}
+
+impl Default for Schedule {
+    fn default() -> Schedule {
+        return Schedule {
+            blocks: 1000,
+            seed: 0,
+            miner: vec![]
+        }
+    }
+}
+
+impl Default for MinerSchedule {
+    fn default() -> MinerSchedule {
+        return MinerSchedule {
+            name: "miner".to_string(),
+            hashrate: 0.0,
+            join: 0,
+            leave: None,
+            timestamp_offset: 0
+        }
+    }
+}
+
+impl MinerSchedule {
+    // Check if the miner is hashing at the given number of seconds into the simulation.
+    fn is_active(&self, time: f64) -> bool {
+        return self.join as f64 <= time && self.leave.is_none_or(|leave| time < leave as f64);
+    }
+}
+
+impl Schedule {
+    pub fn from_file(path: &str) -> Result<Schedule, ScheduleError> {
+        let contents = read_to_string(path).map_err(|why| ScheduleError::Io(format!("{}: {}", path, why)))?;
+        return Schedule::from_toml(&contents);
+    }
+
+    pub fn from_toml(contents: &str) -> Result<Schedule, ScheduleError> {
+        return toml::from_str(contents).map_err(|why| ScheduleError::Parse(why.to_string()));
+    }
+
+    // Check the schedule can be simulated.
+    // Hashrate must remain once every miner has joined or left, or mining would stall forever.
+    pub fn validate(&self) -> Result<(), ScheduleError> {
+        if self.blocks == 0 {
+            return Err(ScheduleError::Invalid("blocks", "at least one block is required".to_string()));
+        }
+        for miner in self.miner.iter() {
+            if !miner.hashrate.is_finite() || miner.hashrate < 0.0 {
+                return Err(ScheduleError::Invalid("miner.hashrate", format!("{} must have a non-negative hashrate", miner.name)));
+            }
+            if miner.leave.is_some_and(|leave| leave <= miner.join) {
+                return Err(ScheduleError::Invalid("miner.leave", format!("{} must leave after joining", miner.name)));
+            }
+        }
+        let last_event = self.events().last().copied().unwrap_or(0.0);
+        if self.hashrate_at(last_event) <= 0.0 {
+            return Err(ScheduleError::Invalid("miner", "some hashrate must remain after the last miner joins or leaves".to_string()));
+        }
+        return Ok(());
+    }
+
+    // Total hashrate of the miners active at the given time.
+    pub fn hashrate_at(&self, time: f64) -> f64 {
+        return self.miner.iter().filter(|miner| miner.is_active(time)).map(|miner| miner.hashrate).sum();
+    }
+
+    // Times the hashrate may change at, in ascending order.
+    fn events(&self) -> Vec<f64> {
+        let mut events: Vec<f64> = self.miner.iter()
+            .flat_map(|miner| std::iter::once(miner.join).chain(miner.leave))
+            .map(|time| time as f64)
+            .collect();
+        events.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        events.dedup();
+        return events;
+    }
+
+    // Pick the miner of a block in proportion to hashrate, given a uniform draw in [0, 1).
+    fn pick_miner(&self, time: f64, draw: f64) -> &MinerSchedule {
+        let active: Vec<&MinerSchedule> = self.miner.iter().filter(|miner| miner.is_active(time) && miner.hashrate > 0.0).collect();
+        let mut remaining = draw * active.iter().map(|miner| miner.hashrate).sum::<f64>();
+        for miner in active.iter() {
+            if remaining < miner.hashrate {
+                return miner;
+            }
+            remaining -= miner.hashrate;
+        }
+        return active[active.len() - 1];
+    }
+}
+
+// Seconds taken to find a block of the given work at a hashrate, given a uniform draw in [0, 1).
+// Solve times follow the exponential distribution with a mean of work over hashrate,
+// and a block is never found without hashrate.
+pub fn solve_time(work: f64, hashrate: f64, draw: f64) -> f64 {
+    if hashrate <= 0.0 {
+        return f64::INFINITY;
+    }
+    return -(work / hashrate) * (1.0 - draw).ln();
+}
+
+// Mine the scheduled number of blocks after a genesis block at time zero, retargeting with the given algorithm.
+// Each solve time is drawn from the exponential distribution of the block's work over the active hashrate,
+// and redrawn whenever the hashrate changes, which the distribution being memoryless allows.
+// Timestamps are clamped after the median time past and to at most MAX_FUTURE_TIME past the true time.
+pub fn simulate(schedule: &Schedule, algorithm: &dyn RetargetAlgorithm, params: &ChainConfig) -> Result<Vec<SimBlock>, ScheduleError> {
+    schedule.validate()?;
+
+    let mut rng = StdRng::seed_from_u64(schedule.seed);
+    let events = schedule.events();
+    let span = ancestry_span(params);
+    let mut window = vec![ BlockTime { timestamp: 0, bits: bits_from_diff(params.genesis_diff) } ];
+    let mut blocks = vec![ SimBlock {
+        height: 0,
+        time: 0.0,
+        timestamp: 0,
+        bits: window[0].bits,
+        hashrate: schedule.hashrate_at(0.0),
+        miner: "genesis".to_string()
+    } ];
+    let mut time: f64 = 0.0;
+
+    for height in 1..=schedule.blocks as u32 {
+        let bits = algorithm.next_bits(&window, params);
+        let work = block_work(bits) as f64;
+
+        loop {
+            let next_event = events.iter().find(|event| **event > time).copied();
+            let solvetime = solve_time(work, schedule.hashrate_at(time), rng.gen::<f64>());
+            match next_event {
+                Some(event) if time + solvetime >= event => time = event,
+                _ => {
+                    time += solvetime;
+                    break;
+                }
+            }
+        }
+
+        let miner = schedule.pick_miner(time, rng.gen::<f64>());
+        let claimed = (time as i64).saturating_add(miner.timestamp_offset).max(0) as u64;
+        let timestamp = claimed.min(time as u64 + MAX_FUTURE_TIME).max(median_time_past(&window) + 1);
+
+        window.push(BlockTime { timestamp: timestamp, bits: bits });
+        if window.len() > span {
+            window.remove(0);
+        }
+        blocks.push(SimBlock {
+            height: height,
+            time: time,
+            timestamp: timestamp,
+            bits: bits,
+            hashrate: schedule.hashrate_at(time),
+            miner: miner.name.clone()
+        });
+    }
+
+    return Ok(blocks);
+}
+
+// Quote a CSV field holding a comma, quote or line break, doubling any quotes inside it.
+fn csv_field(text: &str) -> String {
+    if !text.contains(&[',', '"', '\n', '\r'][..]) {
+        return text.to_string();
+    }
+    return format!("\"{}\"", text.replace('"', "\"\""));
+}
+
+// Format simulated blocks as CSV, one row per block after a header.
+// The interval is the true time since the previous block and the solvetime the difference in timestamps.
+pub fn to_csv(blocks: &[SimBlock]) -> String {
+    let mut csv = format!("{}\n", CSV_HEADER);
+    for (i, block) in blocks.iter().enumerate() {
+        let (interval, solvetime) = match i {
+            0 => (0.0, 0),
+            _ => (block.time - blocks[i - 1].time, block.timestamp as i64 - blocks[i - 1].timestamp as i64)
+        };
+        csv.push_str(&format!("{},{:.3},{:.3},{},{},{:#010x},{},{},{}\n",
+            block.height, block.time, interval, block.timestamp, solvetime, block.bits, block_work(block.bits), block.hashrate, csv_field(&block.miner)));
+    }
+    return csv;
+}
+
+// Return the mean and standard deviation of the true block intervals, in seconds.
+pub fn interval_stats(blocks: &[SimBlock]) -> (f64, f64) {
+    if blocks.len() < 2 {
+        return (0.0, 0.0);
+    }
+    let intervals: Vec<f64> = blocks.windows(2).map(|pair| pair[1].time - pair[0].time).collect();
+    let mean = intervals.iter().sum::<f64>() / intervals.len() as f64;
+    let variance = intervals.iter().map(|interval| (interval - mean).powi(2)).sum::<f64>() / intervals.len() as f64;
+
+    return (mean, variance.sqrt());
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn params() -> ChainConfig {
+        return ChainConfig { genesis_diff: 10, block_time: 120, retarget_window: 45, ..ChainConfig::default() }
+    }
+
+    fn miner(name: &str, hashrate: f64, join: u64, leave: Option<u64>) -> MinerSchedule {
+        return MinerSchedule { name: name.to_string(), hashrate: hashrate, join: join, leave: leave, timestamp_offset: 0 }
+    }
+
+    fn schedule(blocks: usize, miners: Vec<MinerSchedule>) -> Schedule {
+        return Schedule { blocks: blocks, seed: 3, miner: miners }
+    }
+
+    #[test]
+    fn parses_schedule_file() {
+        let schedule = Schedule::from_toml("
+            blocks = 500
+            seed = 9
+
+            [[miner]]
+            name = \"base\"
+            hashrate = 1000.0
+
+            [[miner]]
+            name = \"pool\"
+            hashrate = 5000.0
+            join = 3600
+            leave = 7200
+            timestamp_offset = -600
+        ").unwrap();
+
+        assert_eq!(schedule.blocks, 500);
+        assert_eq!(schedule.seed, 9);
+        assert_eq!(schedule.miner[1].leave, Some(7200));
+        assert_eq!(schedule.miner[1].timestamp_offset, -600);
+        assert_eq!(schedule.hashrate_at(0.0), 1000.0);
+        assert_eq!(schedule.hashrate_at(3600.0), 6000.0);
+        assert_eq!(schedule.hashrate_at(7200.0), 1000.0);
+        assert!(matches!(Schedule::from_toml("[[miner]]\nhashrat = 10.0"), Err(ScheduleError::Parse(_))));
+    }
+
+    #[test]
+    fn rejects_unsimulatable_schedules() {
+        assert!(matches!(schedule(10, vec![]).validate(), Err(ScheduleError::Invalid("miner", _))));
+        assert!(matches!(schedule(10, vec![miner("a", 10.0, 0, Some(50))]).validate(), Err(ScheduleError::Invalid("miner", _))));
+        assert!(matches!(schedule(10, vec![miner("a", -1.0, 0, None)]).validate(), Err(ScheduleError::Invalid("miner.hashrate", _))));
+        assert!(matches!(schedule(10, vec![miner("a", 10.0, 50, Some(50))]).validate(), Err(ScheduleError::Invalid("miner.leave", _))));
+        assert!(matches!(schedule(0, vec![miner("a", 10.0, 0, None)]).validate(), Err(ScheduleError::Invalid("blocks", _))));
+        assert_eq!(schedule(10, vec![miner("a", 10.0, 100, None)]).validate(), Ok(()));
+    }
+
+    #[test]
+    fn runs_are_deterministic_per_seed() {
+        let run = |seed: u64| {
+            let schedule = Schedule { seed: seed, ..schedule(100, vec![miner("a", 1000.0, 0, None), miner("b", 3000.0, 0, None)]) };
+            return simulate(&schedule, &Lwma, &params()).unwrap();
+        };
+
+        let blocks = run(1);
+        assert_eq!(blocks.len(), 101);
+        assert_eq!(blocks, run(1));
+        assert_ne!(blocks, run(2));
+        assert!(blocks.iter().any(|block| block.miner == "a") && blocks.iter().any(|block| block.miner == "b"));
+    }
+
+    #[test]
+    fn waits_for_miners_to_join() {
+        let blocks = simulate(&schedule(5, vec![miner("late", 1_000_000.0, 5000, None)]), &Fixed, &params()).unwrap();
+
+        assert!(blocks[1].time >= 5000.0);
+        assert!(blocks[1..].iter().all(|block| block.miner == "late" && block.bits == bits_from_diff(10)));
+    }
+
+    #[test]
+    fn lwma_settles_after_hashrate_steps() {
+        let miners = vec![miner("base", 1000.0, 0, None), miner("pool", 9000.0, 200_000, Some(400_000))];
+        let blocks = simulate(&schedule(4500, miners), &Lwma, &params()).unwrap();
+
+        // Settled stretches inside each phase average close to the block time.
+        let phase = |start: f64, end: f64| blocks.iter().filter(|block| block.time >= start && block.time < end).cloned().collect::<Vec<SimBlock>>();
+        for (start, end) in [(50_000.0, 200_000.0), (250_000.0, 400_000.0), (450_000.0, f64::INFINITY)].iter() {
+            let (mean, _) = interval_stats(&phase(*start, *end));
+            assert!((96.0..144.0).contains(&mean), "phase from {}s averaged {:.1}s per block", start, mean);
+        }
+    }
+
+    #[test]
+    fn timestamps_stay_within_consensus_rules() {
+        let backdating = MinerSchedule { timestamp_offset: -1_000_000, ..miner("backdater", 1000.0, 0, None) };
+        let blocks = simulate(&schedule(30, vec![backdating]), &Lwma, &params()).unwrap();
+        let window: Vec<BlockTime> = blocks.iter().map(|block| BlockTime { timestamp: block.timestamp, bits: block.bits }).collect();
+        for height in 1..blocks.len() {
+            let start = height.saturating_sub(11);
+            assert_eq!(blocks[height].timestamp, median_time_past(&window[start..height]) + 1);
+        }
+
+        // Starting near the settled target keeps blocks seconds apart, so the median stays behind the limit.
+        let forward = MinerSchedule { timestamp_offset: 1_000_000, ..miner("forward", 1000.0, 0, None) };
+        let params = ChainConfig { genesis_diff: 17, ..params() };
+        let blocks = simulate(&schedule(30, vec![forward]), &Lwma, &params).unwrap();
+        assert!(blocks[1..].iter().all(|block| block.timestamp == block.time as u64 + MAX_FUTURE_TIME));
+    }
+
+    #[test]
+    fn step_moves_one_bit_outside_band() {
+        let block = |timestamp: u64| BlockTime { timestamp: timestamp, bits: bits_from_diff(10) };
+        let target = target_from_bits(bits_from_diff(10)).unwrap();
+
+        assert_eq!(Step.next_bits(&[block(0), block(120)], &params()), bits_from_diff(10));
+        assert_eq!(Step.next_bits(&[block(0), block(80)], &params()), bits_from_target(&target.shr(1)));
+        assert_eq!(Step.next_bits(&[block(0), block(160)], &params()), bits_from_target(&target.shl(1)));
+        assert_eq!(Fixed.next_bits(&[block(0), block(1)], &params()), bits_from_diff(10));
+        assert_eq!(ALGORITHMS.iter().map(|name| algorithm_by_name(name).unwrap().name()).collect::<Vec<&str>>(), ALGORITHMS.to_vec());
+        assert!(algorithm_by_name("sha").is_none());
+    }
+
+    #[test]
+    fn writes_csv_rows() {
+        let blocks = simulate(&schedule(3, vec![miner("a", 1000.0, 0, None)]), &Fixed, &params()).unwrap();
+        let csv = to_csv(&blocks);
+        let lines: Vec<&str> = csv.lines().collect();
+
+        assert_eq!(lines.len(), 5);
+        assert_eq!(lines[0], CSV_HEADER);
+        assert_eq!(lines[1], "0,0.000,0.000,0,0,0x1f3fffff,1024,1000,genesis");
+        let row: Vec<&str> = lines[2].split(',').collect();
+        assert_eq!(row.len(), 9);
+        assert_eq!(row[0], "1");
+        assert_eq!(row[8], "a");
+    }
+
+    #[test]
+    fn quotes_csv_miner_names() {
+        let blocks = simulate(&schedule(1, vec![miner("pool, \"east\"", 1000.0, 0, None)]), &Fixed, &params()).unwrap();
+        let csv = to_csv(&blocks);
+        let row = csv.lines().nth(2).unwrap();
+
+        assert!(row.ends_with(",\"pool, \"\"east\"\"\""), "{}", row);
+        assert_eq!(csv_field("plain"), "plain");
+        assert_eq!(csv_field("two\nlines"), "\"two\nlines\"");
+    }
+}
@@ -0,0 +1,459 @@
+use crate::block_struct::Block;
+use byteorder::{ByteOrder, BigEndian};
+use std::collections::HashMap;
+use std::fs::{File, OpenOptions, create_dir_all};
+use std::io::{Read, Seek, SeekFrom, Write, Error};
+use std::path::{Path, PathBuf};
+
+// Record kind tags used within the block store file.
+const RECORD_BLOCK: u8 = 0x01;
+const RECORD_DISCONNECT: u8 = 0x02;     // Removes the tip block, payload is its hash
+
+// Size of a record header: kind (1 byte) and payload length (4 bytes).
+const RECORD_HEADER: u64 = 5;
+
+// Errors raised by a block store.
+// The fields are only read through Debug when an error is logged.
+#[derive(Debug)]
+#[allow(dead_code)]
+pub enum StoreError {
+    Io(Error),                  // Underlying file system error
+    Corrupt(u64),               // Unreadable record at the given byte offset
+    InvalidHeight(u32, u32),    // Block height (expected, found)
+    Duplicate([u8; 32]),        // Block hash already stored
+    Empty                       // No block to remove
+}
+
+impl From<Error> for StoreError {
+    fn from(err: Error) -> StoreError {
+        return StoreError::Io(err);
+    }
+}
+
+// Generalised interface for block storage backends.
+// Blocks are appended in height order and can be fetched by hash or height.
+pub trait BlockStore {
+    // Append a block on top of the current tip.
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError>;
+
+    // Remove the tip block from the chain, returning it.
+    // Used to disconnect blocks when switching to a competing branch.
+    fn pop_block(&mut self) -> Result<Block, StoreError>;
+
+    // Fetch a stored block by its hash.
+    fn get_by_hash(&self, hash: &[u8; 32]) -> Option<Block>;
+
+    // Check if a block is stored, from the index without reading the block.
+    fn contains(&self, hash: &[u8; 32]) -> bool;
+
+    // Fetch a stored block by its height.
+    fn get_by_height(&self, height: u32) -> Option<Block>;
+
+    // Return the height of the tip, or None if the store is empty.
+    fn tip_height(&self) -> Option<u32>;
+
+    // Return the height of the block holding a tx.
+    fn get_tx_height(&self, hash: &[u8; 32]) -> Option<u32>;
+
+    // Fetch the block at the tip of the stored chain.
+    fn get_tip(&self) -> Option<Block> {
+        return self.tip_height().and_then(|height| self.get_by_height(height));
+    }
+}
+
+// Check a block can be appended to a store of the given length.
+fn check_next(block: &Block, len: usize, index: &HashMap<[u8; 32], u32>) -> Result<(), StoreError> {
+    if block.get_height() as usize != len {
+        return Err(StoreError::InvalidHeight(len as u32, block.get_height()));
+    }
+    if index.contains_key(&block.hash) {
+        return Err(StoreError::Duplicate(block.hash));
+    }
+
+    return Ok(());
+}
+
+// Add the txs of a block to a tx index.
+fn index_txs(txs: &mut HashMap<[u8; 32], u32>, block: &Block) -> () {
+    for tx in block.txs.iter() {
+        txs.insert(tx.get_hash(), block.get_height());
+    }
+}
+
+
+/**
+ * In-memory block store, used by tests.
+ * Holds block binaries in a vector, nothing survives a restart.
+ */
+#[cfg(test)]
+pub struct MemoryStore {
+    blocks: Vec<Vec<u8>>,
+    index: HashMap<[u8; 32], u32>,
+    txs: HashMap<[u8; 32], u32>
+}
+
+#[cfg(test)]
+impl MemoryStore {
+    pub fn new() -> MemoryStore {
+        return MemoryStore {
+            blocks: vec![],
+            index: HashMap::new(),
+            txs: HashMap::new()
+        }
+    }
+}
+
+#[cfg(test)]
+impl BlockStore for MemoryStore {
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError> {
+        check_next(block, self.blocks.len(), &self.index)?;
+        self.index.insert(block.hash, block.get_height());
+        index_txs(&mut self.txs, block);
+        self.blocks.push(block.to_bin());
+
+        return Ok(());
+    }
+
+    fn pop_block(&mut self) -> Result<Block, StoreError> {
+        let block = self.get_tip().ok_or(StoreError::Empty)?;
+        self.blocks.pop();
+        self.index.remove(&block.hash);
+        self.txs.retain(|_, height| *height != block.get_height());
+
+        return Ok(block);
+    }
+
+    fn get_by_hash(&self, hash: &[u8; 32]) -> Option<Block> {
+        let height = *self.index.get(hash)?;
+        return self.get_by_height(height);
+    }
+
+    fn contains(&self, hash: &[u8; 32]) -> bool {
+        return self.index.contains_key(hash);
+    }
+
+    fn get_by_height(&self, height: u32) -> Option<Block> {
+        let bin = self.blocks.get(height as usize)?;
+        return Block::from_bin(bin).ok();
+    }
+
+    fn tip_height(&self) -> Option<u32> {
+        return (self.blocks.len() as u32).checked_sub(1);
+    }
+
+    fn get_tx_height(&self, hash: &[u8; 32]) -> Option<u32> {
+        return self.txs.get(hash).copied();
+    }
+}
+
+
+/**
+ * Append-only file backed block store.
+ * Each record is a kind byte, a u32 payload length and a payload, either
+ * a block binary appended to the chain or the hash of a disconnected tip.
+ * The block and tx indexes are rebuilt from the file on open.
+ */
+pub struct FileStore {
+    path: PathBuf,
+    file: File,
+    offsets: Vec<(u64, u32)>,       // (payload offset, payload length) by height
+    index: HashMap<[u8; 32], u32>,
+    txs: HashMap<[u8; 32], u32>,    // Height of the block holding each tx
+    end: u64,
+    #[cfg(test)]
+    fail_writes: bool               // Stop each record write part way, failing as a full disk would
+}
+
+impl FileStore {
+    // Open the store at the given path, creating it if it does not exist.
+    // A partially written record at the end of the file is discarded.
+    pub fn open(path: &Path) -> Result<FileStore, StoreError> {
+        if let Some(dir) = path.parent() {
+            create_dir_all(dir)?;
+        }
+        let mut file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(path)?;
+
+        let mut contents: Vec<u8> = vec![];
+        file.read_to_end(&mut contents)?;
+
+        let mut store = FileStore {
+            path: path.to_path_buf(),
+            file: file,
+            offsets: vec![],
+            index: HashMap::new(),
+            txs: HashMap::new(),
+            end: 0,
+            #[cfg(test)]
+            fail_writes: false
+        };
+
+        let mut pos: u64 = 0;
+        while pos + RECORD_HEADER <= contents.len() as u64 {
+            let start = pos as usize;
+            let kind = contents[start];
+            let len = BigEndian::read_u32(&contents[(start + 1)..(start + 5)]);
+            let payload_pos = pos + RECORD_HEADER;
+            if payload_pos + len as u64 > contents.len() as u64 {
+                break;
+            }
+            let payload = &contents[(payload_pos as usize)..(payload_pos as usize + len as usize)];
+            match kind {
+                RECORD_BLOCK => {
+                    let block = match Block::from_bin(payload) {
+                        Err(_) => return Err(StoreError::Corrupt(pos)),
+                        Ok(block) => block
+                    };
+                    check_next(&block, store.offsets.len(), &store.index)?;
+                    store.index.insert(block.hash, block.get_height());
+                    index_txs(&mut store.txs, &block);
+                    store.offsets.push((payload_pos, len));
+                },
+                RECORD_DISCONNECT => {
+                    let tip_height = store.offsets.len() as u32;
+                    let is_tip = |hash: &[u8]| store.index.get(hash) == tip_height.checked_sub(1).as_ref();
+                    if !is_tip(payload) {
+                        return Err(StoreError::Corrupt(pos));
+                    }
+                    store.index.remove(payload);
+                    store.txs.retain(|_, height| *height + 1 != tip_height);
+                    store.offsets.pop();
+                },
+                _ => return Err(StoreError::Corrupt(pos))
+            }
+
+            pos = payload_pos + len as u64;
+        }
+
+        // Drop any trailing bytes left by an interrupted write.
+        if pos < contents.len() as u64 {
+            store.file.set_len(pos)?;
+        }
+        store.end = pos;
+
+        return Ok(store);
+    }
+
+    // Append a record to the file.
+    // A failed write is truncated away, so the next record starts at the end of the last whole one.
+    fn write_record(&mut self, kind: u8, payload: &[u8]) -> Result<(), StoreError> {
+        let mut record: Vec<u8> = vec![kind];
+        record.extend_from_slice(&(payload.len() as u32).to_be_bytes());
+        record.extend_from_slice(payload);
+        if let Err(why) = self.append(&record) {
+            let _ = self.file.set_len(self.end);
+            return Err(StoreError::Io(why));
+        }
+        self.end += record.len() as u64;
+
+        return Ok(());
+    }
+
+    // Write bytes to the end of the file and sync them to disk.
+    fn append(&mut self, bytes: &[u8]) -> Result<(), Error> {
+        #[cfg(test)]
+        if self.fail_writes {
+            self.file.write_all(&bytes[..bytes.len() / 2])?;
+            return Err(Error::new(std::io::ErrorKind::WriteZero, "Simulated failed write"));
+        }
+        self.file.write_all(bytes)?;
+        return self.file.sync_data();
+    }
+}
+
+impl BlockStore for FileStore {
+    fn put_block(&mut self, block: &Block) -> Result<(), StoreError> {
+        check_next(block, self.offsets.len(), &self.index)?;
+
+        let payload = block.to_bin();
+        let offset = self.end + RECORD_HEADER;
+        self.write_record(RECORD_BLOCK, &payload)?;
+
+        self.index.insert(block.hash, block.get_height());
+        index_txs(&mut self.txs, block);
+        self.offsets.push((offset, payload.len() as u32));
+
+        return Ok(());
+    }
+
+    fn pop_block(&mut self) -> Result<Block, StoreError> {
+        let block = self.get_tip().ok_or(StoreError::Empty)?;
+        self.write_record(RECORD_DISCONNECT, &block.hash)?;
+
+        self.index.remove(&block.hash);
+        self.txs.retain(|_, height| *height != block.get_height());
+        self.offsets.pop();
+
+        return Ok(block);
+    }
+
+    fn get_by_hash(&self, hash: &[u8; 32]) -> Option<Block> {
+        let height = *self.index.get(hash)?;
+        return self.get_by_height(height);
+    }
+
+    fn contains(&self, hash: &[u8; 32]) -> bool {
+        return self.index.contains_key(hash);
+    }
+
+    fn get_by_height(&self, height: u32) -> Option<Block> {
+        let (offset, len) = *self.offsets.get(height as usize)?;
+        let mut file = File::open(&self.path).ok()?;
+        file.seek(SeekFrom::Start(offset)).ok()?;
+        let mut payload = vec![0; len as usize];
+        file.read_exact(&mut payload).ok()?;
+
+        return Block::from_bin(&payload).ok();
+    }
+
+    fn tip_height(&self) -> Option<u32> {
+        return (self.offsets.len() as u32).checked_sub(1);
+    }
+
+    fn get_tx_height(&self, hash: &[u8; 32]) -> Option<u32> {
+        return self.txs.get(hash).copied();
+    }
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::tx_struct::{DataTx, FinancialTx};
+    use std::fs::remove_file;
+
+    fn test_block(height: u32, previous_hash: [u8; 32]) -> Block {
+        let mut block = Block::new();
+        block.previous_hash = previous_hash;
+        block.height = height.to_be_bytes();
+        block.miner = vec![7; 270];
+        block.txs.push(Box::new(DataTx::new()));
+        block.txs.push(Box::new(FinancialTx::new()));
+        block.tx_count = 2;
+        block.generate_hash();
+        return block;
+    }
+
+    #[test]
+    fn memory_store_put_get() {
+        let mut store = MemoryStore::new();
+        let genesis = test_block(0, [0; 32]);
+        let next = test_block(1, genesis.hash);
+        store.put_block(&genesis).unwrap();
+        store.put_block(&next).unwrap();
+
+        assert_eq!(store.tip_height(), Some(1));
+        assert_eq!(store.get_by_height(0).unwrap().hash, genesis.hash);
+        assert_eq!(store.get_by_hash(&next.hash).unwrap().get_height(), 1);
+        assert!(store.contains(&next.hash));
+        assert_eq!(store.get_tip().unwrap().txs.len(), 2);
+        assert_eq!(store.get_tx_height(&next.txs[1].get_hash()), Some(1));
+
+        store.pop_block().unwrap();
+        assert_eq!(store.tip_height(), Some(0));
+        assert!(store.get_by_hash(&next.hash).is_none());
+        assert!(!store.contains(&next.hash));
+        assert!(store.get_tx_height(&next.txs[1].get_hash()).is_none());
+    }
+
+    #[test]
+    fn memory_store_rejects_height_gap() {
+        let mut store = MemoryStore::new();
+        let block = test_block(3, [0; 32]);
+
+        assert!(matches!(store.put_block(&block), Err(StoreError::InvalidHeight(0, 3))));
+        assert!(store.get_tip().is_none());
+    }
+
+    #[test]
+    fn file_store_resumes_from_tip() {
+        let path = temp_path("store");
+        let genesis = test_block(0, [0; 32]);
+        let next = test_block(1, genesis.hash);
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            store.put_block(&genesis).unwrap();
+            store.put_block(&next).unwrap();
+        }
+
+        let store = FileStore::open(&path).unwrap();
+        assert_eq!(store.tip_height(), Some(1));
+        assert_eq!(store.get_tip().unwrap().hash, next.hash);
+        assert_eq!(store.get_by_hash(&genesis.hash).unwrap().get_height(), 0);
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_replays_disconnected_blocks() {
+        let path = temp_path("store");
+        let genesis = test_block(0, [0; 32]);
+        let stale = test_block(1, genesis.hash);
+        let mut replacement = test_block(1, genesis.hash);
+        replacement.timestamp = [1; 8];
+        replacement.generate_hash();
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            store.put_block(&genesis).unwrap();
+            store.put_block(&stale).unwrap();
+            assert_eq!(store.pop_block().unwrap().hash, stale.hash);
+            store.put_block(&replacement).unwrap();
+        }
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.get_tip().unwrap().hash, replacement.hash);
+        assert!(store.get_by_hash(&stale.hash).is_none());
+        assert!(!store.contains(&stale.hash));
+        store.pop_block().unwrap();
+        store.pop_block().unwrap();
+        assert!(matches!(store.pop_block(), Err(StoreError::Empty)));
+        assert!(FileStore::open(&path).unwrap().get_tip().is_none());
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_drops_partial_record() {
+        let path = temp_path("store");
+        let genesis = test_block(0, [0; 32]);
+        {
+            let mut store = FileStore::open(&path).unwrap();
+            store.put_block(&genesis).unwrap();
+        }
+        let mut file = OpenOptions::new().append(true).open(&path).unwrap();
+        file.write_all(&[RECORD_BLOCK, 0, 0, 1]).unwrap();
+
+        let mut store = FileStore::open(&path).unwrap();
+        assert_eq!(store.tip_height(), Some(0));
+        store.put_block(&test_block(1, genesis.hash)).unwrap();
+        assert_eq!(FileStore::open(&path).unwrap().tip_height(), Some(1));
+        remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn file_store_truncates_failed_write() {
+        let path = temp_path("store");
+        let genesis = test_block(0, [0; 32]);
+        let next = test_block(1, genesis.hash);
+        let mut store = FileStore::open(&path).unwrap();
+        store.put_block(&genesis).unwrap();
+        let end = store.end;
+
+        store.fail_writes = true;
+        assert!(matches!(store.put_block(&next), Err(StoreError::Io(_))));
+        assert!(matches!(store.pop_block(), Err(StoreError::Io(_))));
+        assert_eq!(std::fs::metadata(&path).unwrap().len(), end);
+
+        // The store still appends valid records, which read back after reopening.
+        store.fail_writes = false;
+        store.put_block(&next).unwrap();
+        assert_eq!(store.get_tip().unwrap().hash, next.hash);
+        let reopened = FileStore::open(&path).unwrap();
+        assert_eq!(reopened.tip_height(), Some(1));
+        assert_eq!(reopened.get_tip().unwrap().hash, next.hash);
+        remove_file(&path).unwrap();
+    }
+}
@@ -0,0 +1,502 @@
+use crate::block_struct::{Block, BlockHeader};
+use crate::config::ChainConfig;
+use crate::retarget::{BlockTime, ancestry_span, next_bits};
+use crate::target::{meets_target, bits_from_diff};
+use crate::net::PeerId;
+use crate::store::BlockStore;
+use std::collections::{HashMap, VecDeque};
+use std::time::{Duration, Instant};
+
+pub const MAX_HEADERS: usize = 2000;                        // Most headers sent in one headers message
+const MAX_IN_FLIGHT: usize = 16;                            // Most blocks requested from a single peer at once
+const DOWNLOAD_WINDOW: usize = 1024;                        // Blocks past the tip that may be downloaded ahead of validation
+const MAX_PENDING_HEADERS: usize = 16 * DOWNLOAD_WINDOW;    // Headers held before no more are requested until blocks are applied
+const HEADERS_TIMEOUT: Duration = Duration::from_secs(30);  // Time allowed for a peer to answer getheaders
+const BLOCK_TIMEOUT: Duration = Duration::from_secs(30);    // Time allowed for a peer to deliver a requested block
+const REPORT_INTERVAL: Duration = Duration::from_secs(5);   // Delay between progress reports
+
+// Reasons headers from a peer are rejected.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum SyncError {
+    UnexpectedHeaders,          // Headers were not requested from the peer
+    Disconnected(usize),        // Header at index does not follow the previous header
+    InvalidHeight(usize),       // Header at index does not have the next height
+    InvalidHash(usize),         // Header at index does not hash to its hash field
+    InvalidBits(usize),         // Header at index does not have the target retargeted from its ancestors
+    InsufficientWork(usize)     // Header at index has a hash above its target
+}
+
+/**
+ * Initial block download state.
+ * Headers are fetched from one peer at a time and checked for linkage,
+ * retargeting and proof-of-work, then the blocks they describe are requested in
+ * parallel from every peer known to hold them. Downloaded blocks are
+ * buffered and handed back to the chain strictly in height order.
+ */
+pub struct BlockSync {
+    peers: HashMap<PeerId, u32>,                        // Block count known to be held by each ready peer
+    header_request: Option<(PeerId, Instant)>,          // Peer asked for headers and when
+    headers: VecDeque<BlockHeader>,                     // Headers of unknown blocks, next to apply first
+    window: VecDeque<BlockTime>,                        // Retarget window ending at the last header
+    requested: HashMap<[u8; 32], (PeerId, Instant)>,    // Blocks in flight, with the peer asked and when
+    downloaded: HashMap<[u8; 32], (PeerId, Block)>,     // Blocks received but not yet applied, with the peer that sent them
+    params: ChainConfig,                                // Consensus parameters headers are checked against
+    last_report: Instant
+}
+
+impl BlockSync {
+    pub fn new(params: &ChainConfig) -> BlockSync {
+        return BlockSync {
+            peers: HashMap::new(),
+            header_request: None,
+            headers: VecDeque::new(),
+            window: VecDeque::new(),
+            requested: HashMap::new(),
+            downloaded: HashMap::new(),
+            params: params.clone(),
+            last_report: Instant::now()
+        }
+    }
+
+    // Check if the node is fetching headers or blocks.
+    pub fn is_syncing(&self) -> bool {
+        return self.header_request.is_some() || !self.headers.is_empty();
+    }
+
+    // Return the block count the node is syncing towards.
+    pub fn get_target(&self, local_count: u32) -> u32 {
+        let header_count = self.headers.back().map(|header| header.get_height() + 1).unwrap_or(0);
+        return local_count.max(header_count);
+    }
+
+    // Record the block count held by a peer, keeping the highest seen.
+    pub fn update_peer(&mut self, id: PeerId, block_count: u32) -> () {
+        let count = self.peers.entry(id).or_insert(0);
+        *count = (*count).max(block_count);
+    }
+
+    // Lower the block count known to be held by a peer, so it is not asked for blocks it does not have.
+    pub fn cap_peer(&mut self, id: PeerId, block_count: u32) -> () {
+        if let Some(count) = self.peers.get_mut(&id) {
+            *count = (*count).min(block_count);
+        }
+    }
+
+    // Forget a disconnected peer, freeing its block requests for other peers.
+    pub fn remove_peer(&mut self, id: PeerId) -> () {
+        self.peers.remove(&id);
+        self.requested.retain(|_, (peer, _)| *peer != id);
+        if self.header_request.map(|(peer, _)| peer) == Some(id) {
+            self.header_request = None;
+        }
+    }
+
+    // Choose a peer to ask for headers, if any peer holds more blocks than are known.
+    // The peer with the most blocks is chosen, and is remembered until it answers or times out.
+    // No headers are requested while MAX_PENDING_HEADERS are waiting for their blocks.
+    pub fn next_header_request(&mut self, local_count: u32, now: Instant) -> Option<PeerId> {
+        if let Some((_, requested_at)) = self.header_request {
+            if now.duration_since(requested_at) < HEADERS_TIMEOUT {
+                return None;
+            }
+        }
+        self.header_request = None;
+        if self.headers.len() >= MAX_PENDING_HEADERS {
+            return None;
+        }
+
+        let target = self.get_target(local_count);
+        let (id, _) = self.peers.iter()
+            .filter(|(_, count)| **count > target)
+            .max_by_key(|(id, count)| (**count, std::cmp::Reverse(**id)))?;
+        self.header_request = Some((*id, now));
+
+        return Some(*id);
+    }
+
+    // Return the hash of the last known header, used to continue a header download.
+    pub fn get_header_tip(&self) -> Option<[u8; 32]> {
+        return self.headers.back().map(|header| header.hash);
+    }
+
+    // Add headers sent by a peer on top of any headers already held.
+    // Headers of blocks already known must be left out by the caller, which passes
+    // the known block the headers continue from, that may be on a side branch,
+    // and the timestamps and bits of that block and its ancestors, oldest first.
+    // Each header must carry the target retargeted from the blocks before it.
+    pub fn add_headers(&mut self, id: PeerId, headers: Vec<BlockHeader>, parent: Option<&BlockHeader>, ancestry: Vec<BlockTime>) -> Result<(), SyncError> {
+        if self.header_request.map(|(peer, _)| peer) != Some(id) {
+            return Err(SyncError::UnexpectedHeaders);
+        }
+        self.header_request = None;
+
+        let mut window: VecDeque<BlockTime> = if self.headers.is_empty() { ancestry.into() } else { self.window.clone() };
+        let mut accepted: Vec<BlockHeader> = vec![];
+        for (i, header) in headers.into_iter().enumerate() {
+            let (prev_hash, next_height) = match accepted.last().or_else(|| self.headers.back()).or(parent) {
+                Some(prev) => (prev.hash, prev.get_height() + 1),
+                None => ([0; 32], 0)
+            };
+            if header.previous_hash != prev_hash {
+                return Err(SyncError::Disconnected(i));
+            }
+            if header.get_height() != next_height {
+                return Err(SyncError::InvalidHeight(i));
+            }
+            if header.calculate_hash() != header.hash {
+                return Err(SyncError::InvalidHash(i));
+            }
+            let bits = match next_height {
+                0 => bits_from_diff(self.params.genesis_diff),
+                _ => next_bits(window.make_contiguous(), &self.params)
+            };
+            if header.bits != bits {
+                return Err(SyncError::InvalidBits(i));
+            }
+            if !meets_target(&header.hash, header.bits) {
+                return Err(SyncError::InsufficientWork(i));
+            }
+
+            if window.len() == ancestry_span(&self.params) {
+                window.pop_front();
+            }
+            window.push_back(BlockTime::from_header(&header));
+            accepted.push(header);
+        }
+
+        if let Some(last) = accepted.last() {
+            self.update_peer(id, last.get_height() + 1);
+            self.window = window;
+        }
+        self.headers.extend(accepted);
+
+        return Ok(());
+    }
+
+    // Assign blocks to download to peers known to hold them.
+    // Requests left unanswered past the timeout are reassigned.
+    // Returns the block hashes to request from each peer.
+    pub fn next_block_requests(&mut self, now: Instant) -> Vec<(PeerId, Vec<[u8; 32]>)> {
+        self.requested.retain(|_, (_, requested_at)| now.duration_since(*requested_at) < BLOCK_TIMEOUT);
+
+        let mut in_flight: HashMap<PeerId, usize> = self.peers.keys().map(|id| (*id, 0)).collect();
+        for (peer, _) in self.requested.values() {
+            *in_flight.entry(*peer).or_insert(0) += 1;
+        }
+
+        let mut requests: HashMap<PeerId, Vec<[u8; 32]>> = HashMap::new();
+        for header in self.headers.iter().take(DOWNLOAD_WINDOW) {
+            if self.requested.contains_key(&header.hash) || self.downloaded.contains_key(&header.hash) {
+                continue;
+            }
+
+            // Pick the least loaded peer holding the block.
+            let height = header.get_height();
+            let peer = in_flight.iter()
+                .filter(|(id, count)| **count < MAX_IN_FLIGHT && self.peers.get(id).map(|held| *held > height).unwrap_or(false))
+                .min_by_key(|(id, count)| (**count, **id))
+                .map(|(id, _)| *id);
+            if let Some(id) = peer {
+                *in_flight.get_mut(&id).unwrap() += 1;
+                self.requested.insert(header.hash, (id, now));
+                requests.entry(id).or_default().push(header.hash);
+            }
+        }
+
+        let mut requests: Vec<(PeerId, Vec<[u8; 32]>)> = requests.into_iter().collect();
+        requests.sort_by_key(|(id, _)| *id);
+        return requests;
+    }
+
+    // Accept a block if it was requested from the peer.
+    // The block is handed back if it is not part of the download.
+    pub fn add_block(&mut self, id: PeerId, block: Block) -> Option<Block> {
+        match self.requested.get(&block.hash) {
+            Some((peer, _)) if *peer == id => {
+                self.requested.remove(&block.hash);
+                self.downloaded.insert(block.hash, (id, block));
+                return None;
+            },
+            _ => return Some(block)
+        }
+    }
+
+    // Handle blocks a peer could not supply, so they are requested from another peer.
+    pub fn blocks_not_found(&mut self, id: PeerId, hashes: &[[u8; 32]]) -> () {
+        for hash in hashes.iter() {
+            if self.requested.get(hash).map(|(peer, _)| *peer) != Some(id) {
+                continue;
+            }
+            self.requested.remove(hash);
+
+            // The peer does not hold the block, so cap its known count below it.
+            if let Some(height) = self.headers.iter().find(|header| header.hash == *hash).map(|header| header.get_height()) {
+                self.cap_peer(id, height);
+            }
+        }
+    }
+
+    // Take the next block to apply and the peer that sent it, if it has been downloaded.
+    pub fn next_block(&mut self) -> Option<(PeerId, Block)> {
+        let hash = self.headers.front()?.hash;
+        let downloaded = self.downloaded.remove(&hash)?;
+        self.headers.pop_front();
+
+        return Some(downloaded);
+    }
+
+    // Drop all pending headers and blocks, used when a downloaded block fails validation.
+    pub fn reset(&mut self) -> () {
+        self.header_request = None;
+        self.headers.clear();
+        self.window.clear();
+        self.requested.clear();
+        self.downloaded.clear();
+    }
+
+    // Check if a progress report is due, restarting the report timer if so.
+    pub fn report_due(&mut self, now: Instant) -> bool {
+        if now.duration_since(self.last_report) < REPORT_INTERVAL {
+            return false;
+        }
+
+        self.last_report = now;
+        return true;
+    }
+}
+
+// Build a block locator from the store, listing block hashes from the tip back to genesis.
+// The ten most recent blocks are listed, then the step between heights doubles.
+pub fn build_locator(store: &dyn BlockStore) -> Vec<[u8; 32]> {
+    let mut locator: Vec<[u8; 32]> = vec![];
+    let mut height = match store.tip_height() {
+        None => return locator,
+        Some(height) => height
+    };
+
+    let mut step: u32 = 1;
+    loop {
+        if let Some(block) = store.get_by_height(height) {
+            locator.push(block.hash);
+        }
+        if height == 0 {
+            break;
+        }
+        if locator.len() >= 10 {
+            step *= 2;
+        }
+        height = height.saturating_sub(step);
+    }
+
+    return locator;
+}
+
+// Return up to MAX_HEADERS headers following the first locator hash held in the store.
+// If no locator hash is held, headers start from the genesis block.
+pub fn headers_after(store: &dyn BlockStore, locator: &[[u8; 32]]) -> Vec<BlockHeader> {
+    let tip_height = match store.tip_height() {
+        None => return vec![],
+        Some(height) => height
+    };
+    let start = locator.iter()
+        .find_map(|hash| store.get_by_hash(hash))
+        .map(|block| block.get_height() + 1)
+        .unwrap_or(0);
+
+    return (start..=tip_height)
+        .take(MAX_HEADERS)
+        .map_while(|height| store.get_by_height(height).map(|block| block.get_header()))
+        .collect();
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::store::MemoryStore;
+    use crate::test_util::mine;
+
+    fn params() -> ChainConfig {
+        return ChainConfig { genesis_diff: 1, ..ChainConfig::default() };
+    }
+
+    // Mine a chain of empty blocks one block time apart, at the easiest target.
+    fn chain(length: u32) -> Vec<Block> {
+        let mut blocks: Vec<Block> = vec![];
+        for height in 0..length {
+            let mut block = Block::new();
+            block.previous_hash = blocks.last().map(|prev: &Block| prev.hash).unwrap_or([0; 32]);
+            block.bits = match height {
+                0 => bits_from_diff(params().genesis_diff),
+                _ => next_bits(&times(&blocks), &params())
+            };
+            block.height = height.to_be_bytes();
+            block.timestamp = (height as u64 * params().block_time as u64).to_be_bytes();
+            block.miner = vec![1, 2, 3];
+            mine(&mut block);
+            blocks.push(block);
+        }
+
+        return blocks;
+    }
+
+    fn store_of(blocks: &[Block]) -> MemoryStore {
+        let mut store = MemoryStore::new();
+        for block in blocks.iter() {
+            store.put_block(block).unwrap();
+        }
+        return store;
+    }
+
+    fn headers(blocks: &[Block]) -> Vec<BlockHeader> {
+        return blocks.iter().map(|block| block.get_header()).collect();
+    }
+
+    fn times(blocks: &[Block]) -> Vec<BlockTime> {
+        return blocks.iter().map(BlockTime::from_block).collect();
+    }
+
+    #[test]
+    fn locator_steps_back_to_genesis() {
+        let blocks = chain(40);
+        let locator = build_locator(&store_of(&blocks));
+
+        assert_eq!(locator[0], blocks[39].hash);
+        assert_eq!(locator[9], blocks[30].hash);
+        assert_eq!(locator[10], blocks[28].hash);
+        assert_eq!(*locator.last().unwrap(), blocks[0].hash);
+        assert!(build_locator(&MemoryStore::new()).is_empty());
+    }
+
+    #[test]
+    fn serves_headers_after_locator() {
+        let blocks = chain(20);
+        let store = store_of(&blocks);
+
+        assert_eq!(headers_after(&store, &[[9; 32], blocks[11].hash, blocks[5].hash]), headers(&blocks[12..]));
+        assert_eq!(headers_after(&store, &[[9; 32]]), headers(&blocks));
+        assert!(headers_after(&store, &[blocks[19].hash]).is_empty());
+    }
+
+    #[test]
+    fn downloads_in_parallel_and_applies_in_order() {
+        let blocks = chain(6);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, 6);
+        sync.update_peer(2, 6);
+
+        assert_eq!(sync.next_header_request(2, now), Some(1));
+        assert_eq!(sync.next_header_request(2, now), None);
+        assert_eq!(sync.add_headers(1, headers(&blocks[2..]), Some(&blocks[1].get_header()), times(&blocks[..2])), Ok(()));
+        assert_eq!(sync.get_target(2), 6);
+
+        let requests = sync.next_block_requests(now);
+        assert_eq!(requests, vec![(1, vec![blocks[2].hash, blocks[4].hash]), (2, vec![blocks[3].hash, blocks[5].hash])]);
+        assert!(sync.next_block_requests(now).is_empty());
+
+        assert!(sync.add_block(2, Block::from_bin(&blocks[3].to_bin()).unwrap()).is_none());
+        assert!(sync.add_block(1, Block::from_bin(&blocks[5].to_bin()).unwrap()).is_some());
+        assert!(sync.next_block().is_none());
+        assert!(sync.add_block(1, Block::from_bin(&blocks[2].to_bin()).unwrap()).is_none());
+
+        assert_eq!(sync.next_block().map(|(id, block)| (id, block.hash)), Some((1, blocks[2].hash)));
+        assert_eq!(sync.next_block().map(|(id, block)| (id, block.hash)), Some((2, blocks[3].hash)));
+        assert!(sync.next_block().is_none());
+        assert!(sync.is_syncing());
+    }
+
+    #[test]
+    fn reassigns_requests_of_lost_and_slow_peers() {
+        let blocks = chain(3);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, 3);
+        sync.update_peer(2, 3);
+        sync.next_header_request(0, now);
+        sync.add_headers(1, headers(&blocks), None, vec![]).unwrap();
+        sync.next_block_requests(now);
+
+        sync.remove_peer(1);
+        assert_eq!(sync.next_block_requests(now), vec![(2, vec![blocks[0].hash, blocks[2].hash])]);
+
+        sync.update_peer(3, 3);
+        let later = now + BLOCK_TIMEOUT;
+        assert_eq!(sync.next_block_requests(later), vec![(2, vec![blocks[0].hash, blocks[2].hash]), (3, vec![blocks[1].hash])]);
+
+        sync.blocks_not_found(3, &[blocks[1].hash]);
+        assert_eq!(sync.next_block_requests(later), vec![(2, vec![blocks[1].hash])]);
+    }
+
+    #[test]
+    fn accepts_headers_forking_below_tip() {
+        let blocks = chain(6);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, 6);
+
+        // The local chain holds five blocks, the peer's branch forks after the second.
+        sync.next_header_request(5, now);
+        assert_eq!(sync.add_headers(1, headers(&blocks[2..]), Some(&blocks[1].get_header()), times(&blocks[..2])), Ok(()));
+        assert_eq!(sync.get_target(5), 6);
+        assert_eq!(sync.next_block_requests(now), vec![(1, blocks[2..].iter().map(|block| block.hash).collect())]);
+    }
+
+    #[test]
+    fn rejects_invalid_headers() {
+        let blocks = chain(4);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, 4);
+
+        assert_eq!(sync.add_headers(1, headers(&blocks), None, vec![]), Err(SyncError::UnexpectedHeaders));
+
+        sync.next_header_request(0, now);
+        let mut gap = headers(&blocks);
+        gap.remove(1);
+        assert_eq!(sync.add_headers(1, gap, None, vec![]), Err(SyncError::Disconnected(1)));
+
+        sync.next_header_request(0, now + HEADERS_TIMEOUT);
+        let mut forged = headers(&blocks);
+        forged[2].timestamp = [1; 8];
+        assert_eq!(sync.add_headers(1, forged, None, vec![]), Err(SyncError::InvalidHash(2)));
+        assert!(!sync.is_syncing());
+    }
+
+    #[test]
+    fn rejects_headers_not_following_retarget() {
+        let blocks = chain(4);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, 4);
+
+        // A header choosing its own target is refused, even though its hash meets that target.
+        let mut own_target = blocks[3].clone();
+        own_target.bits = bits_from_diff(2);
+        mine(&mut own_target);
+        sync.next_header_request(3, now);
+        assert_eq!(sync.add_headers(1, vec![own_target.get_header()], Some(&blocks[2].get_header()), times(&blocks[..3])), Err(SyncError::InvalidBits(0)));
+
+        // The retarget window carries over from headers already held.
+        sync.next_header_request(1, now + HEADERS_TIMEOUT);
+        assert_eq!(sync.add_headers(1, headers(&blocks[1..3]), Some(&blocks[0].get_header()), times(&blocks[..1])), Ok(()));
+        sync.next_header_request(1, now + HEADERS_TIMEOUT * 2);
+        assert_eq!(sync.add_headers(1, vec![own_target.get_header()], None, vec![]), Err(SyncError::InvalidBits(0)));
+        sync.next_header_request(1, now + HEADERS_TIMEOUT * 3);
+        assert_eq!(sync.add_headers(1, headers(&blocks[3..]), None, vec![]), Ok(()));
+    }
+
+    #[test]
+    fn stops_requesting_headers_while_many_are_pending() {
+        let blocks = chain(1);
+        let now = Instant::now();
+        let mut sync = BlockSync::new(&params());
+        sync.update_peer(1, u32::MAX);
+
+        sync.headers.extend(std::iter::repeat_n(blocks[0].get_header(), MAX_PENDING_HEADERS - 1));
+        assert_eq!(sync.next_header_request(1, now), Some(1));
+        sync.headers.push_back(blocks[0].get_header());
+        assert_eq!(sync.next_header_request(1, now + HEADERS_TIMEOUT), None);
+    }
+}
@@ -0,0 +1,305 @@
+use byteorder::{ByteOrder, BigEndian};
+
+
+// Compact bits of the easiest target allowed, any hash with a leading zero bit meets it.
+pub const MAX_BITS: u32 = 0x207f_ffff;
+
+/**
+ * An unsigned 256-bit integer, used for proof-of-work targets.
+ * Limbs are held most significant first, so the derived ordering
+ * matches comparing hashes as big-endian integers.
+ */
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord)]
+pub struct U256(pub [u64; 4]);
+
+impl U256 {
+    pub const ZERO: U256 = U256([0; 4]);
+    pub const ONE: U256 = U256([0, 0, 0, 1]);
+    pub const MAX: U256 = U256([u64::MAX; 4]);
+
+    pub fn from_u64(val: u64) -> U256 {
+        return U256([0, 0, 0, val]);
+    }
+
+    // Read a big-endian 32 byte array, such as a hash.
+    pub fn from_bytes(bytes: &[u8; 32]) -> U256 {
+        let mut limbs = [0; 4];
+        for (i, limb) in limbs.iter_mut().enumerate() {
+            *limb = BigEndian::read_u64(&bytes[i * 8..(i + 1) * 8]);
+        }
+        return U256(limbs);
+    }
+
+    // Write as a big-endian 32 byte array, comparable against hashes.
+    pub fn to_bytes(self) -> [u8; 32] {
+        let mut bytes = [0; 32];
+        for (i, limb) in self.0.iter().enumerate() {
+            BigEndian::write_u64(&mut bytes[i * 8..(i + 1) * 8], *limb);
+        }
+        return bytes;
+    }
+
+    // Return the lowest 64 bits.
+    pub fn low_u64(&self) -> u64 {
+        return self.0[3];
+    }
+
+    // Convert to a u128, saturating if the value does not fit.
+    pub fn saturating_u128(&self) -> u128 {
+        if self.0[0] != 0 || self.0[1] != 0 {
+            return u128::MAX;
+        }
+        return (self.0[2] as u128) << 64 | self.0[3] as u128;
+    }
+
+    // Return the number of bits needed to represent the value.
+    pub fn bits(&self) -> u32 {
+        for (i, limb) in self.0.iter().enumerate() {
+            if *limb != 0 {
+                return (3 - i as u32) * 64 + (64 - limb.leading_zeros());
+            }
+        }
+        return 0;
+    }
+
+    // Shift left, dropping bits shifted past the most significant.
+    pub fn shl(&self, shift: u32) -> U256 {
+        let mut result = U256::ZERO;
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for i in 0..4 {
+            let src = i + limbs;
+            if src < 4 {
+                result.0[i] = self.0[src] << bits;
+                if bits > 0 && src + 1 < 4 {
+                    result.0[i] |= self.0[src + 1] >> (64 - bits);
+                }
+            }
+        }
+        return result;
+    }
+
+    // Shift right, dropping bits shifted past the least significant.
+    pub fn shr(&self, shift: u32) -> U256 {
+        let mut result = U256::ZERO;
+        let (limbs, bits) = ((shift / 64) as usize, shift % 64);
+        for i in limbs.min(4)..4 {
+            let src = i - limbs;
+            result.0[i] = self.0[src] >> bits;
+            if bits > 0 && src > 0 {
+                result.0[i] |= self.0[src - 1] << (64 - bits);
+            }
+        }
+        return result;
+    }
+
+    // Add, returning the wrapped sum and whether it overflowed.
+    pub fn overflowing_add(&self, other: &U256) -> (U256, bool) {
+        let mut result = U256::ZERO;
+        let mut carry = false;
+        for i in (0..4).rev() {
+            let (sum, over_a) = self.0[i].overflowing_add(other.0[i]);
+            let (sum, over_b) = sum.overflowing_add(carry as u64);
+            result.0[i] = sum;
+            carry = over_a || over_b;
+        }
+        return (result, carry);
+    }
+
+    // Subtract, returning the wrapped difference and whether it underflowed.
+    pub fn overflowing_sub(&self, other: &U256) -> (U256, bool) {
+        let mut result = U256::ZERO;
+        let mut borrow = false;
+        for i in (0..4).rev() {
+            let (diff, under_a) = self.0[i].overflowing_sub(other.0[i]);
+            let (diff, under_b) = diff.overflowing_sub(borrow as u64);
+            result.0[i] = diff;
+            borrow = under_a || under_b;
+        }
+        return (result, borrow);
+    }
+
+    // Multiply by a u64, returning None on overflow.
+    pub fn checked_mul_u64(&self, val: u64) -> Option<U256> {
+        let mut result = U256::ZERO;
+        let mut carry: u128 = 0;
+        for i in (0..4).rev() {
+            let product = self.0[i] as u128 * val as u128 + carry;
+            result.0[i] = product as u64;
+            carry = product >> 64;
+        }
+        return match carry {
+            0 => Some(result),
+            _ => None
+        };
+    }
+
+    // Divide by another value using binary long division, returning None when dividing by zero.
+    pub fn checked_div(&self, divisor: &U256) -> Option<U256> {
+        if *divisor == U256::ZERO {
+            return None;
+        }
+
+        let mut quotient = U256::ZERO;
+        let mut remainder = U256::ZERO;
+        for bit in (0..self.bits()).rev() {
+            // A set top bit would be shifted out, in which case the remainder exceeds the divisor.
+            let carried = remainder.0[0] >> 63 == 1;
+            remainder = remainder.shl(1);
+            remainder.0[3] |= (self.0[3 - (bit / 64) as usize] >> (bit % 64)) & 1;
+            if carried || remainder >= *divisor {
+                remainder = remainder.overflowing_sub(divisor).0;
+                quotient.0[3 - (bit / 64) as usize] |= 1 << (bit % 64);
+            }
+        }
+        return Some(quotient);
+    }
+}
+
+// Decode compact bits into a target.
+// The top byte of the bits is an exponent and the low three bytes a mantissa,
+// giving a target of mantissa * 256^(exponent - 3).
+// Returns None for bits with the mantissa sign bit set, a zero target or a target over 256 bits.
+pub fn target_from_bits(bits: u32) -> Option<U256> {
+    let exponent = bits >> 24;
+    let mantissa = bits & 0x007f_ffff;
+    if bits & 0x0080_0000 != 0 {
+        return None;
+    }
+
+    let target = if exponent <= 3 {
+        U256::from_u64((mantissa >> (8 * (3 - exponent))) as u64)
+    }
+    else {
+        let mantissa = U256::from_u64(mantissa as u64);
+        if mantissa.bits() + 8 * (exponent - 3) > 256 {
+            return None;
+        }
+        mantissa.shl(8 * (exponent - 3))
+    };
+    if target == U256::ZERO {
+        return None;
+    }
+
+    return Some(target);
+}
+
+// Encode a target as compact bits, keeping its three most significant bytes.
+// The mantissa is shifted down a byte if its top bit is set, as that bit marks a negative value.
+pub fn bits_from_target(target: &U256) -> u32 {
+    let mut exponent = target.bits().div_ceil(8);
+    let mut mantissa = match exponent <= 3 {
+        true => (target.low_u64() << (8 * (3 - exponent))) as u32,
+        false => target.shr(8 * (exponent - 3)).low_u64() as u32
+    };
+    if mantissa & 0x0080_0000 != 0 {
+        mantissa >>= 8;
+        exponent += 1;
+    }
+
+    return exponent << 24 | mantissa;
+}
+
+// Return the compact bits of the target met by hashes with the given number of leading zero bits.
+pub fn bits_from_diff(diff: u8) -> u32 {
+    return bits_from_target(&U256::MAX.shr(diff.max(1) as u32));
+}
+
+// Check if a hash, read as a big-endian integer, is at or below the target of the given bits.
+pub fn meets_target(hash: &[u8; 32], bits: u32) -> bool {
+    return match target_from_bits(bits) {
+        None => false,
+        Some(target) => U256::from_bytes(hash) <= target
+    };
+}
+
+// Return the expected number of hashes tried to meet the target of the given bits, 2^256 / (target + 1).
+// Saturates at u128::MAX, bits not decoding to a target represent no work.
+pub fn block_work(bits: u32) -> u128 {
+    let target = match target_from_bits(bits) {
+        None => return 0,
+        Some(target) => target
+    };
+
+    // 2^256 does not fit in 256 bits, but 2^256 / (target + 1) == (2^256 - 1 - target) / (target + 1) + 1.
+    let (divisor, overflow) = target.overflowing_add(&U256::ONE);
+    if overflow {
+        return 1;
+    }
+    let work = U256::MAX.overflowing_sub(&target).0.checked_div(&divisor).unwrap_or(U256::ZERO);
+
+    return work.saturating_u128().saturating_add(1);
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn compact_bits_round_trip() {
+        let target = target_from_bits(0x1d00_ffff).unwrap();
+        assert_eq!(target, U256::from_u64(0xffff).shl(208));
+        assert_eq!(bits_from_target(&target), 0x1d00_ffff);
+
+        assert_eq!(target_from_bits(0x0312_3456), Some(U256::from_u64(0x12_3456)));
+        assert_eq!(target_from_bits(0x0212_3456), Some(U256::from_u64(0x1234)));
+        assert_eq!(bits_from_target(&U256::from_u64(0x12)), 0x0112_0000);
+        assert_eq!(bits_from_target(&U256::from_u64(0x80)), 0x0200_8000);
+        assert_eq!(target_from_bits(MAX_BITS), Some(U256::from_u64(0x7f_ffff).shl(232)));
+    }
+
+    #[test]
+    fn rejects_invalid_bits() {
+        assert_eq!(target_from_bits(0x0480_0000), None);
+        assert_eq!(target_from_bits(0x2200_0100), None);
+        assert_eq!(target_from_bits(0x1d00_0000), None);
+        assert_eq!(block_work(0x0480_0000), 0);
+        assert!(!meets_target(&[0; 32], 0x0480_0000));
+    }
+
+    #[test]
+    fn compares_hashes_to_target() {
+        let bits = bits_from_diff(12);
+        let mut hash = [0; 32];
+        hash[1] = 0x0f;
+        hash[2] = 0xff;
+        assert!(meets_target(&hash, bits));
+        hash[1] = 0x10;
+        assert!(!meets_target(&hash, bits));
+        assert!(meets_target(&[0; 32], MAX_BITS));
+        assert!(!meets_target(&[0xff; 32], MAX_BITS));
+    }
+
+    #[test]
+    fn work_doubles_with_each_leading_zero_bit() {
+        assert_eq!(block_work(bits_from_diff(1)), 2);
+        assert_eq!(block_work(bits_from_diff(4)), 16);
+        assert_eq!(block_work(0x0300_0001), u128::MAX);
+
+        // Compact bits keep three bytes of the target, so larger targets are rounded down slightly.
+        let work = block_work(bits_from_diff(40));
+        assert!((1 << 40..(1 << 40) + (1 << 25)).contains(&work));
+
+        // Halving the target doubles the work.
+        let target = target_from_bits(0x1d00_ffff).unwrap();
+        assert_eq!(block_work(bits_from_target(&target.shr(1))) / 2, block_work(0x1d00_ffff));
+    }
+
+    #[test]
+    fn arithmetic_matches_u128() {
+        let a: u128 = 0x1234_5678_9abc_def0_1122_3344_5566_7788;
+        let b: u128 = 0xfedc_ba98_7654_3210;
+        let big = |val: u128| U256([0, 0, (val >> 64) as u64, val as u64]);
+
+        assert_eq!(big(a).checked_div(&big(b)), Some(big(a / b)));
+        assert_eq!(big(a).overflowing_add(&big(b)), (big(a + b), false));
+        assert_eq!(big(a).overflowing_sub(&big(b)), (big(a - b), false));
+        assert_eq!(big(b).checked_mul_u64(1000), Some(big(b * 1000)));
+        assert_eq!(big(a).shl(100).shr(100), big(a));
+        assert_eq!(U256::from_bytes(&big(a).to_bytes()), big(a));
+        assert_eq!(U256::MAX.checked_mul_u64(2), None);
+        assert_eq!(U256::MAX.checked_div(&U256::ZERO), None);
+        assert_eq!(U256::MAX.checked_div(&U256::MAX.shr(1)), Some(U256::from_u64(2)));
+    }
+}
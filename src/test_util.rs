@@ -0,0 +1,43 @@
+use crate::block_struct::Block;
+use crate::target::meets_target;
+use crate::tx_struct::{Tx, FinancialTx, TxType, TX_VERSION};
+use crate::util::generate_rand_data;
+use crate::wallet_struct::Wallet;
+use std::env::temp_dir;
+use std::path::PathBuf;
+
+
+// Increment the nonce until the block hash meets its target.
+pub fn mine(block: &mut Block) -> () {
+    loop {
+        block.generate_hash();
+        if meets_target(&block.hash, block.bits) {
+            break;
+        }
+        block.nonce = (u128::from_be_bytes(block.nonce) + 1).to_be_bytes();
+    }
+}
+
+// Build a financial tx signed by the wallet, paying a fixed receiver.
+pub fn signed_transfer(wallet: &Wallet, quantity: u32, reward: u32, previous_hash: [u8; 32]) -> FinancialTx {
+    let mut tx = FinancialTx {
+        version: TX_VERSION,
+        tx_type: TxType::Financial,
+        scheme: wallet.scheme,
+        owner: wallet.public_key.clone(),
+        receiver: vec![0xB0; 4],
+        quantity: quantity.to_be_bytes(),
+        reward: reward.to_be_bytes(),
+        previous_hash: previous_hash,
+        hash: [0; 32],
+        signature: vec![]
+    };
+    tx.generate_hash();
+    tx.generate_signature(wallet);
+    return tx;
+}
+
+// Return a fresh path in the temporary directory, unique to the call.
+pub fn temp_path(name: &str) -> PathBuf {
+    return temp_dir().join(format!("rusty-chain-{}-{:x?}", name, generate_rand_data()));
+}
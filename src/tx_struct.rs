@@ -1,5 +1,11 @@
-use crate::util::{type_of, hash};
+use crate::util::hash;
 use crate::wallet_struct::{Wallet};
+use crate::signature_scheme::{Scheme, MAX_SIGNATURE_LEN};
+use byteorder::{ByteOrder, BigEndian};
+use crate::codec::{DecodeError, take, take_array, take_u8, take_prefixed, push_prefixed, check_end};
+use crate::json::{JsonError, TxJson, parse, format};
+
+pub const TX_VERSION: u8 = 1;   // Version of the tx binary format, which carries a signature scheme id and a length prefixed signature
 
 // Enum containing transaction type(s).
 #[derive(Debug, Copy, Clone, PartialEq)]
@@ -9,7 +15,8 @@ pub enum TxType {
 }
 
 // Generalised interface for Tx structs.
-pub trait Tx {
+// Txs are Send so they can be passed between the chain and network threads.
+pub trait Tx: Send {
     // Convert full transaction to bin.
     fn to_bin(&self) -> Vec<u8>;
 
@@ -24,6 +31,111 @@ pub trait Tx {
 
     // Generate and set signature of transaction.
     fn generate_signature(&mut self, wallet: &Wallet) -> ();
+
+    // Return the binary format version of the transaction.
+    fn get_version(&self) -> u8;
+
+    // Return the type of the transaction.
+    fn get_type(&self) -> TxType;
+
+    // Return the stored hash of the transaction.
+    fn get_hash(&self) -> [u8; 32];
+
+    // Return the public key of the wallet making the transaction.
+    fn get_owner(&self) -> &Vec<u8>;
+
+    // Return the signature scheme of the owner key.
+    fn get_scheme(&self) -> Scheme;
+
+    // Return the owner signature of the transaction.
+    fn get_signature(&self) -> &[u8];
+
+    // Return the mining reward offered by the transaction.
+    fn get_reward(&self) -> u32;
+
+    // Return the hash of the previous tx made by the owner.
+    fn get_previous_hash(&self) -> [u8; 32];
+
+    // Clone the tx into a new box.
+    fn box_clone(&self) -> Box<dyn Tx>;
+
+    // Convert the tx to its JSON representation, see json.rs for the schema.
+    fn to_json(&self) -> String;
+
+    // Return the tx as a DataTx, or None for other tx types.
+    fn as_data(&self) -> Option<&DataTx> {
+        return None;
+    }
+
+    // Return the tx as a FinancialTx, or None for other tx types.
+    fn as_financial(&self) -> Option<&FinancialTx> {
+        return None;
+    }
+}
+
+impl Clone for Box<dyn Tx> {
+    fn clone(&self) -> Box<dyn Tx> {
+        return self.box_clone();
+    }
+}
+
+impl TxType {
+    // Parse a tx type byte into the matching TxType.
+    pub fn from_u8(val: u8) -> Result<TxType, DecodeError> {
+        match val {
+            0x00 => Ok(TxType::Data),
+            0x01 => Ok(TxType::Financial),
+            _ => Err(DecodeError::UnknownTxType(val))
+        }
+    }
+}
+
+// Parse a full tx binary into the matching boxed Tx struct.
+// The tx type is read from the second byte of the binary.
+pub fn tx_from_bin(bin: &[u8]) -> Result<Box<dyn Tx>, DecodeError> {
+    let mut pos: usize = 1;
+    match TxType::from_u8(take_u8(bin, &mut pos)?)? {
+        TxType::Data => Ok(Box::new(DataTx::from_bin(bin)?)),
+        TxType::Financial => Ok(Box::new(FinancialTx::from_bin(bin)?))
+    }
+}
+
+// Parse a JSON tx into the matching boxed Tx struct.
+// The tx type is read from the "type" field.
+#[cfg(test)]
+pub fn tx_from_json(json: &str) -> Result<Box<dyn Tx>, JsonError> {
+    return parse::<TxJson>(json)?.to_tx();
+}
+
+// Read a tx version byte and check this build can decode its format.
+fn take_tx_version(bin: &[u8], pos: &mut usize) -> Result<u8, DecodeError> {
+    let version = take_u8(bin, pos)?;
+    if version != TX_VERSION {
+        return Err(DecodeError::UnsupportedVersion(version));
+    }
+
+    return Ok(version);
+}
+
+// Read a tx type byte and check it matches the type being decoded.
+fn take_tx_type(bin: &[u8], pos: &mut usize, expected: TxType) -> Result<TxType, DecodeError> {
+    let val = take_u8(bin, pos)?;
+    let tx_type = TxType::from_u8(val)?;
+    if tx_type != expected {
+        return Err(DecodeError::UnexpectedTxType(val));
+    }
+
+    return Ok(tx_type);
+}
+
+// Read a prefixed signature, refusing any longer than a supported key can produce.
+fn take_signature(bin: &[u8], pos: &mut usize) -> Result<Vec<u8>, DecodeError> {
+    let signature = take_prefixed(bin, pos)?;
+    if signature.len() > MAX_SIGNATURE_LEN {
+        return Err(DecodeError::TooLong(signature.len(), MAX_SIGNATURE_LEN));
+    }
+
+    return Ok(signature);
 }
 
 /**
@@ -31,32 +143,68 @@ pub trait Tx {
  * A transaction struct that allows
  * for 256-bytes of arbitrary data.
  */
+#[derive(Clone)]
 pub struct DataTx {
     pub version: u8,                // u8 field for tx version
     pub tx_type: TxType,            // 8-bit transaction type field represented as TxType enum
-    pub owner: Vec<u8>,             // Public key of wallet making transaction (270 bytes - ASN.1 Public Key Format)
+    pub scheme: Scheme,             // 8-bit signature scheme id of the owner key
+    pub owner: Vec<u8>,             // Public key of wallet making transaction, in the format of its scheme
     pub data_len: u8,               // Length of data field in bytes (0-255)
     pub data: Vec<u8>,              // 256-byte arbitrary data field
     pub reward: [u8; 4],            // u32 amount of tokens for mining reward (optional)
     pub previous_hash: [u8; 32],    // 32-byte field for previous tx hash from owner wallet
     pub hash: [u8; 32],             // 32-byte field for unique transaction hash
-    pub signature: [u8; 256]        // 256-byte owner RSA signature field
+    pub signature: Vec<u8>          // Owner signature, with its length given by the scheme
 }
 
 impl DataTx {
     pub fn new() -> DataTx {
         return DataTx {
-            version: 0x00,
+            version: TX_VERSION,
             tx_type: TxType::Data,
+            scheme: Scheme::Rsa,
             owner: vec![0; 32],
             data_len: 0x00,
             data: vec![],
             reward: [0, 0, 0, 0],
             previous_hash: [0; 32],
             hash: [0; 32],
-            signature: [0; 256]
+            signature: vec![]
         }
     }
+
+    // Parse a full tx binary, as produced by to_bin, into a DataTx.
+    // The data field length is given by the preceding data_len byte.
+    pub fn from_bin(bin: &[u8]) -> Result<DataTx, DecodeError> {
+        let mut pos: usize = 0;
+        let version = take_tx_version(bin, &mut pos)?;
+        let tx_type = take_tx_type(bin, &mut pos, TxType::Data)?;
+        let scheme = Scheme::from_u8(take_u8(bin, &mut pos)?)?;
+        let owner = take_prefixed(bin, &mut pos)?;
+        let data_len = take_u8(bin, &mut pos)?;
+        let data = take(bin, &mut pos, data_len as usize)?.to_vec();
+
+        let tx = DataTx {
+            version: version,
+            tx_type: tx_type,
+            scheme: scheme,
+            owner: owner,
+            data_len: data_len,
+            data: data,
+            reward: take_array(bin, &mut pos)?,
+            previous_hash: take_array(bin, &mut pos)?,
+            hash: take_array(bin, &mut pos)?,
+            signature: take_signature(bin, &mut pos)?
+        };
+        check_end(bin, pos)?;
+
+        return Ok(tx);
+    }
+
+    // Parse a JSON tx, as produced by to_json, into a DataTx.
+    pub fn from_json(json: &str) -> Result<DataTx, JsonError> {
+        return parse::<TxJson>(json)?.to_data();
+    }
 }
 
 impl ToString for DataTx {
@@ -65,6 +213,7 @@ impl ToString for DataTx {
             DataTx {{
                 \tversion: {:x?},
                 \ttx_type: {:x?},
+                \tscheme: {:?},
                 \towner: {:x?},
                 \tdata: {:x?},
                 \treward: {:x?},
@@ -74,6 +223,7 @@ impl ToString for DataTx {
             }}",
             self.version,
             self.tx_type as u8,
+            self.scheme,
             self.owner,
             self.data,
             self.reward,
@@ -86,28 +236,32 @@ impl ToString for DataTx {
 
 impl Tx for DataTx {
     // Convert all tx contents to bin.
+    // Variable length key and signature fields are prefixed with their length.
     fn to_bin(&self) -> Vec<u8> {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
         binary.push(self.data_len);
         binary.extend_from_slice(&self.data.clone());
         binary.extend_from_slice(&self.reward.clone());
         binary.extend_from_slice(&self.previous_hash.clone());
         binary.extend_from_slice(&self.hash.clone());
-        binary.extend_from_slice(&self.signature.clone());
+        push_prefixed(&mut binary, &self.signature);
 
         return binary;
     }
 
     // Convert transaction fields into a
     // binary used for generating hash.
+    // Keys are prefixed with their length, so bytes cannot move between fields.
     fn to_hashable_bin(&self) -> Vec<u8> {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
         binary.push(self.data_len);
         binary.extend_from_slice(&self.data.clone());
         binary.extend_from_slice(&self.reward.clone());
@@ -122,7 +276,8 @@ impl Tx for DataTx {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
         binary.push(self.data_len);
         binary.extend_from_slice(&self.data.clone());
         binary.extend_from_slice(&self.reward.clone());
@@ -140,10 +295,65 @@ impl Tx for DataTx {
     }
 
     // Generate and set signature of transaction.
+    // The wallet must use the scheme set in the tx.
     fn generate_signature(&mut self, wallet: &Wallet) -> () {
         let bin: Vec<u8> = self.to_signable_bin();
-        let sig: [u8; 256] = wallet.sign(&bin);
-        self.signature = sig;
+        self.signature = wallet.sign(&bin);
+    }
+
+    // Return the binary format version of the transaction.
+    fn get_version(&self) -> u8 {
+        return self.version;
+    }
+
+    // Return the type of the transaction.
+    fn get_type(&self) -> TxType {
+        return self.tx_type;
+    }
+
+    // Return the stored hash of the transaction.
+    fn get_hash(&self) -> [u8; 32] {
+        return self.hash;
+    }
+
+    // Return the public key of the wallet making the transaction.
+    fn get_owner(&self) -> &Vec<u8> {
+        return &self.owner;
+    }
+
+    // Return the signature scheme of the owner key.
+    fn get_scheme(&self) -> Scheme {
+        return self.scheme;
+    }
+
+    // Return the owner signature of the transaction.
+    fn get_signature(&self) -> &[u8] {
+        return &self.signature;
+    }
+
+    // Return the mining reward offered by the transaction.
+    fn get_reward(&self) -> u32 {
+        return BigEndian::read_u32(&self.reward);
+    }
+
+    // Return the hash of the previous tx made by the owner.
+    fn get_previous_hash(&self) -> [u8; 32] {
+        return self.previous_hash;
+    }
+
+    // Clone the tx into a new box.
+    fn box_clone(&self) -> Box<dyn Tx> {
+        return Box::new(self.clone());
+    }
+
+    // Convert the tx to its JSON representation.
+    fn to_json(&self) -> String {
+        return format(&TxJson::from_data(self));
+    }
+
+    // Return the tx as a DataTx.
+    fn as_data(&self) -> Option<&DataTx> {
+        return Some(self);
     }
 }
 
@@ -154,32 +364,65 @@ impl Tx for DataTx {
  * proposed financial transfer between
  * two wallets within the network.
  */
+#[derive(Clone)]
 pub struct FinancialTx {
     pub version: u8,                // u8 field for tx version
     pub tx_type: TxType,            // 8-bit transaction type field represented as TxType enum
-    pub owner: Vec<u8>,             // 32-byte (256-bit) creator wallet reference
-    pub receiver: Vec<u8>,          // 32-byte (256-bit) receiver wallet reference
+    pub scheme: Scheme,             // 8-bit signature scheme id of the owner key
+    pub owner: Vec<u8>,             // Public key of wallet making transaction, in the format of its scheme, shown as its address
+    pub receiver: Vec<u8>,          // Public key of wallet receiving the tokens, of any scheme, shown as its address
     pub quantity: [u8; 4],          // u32 amount of tokens to be transfered
     pub reward: [u8; 4],            // u32 amount of tokens for mining reward
     pub previous_hash: [u8; 32],    // 32-byte field for previous tx hash from owner wallet
     pub hash: [u8; 32],             // 32-byte field for unique transaction hash
-    pub signature: [u8; 256]        // 256-byte owner RSA signature field
+    pub signature: Vec<u8>          // Owner signature, with its length given by the scheme
 }
 
 impl FinancialTx {
     pub fn new() -> FinancialTx {
         return FinancialTx {
-            version: 0x00,
+            version: TX_VERSION,
             tx_type: TxType::Financial,
+            scheme: Scheme::Rsa,
             owner: vec![0; 32],
             receiver: vec![0; 32],
             quantity: [0, 0, 0, 0],
             reward: [0, 0, 0, 0],
             previous_hash: [0; 32],
             hash: [0; 32],
-            signature: [0; 256]
+            signature: vec![]
         }
     }
+
+    // Parse a full tx binary, as produced by to_bin, into a FinancialTx.
+    pub fn from_bin(bin: &[u8]) -> Result<FinancialTx, DecodeError> {
+        let mut pos: usize = 0;
+        let tx = FinancialTx {
+            version: take_tx_version(bin, &mut pos)?,
+            tx_type: take_tx_type(bin, &mut pos, TxType::Financial)?,
+            scheme: Scheme::from_u8(take_u8(bin, &mut pos)?)?,
+            owner: take_prefixed(bin, &mut pos)?,
+            receiver: take_prefixed(bin, &mut pos)?,
+            quantity: take_array(bin, &mut pos)?,
+            reward: take_array(bin, &mut pos)?,
+            previous_hash: take_array(bin, &mut pos)?,
+            hash: take_array(bin, &mut pos)?,
+            signature: take_signature(bin, &mut pos)?
+        };
+        check_end(bin, pos)?;
+
+        return Ok(tx);
+    }
+
+    // Parse a JSON tx, as produced by to_json, into a FinancialTx.
+    pub fn from_json(json: &str) -> Result<FinancialTx, JsonError> {
+        return parse::<TxJson>(json)?.to_financial();
+    }
+
+    // Return the quantity of tokens transferred as a u32.
+    pub fn get_quantity(&self) -> u32 {
+        return BigEndian::read_u32(&self.quantity);
+    }
 }
 
 impl ToString for FinancialTx {
@@ -188,6 +431,7 @@ impl ToString for FinancialTx {
             FinancialTx {{
                 \tversion: {:x?},
                 \ttx_type: {:x?},
+                \tscheme: {:?},
                 \towner: {:x?},
                 \treceiver: {:x?},
                 \tquantity: {:x?},
@@ -198,6 +442,7 @@ impl ToString for FinancialTx {
             }}",
             self.version,
             self.tx_type as u8,
+            self.scheme,
             self.owner,
             self.receiver,
             self.quantity,
@@ -210,29 +455,33 @@ impl ToString for FinancialTx {
 
 impl Tx for FinancialTx {
     // Convert all tx contents to bin.
+    // Variable length key and signature fields are prefixed with their length.
     fn to_bin(&self) -> Vec<u8> {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
-        binary.extend_from_slice(&self.receiver.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
+        push_prefixed(&mut binary, &self.receiver);
         binary.extend_from_slice(&self.quantity.clone());
         binary.extend_from_slice(&self.reward.clone());
         binary.extend_from_slice(&self.previous_hash.clone());
         binary.extend_from_slice(&self.hash.clone());
-        binary.extend_from_slice(&self.signature.clone());
+        push_prefixed(&mut binary, &self.signature);
 
         return binary;
     }
 
     // Convert transaction fields into a
     // binary used for generating hash.
+    // Keys are prefixed with their length, so bytes cannot move between fields.
     fn to_hashable_bin(&self) -> Vec<u8> {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
-        binary.extend_from_slice(&self.receiver.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
+        push_prefixed(&mut binary, &self.receiver);
         binary.extend_from_slice(&self.quantity.clone());
         binary.extend_from_slice(&self.reward.clone());
         binary.extend_from_slice(&self.previous_hash.clone());
@@ -246,8 +495,9 @@ impl Tx for FinancialTx {
         let mut binary: Vec<u8> = vec![];
         binary.push(self.version);
         binary.push(self.tx_type as u8);
-        binary.extend_from_slice(&self.owner.clone());
-        binary.extend_from_slice(&self.receiver.clone());
+        binary.push(self.scheme as u8);
+        push_prefixed(&mut binary, &self.owner);
+        push_prefixed(&mut binary, &self.receiver);
         binary.extend_from_slice(&self.quantity.clone());
         binary.extend_from_slice(&self.reward.clone());
         binary.extend_from_slice(&self.previous_hash.clone());
@@ -264,10 +514,65 @@ impl Tx for FinancialTx {
     }
 
     // Generate and set signature of transaction.
+    // The wallet must use the scheme set in the tx.
     fn generate_signature(&mut self, wallet: &Wallet) -> () {
         let bin: Vec<u8> = self.to_signable_bin();
-        let sig: [u8; 256] = wallet.sign(&bin);
-        self.signature = sig;
+        self.signature = wallet.sign(&bin);
+    }
+
+    // Return the binary format version of the transaction.
+    fn get_version(&self) -> u8 {
+        return self.version;
+    }
+
+    // Return the type of the transaction.
+    fn get_type(&self) -> TxType {
+        return self.tx_type;
+    }
+
+    // Return the stored hash of the transaction.
+    fn get_hash(&self) -> [u8; 32] {
+        return self.hash;
+    }
+
+    // Return the public key of the wallet making the transaction.
+    fn get_owner(&self) -> &Vec<u8> {
+        return &self.owner;
+    }
+
+    // Return the signature scheme of the owner key.
+    fn get_scheme(&self) -> Scheme {
+        return self.scheme;
+    }
+
+    // Return the owner signature of the transaction.
+    fn get_signature(&self) -> &[u8] {
+        return &self.signature;
+    }
+
+    // Return the mining reward offered by the transaction.
+    fn get_reward(&self) -> u32 {
+        return BigEndian::read_u32(&self.reward);
+    }
+
+    // Return the hash of the previous tx made by the owner.
+    fn get_previous_hash(&self) -> [u8; 32] {
+        return self.previous_hash;
+    }
+
+    // Clone the tx into a new box.
+    fn box_clone(&self) -> Box<dyn Tx> {
+        return Box::new(self.clone());
+    }
+
+    // Convert the tx to its JSON representation.
+    fn to_json(&self) -> String {
+        return format(&TxJson::from_financial(self));
+    }
+
+    // Return the tx as a FinancialTx.
+    fn as_financial(&self) -> Option<&FinancialTx> {
+        return Some(self);
     }
 }
 
@@ -276,6 +581,8 @@ impl Tx for FinancialTx {
 #[cfg(test)]
 mod test {
     use super::*;
+    use crate::util::type_of;
+    use crate::wallet_struct::{test_wallet, test_ed25519_wallet};
 
     #[test]
     fn construct_data_tx() {
@@ -286,18 +593,19 @@ mod test {
         let reward: [u8; 4] = [0, 0, 0, 1];
         let previous_hash: [u8; 32] = [0; 32];
         let hash: [u8; 32] = [0; 32];
-        let signature: [u8; 256] = [0x00; 256];
+        let signature: Vec<u8> = vec![0x00; 64];
 
         let tx: DataTx = DataTx {
             version: version,
             tx_type: tx_type,
+            scheme: Scheme::Ed25519,
             owner: owner.clone(),
             data_len: data.len() as u8,
             data: data.clone(),
             reward: reward,
             previous_hash: previous_hash,
             hash: hash,
-            signature: signature
+            signature: signature.clone()
         };
 
         assert_eq!(tx.version, version);
@@ -308,7 +616,7 @@ mod test {
         assert_eq!(tx.reward, reward);
         assert_eq!(tx.previous_hash, previous_hash);
         assert_eq!(tx.hash, hash);
-        assert!(tx.signature.iter().eq(signature.iter()));
+        assert_eq!(tx.signature, signature);
     }
 
     #[test]
@@ -321,18 +629,19 @@ mod test {
         let reward: [u8; 4] = [0, 0, 0, 1];
         let previous_hash: [u8; 32] = [0; 32];
         let hash: [u8; 32] = [0; 32];
-        let signature: [u8; 256] = [0x00; 256];
+        let signature: Vec<u8> = vec![0x00; 256];
 
         let tx: FinancialTx = FinancialTx {
             version: version,
             tx_type: tx_type,
+            scheme: Scheme::Rsa,
             owner: owner.clone(),
             receiver: receiver.clone(),
             quantity: quantity,
             reward: reward,
             previous_hash: previous_hash,
             hash: hash,
-            signature: signature
+            signature: signature.clone()
         };
 
         assert_eq!(tx.version, version);
@@ -343,7 +652,7 @@ mod test {
         assert_eq!(tx.reward, reward);
         assert_eq!(tx.previous_hash, previous_hash);
         assert_eq!(tx.hash, hash);
-        assert!(tx.signature.iter().eq(signature.iter()));
+        assert_eq!(tx.signature, signature);
     }
 
     #[test]
@@ -362,7 +671,7 @@ mod test {
     fn hash_data_tx() {
         let mut tx: DataTx = DataTx::new();
         tx.generate_hash();
-        let expected = [8, 5, 220, 220, 66, 202, 71, 171, 220, 61, 143, 225, 31, 142, 12, 122, 16, 134, 2, 2, 47, 113, 171, 52, 150, 72, 207, 221, 48, 167, 90, 166];
+        let expected = [110, 154, 221, 166, 174, 91, 14, 19, 148, 133, 139, 122, 61, 214, 195, 167, 170, 13, 89, 222, 100, 164, 228, 227, 231, 69, 176, 161, 185, 148, 173, 92];
 
         assert_eq!(tx.hash, expected);
     }
@@ -371,50 +680,179 @@ mod test {
     fn hash_financial_tx() {
         let mut tx: FinancialTx = FinancialTx::new();
         tx.generate_hash();
-        let expected = [45, 101, 99, 194, 67, 155, 231, 68, 251, 192, 152, 146, 214, 0, 139, 246, 169, 98, 110, 198, 96, 181, 206, 117, 124, 213, 61, 85, 41, 238, 117, 250];
+        let expected = [125, 120, 255, 248, 96, 2, 181, 196, 210, 189, 217, 226, 20, 107, 78, 237, 110, 209, 103, 123, 234, 94, 244, 195, 56, 65, 190, 30, 86, 57, 14, 164];
 
         assert_eq!(tx.hash, expected);
     }
 
+    #[test]
+    fn hash_binds_key_boundaries() {
+        let mut tx: FinancialTx = FinancialTx::new();
+        tx.owner = vec![1, 2, 3];
+        tx.receiver = vec![4, 5];
+        tx.generate_hash();
+        let mut shifted = tx.clone();
+        shifted.owner = vec![1, 2];
+        shifted.receiver = vec![3, 4, 5];
+        shifted.generate_hash();
+
+        assert_ne!(tx.hash, shifted.hash);
+    }
+
+    #[test]
+    fn data_tx_bin_round_trip() {
+        let mut tx: DataTx = DataTx::new();
+        tx.owner = vec![3; 270];
+        tx.data = String::from("Hello World!").into_bytes();
+        tx.data_len = tx.data.len() as u8;
+        tx.reward = [0, 0, 1, 0];
+        tx.signature = vec![9; 256];
+        tx.generate_hash();
+
+        let decoded = DataTx::from_bin(&tx.to_bin()).unwrap();
+        assert_eq!(decoded.to_bin(), tx.to_bin());
+        assert_eq!(decoded.owner, tx.owner);
+        assert_eq!(decoded.data, tx.data);
+        assert_eq!(decoded.hash, tx.hash);
+    }
+
+    #[test]
+    fn financial_tx_bin_round_trip() {
+        let mut tx: FinancialTx = FinancialTx::new();
+        tx.owner = vec![3; 270];
+        tx.receiver = vec![4; 270];
+        tx.quantity = [0, 0, 2, 0];
+        tx.reward = [0, 0, 0, 5];
+        tx.signature = vec![9; 256];
+        tx.generate_hash();
+
+        let decoded = FinancialTx::from_bin(&tx.to_bin()).unwrap();
+        assert_eq!(decoded.to_bin(), tx.to_bin());
+        assert_eq!(decoded.receiver, tx.receiver);
+        assert_eq!(decoded.quantity, tx.quantity);
+    }
+
+    #[test]
+    fn tx_json_round_trip() {
+        let mut tx: FinancialTx = FinancialTx::new();
+        tx.receiver = vec![4; 270];
+        tx.quantity = [0, 0, 2, 0];
+        tx.generate_hash();
+
+        let decoded = tx_from_json(&tx.to_json()).unwrap();
+        assert_eq!(decoded.get_type(), TxType::Financial);
+        assert_eq!(decoded.to_bin(), tx.to_bin());
+        assert_eq!(FinancialTx::from_json(&tx.to_json()).unwrap().get_quantity(), 512);
+        assert!(DataTx::from_json(&tx.to_json()).is_err());
+    }
+
+    #[test]
+    fn decode_truncated_tx() {
+        let bin = FinancialTx::new().to_bin();
+
+        for len in 0..bin.len() {
+            assert!(matches!(FinancialTx::from_bin(&bin[0..len]), Err(DecodeError::Truncated(_, _))));
+        }
+    }
+
+    #[test]
+    fn decode_tx_trailing_bytes() {
+        let mut bin = DataTx::new().to_bin();
+        bin.push(0);
+
+        assert_eq!(DataTx::from_bin(&bin).err(), Some(DecodeError::TrailingBytes(1)));
+    }
+
+    #[test]
+    fn decode_tx_type_mismatch() {
+        let data_bin = DataTx::new().to_bin();
+        let mut unknown_bin = data_bin.clone();
+        unknown_bin[1] = 0x07;
+
+        assert_eq!(FinancialTx::from_bin(&data_bin).err(), Some(DecodeError::UnexpectedTxType(0x00)));
+        assert_eq!(tx_from_bin(&unknown_bin).err(), Some(DecodeError::UnknownTxType(0x07)));
+        assert_eq!(tx_from_bin(&data_bin).unwrap().to_bin(), data_bin);
+    }
+
     #[test]
     fn sign_verify_data_tx() {
         let mut tx: DataTx = DataTx::new();
-        let wallet = Wallet::new();
+        let wallet = test_wallet(1);
         let binary = tx.to_signable_bin();
         tx.generate_signature(&wallet);
 
-        assert!(Wallet::verify(&wallet.public_key, &binary, &tx.signature));
+        assert!(Wallet::verify(tx.scheme, &wallet.public_key, &binary, &tx.signature));
     }
 
     #[test]
     fn sign_verify_financial_tx() {
         let mut tx: FinancialTx = FinancialTx::new();
-        let wallet = Wallet::new();
+        let wallet = test_wallet(1);
         let binary = tx.to_signable_bin();
         tx.generate_signature(&wallet);
 
-        assert!(Wallet::verify(&wallet.public_key, &binary, &tx.signature));
+        assert!(Wallet::verify(tx.scheme, &wallet.public_key, &binary, &tx.signature));
     }
 
     #[test]
     fn sign_verify_tx_from_owner_field() {
-        let wallet = Wallet::new();
+        let wallet = test_wallet(1);
         let mut tx: DataTx = DataTx {
-            version: 0x00,
+            version: TX_VERSION,
             tx_type: TxType::Data,
+            scheme: wallet.scheme,
             owner: wallet.public_key.clone(),
             data_len: 4,
             data: vec![1, 2, 3, 4],
             reward: [0, 0, 0, 1],
             previous_hash: [0; 32],
             hash: [0; 32],
-            signature: [0; 256]
+            signature: vec![]
         };
         tx.generate_hash();
         tx.generate_signature(&wallet);
 
         let binary = tx.to_signable_bin();
         let public_key = tx.owner.clone();
-        assert!(Wallet::verify(&public_key, &binary, &tx.signature));
+        assert!(Wallet::verify(tx.scheme, &public_key, &binary, &tx.signature));
+    }
+
+    #[test]
+    fn sign_verify_ed25519_tx() {
+        let wallet = test_ed25519_wallet(1);
+        let mut tx: FinancialTx = FinancialTx::new();
+        tx.scheme = Scheme::Ed25519;
+        tx.owner = wallet.public_key.clone();
+        tx.generate_hash();
+        tx.generate_signature(&wallet);
+        assert_eq!(tx.signature.len(), 64);
+        assert!(Wallet::verify(tx.scheme, &tx.owner, &tx.to_signable_bin(), &tx.signature));
+
+        // The scheme is signed, and decides how the signature is checked.
+        let decoded = FinancialTx::from_bin(&tx.to_bin()).unwrap();
+        assert_eq!(decoded.scheme, Scheme::Ed25519);
+        assert_eq!(decoded.signature, tx.signature);
+        let mut relabelled = decoded.clone();
+        relabelled.scheme = Scheme::Rsa;
+        assert!(!Wallet::verify(relabelled.scheme, &relabelled.owner, &relabelled.to_signable_bin(), &relabelled.signature));
+    }
+
+    #[test]
+    fn decode_unknown_version_or_scheme() {
+        let mut bin = DataTx::new().to_bin();
+        bin[2] = 0x09;
+        assert_eq!(DataTx::from_bin(&bin).err(), Some(DecodeError::UnknownScheme(0x09)));
+        bin[0] = 0x00;
+        assert_eq!(tx_from_bin(&bin).err(), Some(DecodeError::UnsupportedVersion(0x00)));
+    }
+
+    #[test]
+    fn decode_rejects_oversized_signature() {
+        let mut tx: FinancialTx = FinancialTx::new();
+        tx.signature = vec![0; MAX_SIGNATURE_LEN];
+        assert!(FinancialTx::from_bin(&tx.to_bin()).is_ok());
+
+        tx.signature = vec![0; MAX_SIGNATURE_LEN + 1];
+        assert_eq!(tx_from_bin(&tx.to_bin()).err(), Some(DecodeError::TooLong(MAX_SIGNATURE_LEN + 1, MAX_SIGNATURE_LEN)));
     }
 }
\ No newline at end of file
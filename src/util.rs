@@ -2,7 +2,9 @@ extern crate byteorder;
 extern crate sha2;
 extern crate ring;
 
+#[cfg(test)]
 use std::any::type_name;
+#[cfg(test)]
 use rand::{RngCore};
 use sha2::{Sha256, Digest};
 use std::time::{SystemTime, UNIX_EPOCH};
@@ -10,14 +12,17 @@ use byteorder::ByteOrder;
 use std::net::{SocketAddr, IpAddr, Ipv4Addr, Ipv6Addr};
 use chrono::DateTime;
 use chrono::offset::Utc;
+use std::io::{stdin, stderr, Write};
 
 
 // Return the type of a variable as a string.
+#[cfg(test)]
 pub fn type_of<T>(_: &T) -> &'static str {
     type_name::<T>()
 }
 
 // Generate and random 32-bit value.
+#[cfg(test)]
 pub fn generate_rand_data() -> [u8; 4] {
     let mut rng = rand::thread_rng();
     let mut id: [u8; 4] = [0; 4];
@@ -37,6 +42,23 @@ pub fn hash(binary: &Vec<u8>) -> [u8; 32] {
     return hash;
 }
 
+// Encode binary data as a lowercase hex string.
+pub fn to_hex(binary: &[u8]) -> String {
+    return binary.iter().map(|byte| format!("{:02x}", byte)).collect::<String>();
+}
+
+// Decode a hex string into binary data.
+// Returns None if the string has odd length or non-hex characters.
+pub fn from_hex(hex_str: &str) -> Option<Vec<u8>> {
+    if !hex_str.len().is_multiple_of(2) || !hex_str.is_ascii() {
+        return None;
+    }
+
+    return (0..hex_str.len()).step_by(2)
+        .map(|i| u8::from_str_radix(&hex_str[i..(i + 2)], 16).ok())
+        .collect();
+}
+
 // Return 64-bit UNIX timestamp formatted as a u8 array.
 pub fn get_timestamp() -> [u8; 8] {
     let duration = match SystemTime::now().duration_since(UNIX_EPOCH) {
@@ -59,8 +81,9 @@ pub fn get_datetime() -> String {
     return date_str.to_string();
 }
 
-// Parse a socket address string to a corresponding SocketAddr struct.
-pub fn parse_net_address(ip_str: &str) -> SocketAddr {
+// Parse a socket address string, returning None if it is malformed.
+// IPv4 addresses are written as a.b.c.d:port, IPv6 as eight hex groups followed by :port.
+pub fn try_parse_net_address(ip_str: &str) -> Option<SocketAddr> {
     let segments = ip_str.split(":").collect::<Vec<&str>>();
 
     if segments.len() == 2 {
@@ -71,26 +94,80 @@ pub fn parse_net_address(ip_str: &str) -> SocketAddr {
     }
 }
 
-fn parse_ipv4(addr: &str) -> SocketAddr {
+fn parse_ipv4(addr: &str) -> Option<SocketAddr> {
     let split = addr.split(":").collect::<Vec<&str>>();
-    let port = split[1];
-    let seg_str = split[0].split(".").collect::<Vec<&str>>();
-    let num: Vec<u8> = seg_str.into_iter().map(|x| x.parse::<u8>().unwrap()).collect();
+    let port = split[1].parse::<u16>().ok()?;
+    let num: Vec<u8> = split[0].split(".").map(|x| x.parse::<u8>().ok()).collect::<Option<Vec<u8>>>()?;
+    if num.len() != 4 {
+        return None;
+    }
 
-    return SocketAddr::new(
+    return Some(SocketAddr::new(
         IpAddr::V4(Ipv4Addr::new(num[0], num[1], num[2], num[3])),
-        port.parse::<u16>().unwrap()
-    );
+        port
+    ));
 }
 
-fn parse_ipv6(ip_str: &str) -> SocketAddr {
+fn parse_ipv6(ip_str: &str) -> Option<SocketAddr> {
     let mut seg_str = ip_str.split(":").collect::<Vec<&str>>();
-    let port = seg_str[8].parse::<u16>().unwrap();
-    seg_str.pop();
-    let num: Vec<u16> = seg_str.into_iter().map(|x| u16::from_str_radix(x, 16).unwrap()).collect();
+    if seg_str.len() != 9 {
+        return None;
+    }
+    let port = seg_str.pop()?.parse::<u16>().ok()?;
+    let num: Vec<u16> = seg_str.into_iter().map(|x| u16::from_str_radix(x, 16).ok()).collect::<Option<Vec<u16>>>()?;
 
-    return SocketAddr::new(
+    return Some(SocketAddr::new(
         IpAddr::V6(Ipv6Addr::new(num[0], num[1], num[2], num[3], num[4], num[5], num[6], num[7])),
-        port as u16
-    );
-}
\ No newline at end of file
+        port
+    ));
+}
+
+// Print a prompt to stderr and read a line from stdin without echoing it, for passphrases.
+// Echo is only turned off when stdin is a terminal, so piped input still works.
+pub fn read_hidden_line(prompt: &str) -> String {
+    eprint!("{}", prompt);
+    let _ = stderr().flush();
+
+    let saved = disable_echo();
+    let mut line = String::new();
+    let read = stdin().read_line(&mut line);
+    if let Some(saved) = saved {
+        restore_echo(saved);
+    }
+    eprintln!();
+    if let Err(why) = read {
+        panic!("Failed to read from stdin: {}", why);
+    }
+
+    return line.trim_end_matches(&['\r', '\n'][..]).to_string();
+}
+
+// Turn off terminal echo on stdin, returning the settings to restore or None if stdin is not a terminal.
+#[cfg(unix)]
+fn disable_echo() -> Option<libc::termios> {
+    unsafe {
+        let mut term: libc::termios = std::mem::zeroed();
+        if libc::tcgetattr(libc::STDIN_FILENO, &mut term) != 0 {
+            return None;
+        }
+        let saved = term;
+        term.c_lflag &= !libc::ECHO;
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &term);
+        return Some(saved);
+    }
+}
+
+#[cfg(unix)]
+fn restore_echo(saved: libc::termios) -> () {
+    unsafe {
+        libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &saved);
+    }
+}
+
+#[cfg(not(unix))]
+fn disable_echo() -> Option<()> {
+    return None;
+}
+
+#[cfg(not(unix))]
+fn restore_echo(_saved: ()) -> () {}
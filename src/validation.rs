@@ -0,0 +1,309 @@
+use crate::block_struct::{Block, BLOCK_VERSION};
+use crate::target::meets_target;
+use crate::tx_struct::TX_VERSION;
+use crate::util::hash;
+use crate::wallet_struct::Wallet;
+use byteorder::{ByteOrder, BigEndian};
+
+pub const MAX_FUTURE_TIME: u64 = 2 * 60 * 60;   // Furthest a block timestamp may be ahead of the local clock, in seconds
+pub const MAX_BLOCK_BYTES: usize = 1_000_000;   // Largest block binary accepted, kept below the network payload limit
+
+// Reasons a block can be rejected.
+// Rules are checked in the order listed, the first failure is returned.
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub enum BlockError {
+    UnsupportedVersion(u8),         // Block version is not the current header format
+    InvalidPreviousHash,            // Block does not build on the current tip
+    InvalidHeight(u32, u32),        // Height is not tip + 1 (expected, found)
+    InvalidBits(u32, u32),          // Target bits differ from the chain (expected, found)
+    TimestampTooEarly(u64, u64),    // Timestamp is not after the median time past (median, found)
+    TimestampTooLate(u64, u64),     // Timestamp is too far ahead of the local clock (latest, found)
+    TxCountMismatch(u8, usize),     // tx_count field differs from txs length (tx_count, txs)
+    TooLarge(usize),                // Block binary is over MAX_BLOCK_BYTES (found)
+    UnsupportedTxVersion(usize),    // Tx at index is not in the current tx binary format
+    InvalidTxHash(usize),           // Tx at index has a hash not matching its contents
+    InvalidTxSignature(usize),      // Tx at index has a signature not valid for its owner
+    InvalidMerkleRoot,              // tx_merkle does not match the block tx hashes
+    InvalidHash,                    // Block hash does not match the block contents
+    InsufficientWork                // Block hash is above the target
+}
+
+/**
+ * The chain state a new block is validated against.
+ * Holds the hash of the current tip, the height the next
+ * block must have, the target bits it must be mined at
+ * and the range its timestamp must fall within.
+ */
+#[derive(Debug, Copy, Clone, PartialEq)]
+pub struct ChainState {
+    pub tip_hash: [u8; 32],
+    pub next_height: u32,
+    pub bits: u32,
+    pub median_time_past: Option<u64>,  // Median timestamp of recent blocks, None for the genesis block
+    pub max_timestamp: u64              // Latest timestamp accepted, set from the local clock for new blocks
+}
+
+impl ChainState {
+    // Chain state of an empty chain awaiting its genesis block.
+    pub fn genesis(bits: u32) -> ChainState {
+        return ChainState {
+            tip_hash: [0; 32],
+            next_height: 0,
+            bits: bits,
+            median_time_past: None,
+            max_timestamp: u64::MAX
+        }
+    }
+
+    // Chain state on top of the given tip, with the expected next target bits
+    // and the median time past of the tip and its ancestors.
+    pub fn from_tip(tip: &Block, bits: u32, median_time_past: u64) -> ChainState {
+        return ChainState {
+            tip_hash: tip.hash,
+            next_height: tip.get_height() + 1,
+            bits: bits,
+            median_time_past: Some(median_time_past),
+            max_timestamp: u64::MAX
+        }
+    }
+}
+
+// Validate a block against the current chain state.
+pub fn validate_block(block: &Block, state: &ChainState) -> Result<(), BlockError> {
+    if block.version != BLOCK_VERSION {
+        return Err(BlockError::UnsupportedVersion(block.version));
+    }
+    if block.previous_hash != state.tip_hash {
+        return Err(BlockError::InvalidPreviousHash);
+    }
+    if block.get_height() != state.next_height {
+        return Err(BlockError::InvalidHeight(state.next_height, block.get_height()));
+    }
+    if block.bits != state.bits {
+        return Err(BlockError::InvalidBits(state.bits, block.bits));
+    }
+    let timestamp = BigEndian::read_u64(&block.timestamp);
+    if let Some(median) = state.median_time_past.filter(|median| timestamp <= *median) {
+        return Err(BlockError::TimestampTooEarly(median, timestamp));
+    }
+    if timestamp > state.max_timestamp {
+        return Err(BlockError::TimestampTooLate(state.max_timestamp, timestamp));
+    }
+    if block.tx_count as usize != block.txs.len() {
+        return Err(BlockError::TxCountMismatch(block.tx_count, block.txs.len()));
+    }
+    let size = block.to_bin().len();
+    if size > MAX_BLOCK_BYTES {
+        return Err(BlockError::TooLarge(size));
+    }
+
+    // Txs of another version would be stored in a block that no longer decodes.
+    for (i, tx) in block.txs.iter().enumerate() {
+        if tx.get_version() != TX_VERSION {
+            return Err(BlockError::UnsupportedTxVersion(i));
+        }
+        if hash(&tx.to_hashable_bin()) != tx.get_hash() {
+            return Err(BlockError::InvalidTxHash(i));
+        }
+        if !Wallet::verify(tx.get_scheme(), tx.get_owner(), &tx.to_signable_bin(), tx.get_signature()) {
+            return Err(BlockError::InvalidTxSignature(i));
+        }
+    }
+
+    if block.calculate_merkle() != block.tx_merkle {
+        return Err(BlockError::InvalidMerkleRoot);
+    }
+    if block.calculate_hash() != block.hash {
+        return Err(BlockError::InvalidHash);
+    }
+    if !meets_target(&block.hash, block.bits) {
+        return Err(BlockError::InsufficientWork);
+    }
+
+    return Ok(());
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::tx_struct::{Tx, DataTx, TxType, TX_VERSION};
+    use crate::signature_scheme::Scheme;
+    use crate::test_util::mine;
+    use crate::wallet_struct::{test_wallet, test_ed25519_wallet};
+
+    const TEST_BITS: u32 = 0x200f_ffff;
+
+    fn signed_tx(wallet: &Wallet) -> DataTx {
+        let mut tx = DataTx {
+            version: TX_VERSION,
+            tx_type: TxType::Data,
+            scheme: wallet.scheme,
+            owner: wallet.public_key.clone(),
+            data_len: 4,
+            data: vec![1, 2, 3, 4],
+            reward: [0, 0, 0, 1],
+            previous_hash: [0; 32],
+            hash: [0; 32],
+            signature: vec![]
+        };
+        tx.generate_hash();
+        tx.generate_signature(wallet);
+        return tx;
+    }
+
+    fn test_state() -> ChainState {
+        return ChainState {
+            tip_hash: [5; 32],
+            next_height: 8,
+            bits: TEST_BITS,
+            median_time_past: Some(1_000),
+            max_timestamp: 2_000
+        }
+    }
+
+    fn test_block(txs: Vec<Box<dyn Tx>>) -> Block {
+        let mut block = Block::new();
+        block.previous_hash = [5; 32];
+        block.height = 8u32.to_be_bytes();
+        block.bits = TEST_BITS;
+        block.timestamp = 1_500u64.to_be_bytes();
+        block.miner = vec![1; 270];
+        block.tx_count = txs.len() as u8;
+        block.txs = txs;
+        block.generate_merkle();
+        mine(&mut block);
+        return block;
+    }
+
+    #[test]
+    fn accept_valid_block() {
+        let wallet = test_wallet(1);
+        let block = test_block(vec![Box::new(signed_tx(&wallet))]);
+
+        assert_eq!(validate_block(&block, &test_state()), Ok(()));
+    }
+
+    #[test]
+    fn accept_genesis_block() {
+        let mut block = Block::new();
+        block.bits = TEST_BITS;
+        mine(&mut block);
+        let state = ChainState::genesis(TEST_BITS);
+
+        assert_eq!(validate_block(&block, &state), Ok(()));
+    }
+
+    #[test]
+    fn reject_bad_linkage() {
+        let block = test_block(vec![]);
+        let wrong_tip = ChainState { tip_hash: [6; 32], ..test_state() };
+        let wrong_height = ChainState { next_height: 9, ..test_state() };
+        let wrong_bits = ChainState { bits: TEST_BITS - 1, ..test_state() };
+
+        assert_eq!(validate_block(&block, &wrong_tip), Err(BlockError::InvalidPreviousHash));
+        assert_eq!(validate_block(&block, &wrong_height), Err(BlockError::InvalidHeight(9, 8)));
+        assert_eq!(validate_block(&block, &wrong_bits), Err(BlockError::InvalidBits(TEST_BITS - 1, TEST_BITS)));
+
+        let mut old_version = block.clone();
+        old_version.version = 0;
+        assert_eq!(validate_block(&old_version, &test_state()), Err(BlockError::UnsupportedVersion(0)));
+    }
+
+    #[test]
+    fn reject_bad_timestamp() {
+        let mut block = test_block(vec![]);
+        let early = ChainState { median_time_past: Some(1_500), ..test_state() };
+        let late = ChainState { max_timestamp: 1_499, ..test_state() };
+
+        assert_eq!(validate_block(&block, &early), Err(BlockError::TimestampTooEarly(1_500, 1_500)));
+        assert_eq!(validate_block(&block, &late), Err(BlockError::TimestampTooLate(1_499, 1_500)));
+
+        block.timestamp = 1_000u64.to_be_bytes();
+        mine(&mut block);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::TimestampTooEarly(1_000, 1_000)));
+    }
+
+    #[test]
+    fn reject_tx_count_mismatch() {
+        let mut block = test_block(vec![]);
+        block.tx_count = 1;
+        mine(&mut block);
+
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::TxCountMismatch(1, 0)));
+    }
+
+    #[test]
+    fn reject_oversized_block() {
+        let mut tx = signed_tx(&test_wallet(1));
+        tx.owner = vec![1; u16::MAX as usize];
+        let txs: Vec<Box<dyn Tx>> = (0..MAX_BLOCK_BYTES / tx.owner.len() + 1).map(|_| Box::new(tx.clone()) as Box<dyn Tx>).collect();
+        let block = test_block(txs);
+        let size = block.to_bin().len();
+
+        assert!(size > MAX_BLOCK_BYTES);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::TooLarge(size)));
+    }
+
+    #[test]
+    fn reject_invalid_txs() {
+        let wallet = test_wallet(1);
+        let mut bad_hash = signed_tx(&wallet);
+        bad_hash.data = vec![4, 3, 2, 1];
+        let mut bad_sig = signed_tx(&wallet);
+        bad_sig.signature[0] ^= 0xFF;
+
+        let block = test_block(vec![Box::new(signed_tx(&wallet)), Box::new(bad_hash)]);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::InvalidTxHash(1)));
+
+        let block = test_block(vec![Box::new(bad_sig)]);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::InvalidTxSignature(0)));
+
+        let mut future = signed_tx(&wallet);
+        future.version = 7;
+        future.generate_hash();
+        future.generate_signature(&wallet);
+        let block = test_block(vec![Box::new(signed_tx(&wallet)), Box::new(future)]);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::UnsupportedTxVersion(1)));
+    }
+
+    #[test]
+    fn verify_txs_by_their_scheme() {
+        let ed25519 = test_ed25519_wallet(1);
+        let block = test_block(vec![Box::new(signed_tx(&test_wallet(1))), Box::new(signed_tx(&ed25519))]);
+        assert_eq!(validate_block(&block, &test_state()), Ok(()));
+
+        // A tx claiming another scheme than its key was signed with fails verification.
+        let mut relabelled = signed_tx(&ed25519);
+        relabelled.scheme = Scheme::Rsa;
+        relabelled.generate_hash();
+        let block = test_block(vec![Box::new(relabelled)]);
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::InvalidTxSignature(0)));
+    }
+
+    #[test]
+    fn reject_bad_merkle_root() {
+        let wallet = test_wallet(1);
+        let mut block = test_block(vec![Box::new(signed_tx(&wallet))]);
+        block.tx_merkle = [0; 32];
+        mine(&mut block);
+
+        assert_eq!(validate_block(&block, &test_state()), Err(BlockError::InvalidMerkleRoot));
+    }
+
+    #[test]
+    fn reject_bad_proof_of_work() {
+        let mut tampered = test_block(vec![]);
+        tampered.reward_amount = [0, 0, 0, 99];
+
+        let mut unmined = test_block(vec![]);
+        while meets_target(&unmined.hash, TEST_BITS) {
+            unmined.nonce[0] += 1;
+            unmined.generate_hash();
+        }
+
+        assert_eq!(validate_block(&tampered, &test_state()), Err(BlockError::InvalidHash));
+        assert_eq!(validate_block(&unmined, &test_state()), Err(BlockError::InsufficientWork));
+    }
+}
@@ -1,39 +1,71 @@
 extern crate ring;
 
+use crate::keystore::{Keystore, KeystoreError};
+use crate::signature_scheme::Scheme;
 use crate::wallet_struct::Wallet;
-use std::fs::{File, write, create_dir, read};
-use std::io::{Error, ErrorKind};
+use std::fs::{OpenOptions, create_dir_all, read, remove_file, rename};
+use std::io::Write;
+use std::path::Path;
 
 
-// Save the DER keypair to the file system.
-pub fn save_to_disk(file_name: &str, wallet: Wallet) -> bool {
-    match create_dir("wallet/") {
-        Err(why) => if Error::last_os_error().kind() != ErrorKind::AlreadyExists {
-                panic!("Failed to create wallet directory: {}", why);
-            },
-        Ok(_) => ()
-    };
-    match File::create(file_name) {
-        Err(why) => panic!("Failed to create key file file: {}", why),
-        Ok(file) => file
-    };
-    match write(file_name, wallet.private_key) {
-        Err(why) => panic!("Failed to write to created key file: {}", why),
-        Ok(_) => ()
-    };
+// Save a wallet to a keystore file, encrypted under a passphrase and readable only by its owner.
+// Creates the parent directory of the key file if it does not exist.
+pub fn save_to_disk(file_name: &str, wallet: &Wallet, passphrase: &str, iterations: u32) -> Result<(), KeystoreError> {
+    let keystore = Keystore::encrypt(wallet, passphrase, iterations)?;
+    return write_owner_only(file_name, &keystore.to_bin());
+}
+
+// Unlock a keystore file stored on disk.
+pub fn load_from_disk(file_name: &str, passphrase: &str) -> Result<Wallet, KeystoreError> {
+    return read_keystore(file_name)?.decrypt(passphrase);
+}
+
+// Read the public key of a keystore file, which does not need the passphrase.
+pub fn load_public_key(file_name: &str) -> Result<Vec<u8>, KeystoreError> {
+    return Ok(read_keystore(file_name)?.public_key);
+}
+
+// Read the signature scheme of a keystore file, which does not need the passphrase.
+pub fn load_scheme(file_name: &str) -> Result<Scheme, KeystoreError> {
+    return Ok(read_keystore(file_name)?.scheme);
+}
+
+// Re-encrypt a keystore file under a new passphrase.
+// The iteration count is raised to the given one if the file used fewer.
+pub fn change_passphrase(file_name: &str, old: &str, new: &str, iterations: u32) -> Result<(), KeystoreError> {
+    let keystore = read_keystore(file_name)?;
+    let changed = keystore.change_passphrase(old, new, iterations.max(keystore.iterations))?;
+    return write_owner_only(file_name, &changed.to_bin());
+}
 
-    return true;
+fn read_keystore(file_name: &str) -> Result<Keystore, KeystoreError> {
+    let contents = read(file_name).map_err(|why| KeystoreError::Io(format!("{}: {}", file_name, why)))?;
+    return Keystore::from_bin(&contents);
 }
 
-// Load a key file stored on disk.
-pub fn load_from_disk() -> Wallet {
-    let key_data = match read("wallet/keypair.der") {
-        Err(why) => panic!("Failed to read the contents of the key file: {}", why),
-        Ok(contents) => contents
-    };
+// Write a file only its owner can read, replacing any existing file atomically.
+// The data is written to a temporary file alongside, created with owner-only permissions, then renamed over.
+pub fn write_owner_only(file_name: &str, data: &[u8]) -> Result<(), KeystoreError> {
+    let io_error = |why: std::io::Error| KeystoreError::Io(format!("{}: {}", file_name, why));
+    if let Some(dir) = Path::new(file_name).parent() {
+        create_dir_all(dir).map_err(io_error)?;
+    }
 
-    let wallet = Wallet::load(key_data);
-    return wallet;
+    let temp_name = format!("{}.tmp", file_name);
+    let _ = remove_file(&temp_name);
+    let mut options = OpenOptions::new();
+    options.write(true).create_new(true);
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+    let mut file = options.open(&temp_name).map_err(io_error)?;
+    file.write_all(data).map_err(io_error)?;
+    file.sync_all().map_err(io_error)?;
+    rename(&temp_name, file_name).map_err(io_error)?;
+
+    return Ok(());
 }
 
 
@@ -41,4 +73,49 @@ pub fn load_from_disk() -> Wallet {
 #[cfg(test)]
 mod test {
     use super::*;
-}
\ No newline at end of file
+    use crate::test_util::temp_path;
+    use crate::keystore::MIN_ITERATIONS;
+    use crate::wallet_struct::test_wallet;
+    use std::fs::remove_dir_all;
+
+    const TEST_ITERATIONS: u32 = MIN_ITERATIONS;
+
+    // Return a key file path nested in a temporary directory.
+    fn key_file(dir: &Path) -> String {
+        return dir.join("keys").join("keystore.dat").to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn save_and_unlock_keystore() {
+        let dir = temp_path("wallet-save");
+        let path = key_file(&dir);
+        let wallet = test_wallet(1);
+        save_to_disk(&path, &wallet, "pass", TEST_ITERATIONS).unwrap();
+
+        assert_eq!(load_public_key(&path).unwrap(), wallet.public_key);
+        assert_eq!(load_from_disk(&path, "pass").unwrap().private_key, wallet.private_key);
+        assert!(matches!(load_from_disk(&path, "wrong"), Err(KeystoreError::WrongPassphrase)));
+        assert!(!Path::new(&format!("{}.tmp", path)).exists());
+
+        #[cfg(unix)]
+        {
+            use std::os::unix::fs::PermissionsExt;
+            let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+            assert_eq!(mode & 0o777, 0o600);
+        }
+        remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn change_passphrase_on_disk() {
+        let dir = temp_path("wallet-passwd");
+        let path = key_file(&dir);
+        save_to_disk(&path, &test_wallet(1), "old", TEST_ITERATIONS).unwrap();
+
+        assert_eq!(change_passphrase(&path, "wrong", "new", TEST_ITERATIONS), Err(KeystoreError::WrongPassphrase));
+        change_passphrase(&path, "old", "new", TEST_ITERATIONS).unwrap();
+        assert!(matches!(load_from_disk(&path, "old"), Err(KeystoreError::WrongPassphrase)));
+        assert_eq!(load_from_disk(&path, "new").unwrap().public_key, test_wallet(1).public_key);
+        remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,355 @@
+use crate::config::WalletConfig;
+use crate::keystore::{Keystore, KeystoreError};
+use crate::wallet;
+use crate::wallet_struct::Wallet;
+use crate::signature_scheme::Scheme;
+use std::fs::{create_dir_all, read, read_dir, read_to_string, rename, write};
+use std::path::PathBuf;
+
+const KEY_EXTENSION: &str = "keystore";     // Extension of the keystore file of each named key
+const DEFAULT_FILE: &str = "default";       // File in the wallet directory naming the default key
+const DELETED_DIR: &str = "deleted";        // Subdirectory deleted keys are moved into
+const MAX_NAME_LEN: usize = 64;
+
+// Reasons a wallet manager operation fails.
+#[derive(Debug, Clone, PartialEq)]
+pub enum WalletError {
+    Keystore(KeystoreError),    // Keystore could not be read, written or unlocked
+    Io(String),                 // Wallet directory could not be read or written
+    InvalidName(String),        // Names are 1 to 64 letters, digits, '-' or '_'
+    NotFound(String),           // No key with the name
+    AlreadyExists(String),      // A key with the name already exists
+    NoDefault,                  // No key was named and no default key is set
+    IsDefault(String),          // The default key cannot be deleted while it is the default
+    InvalidKey                  // Imported data is neither a keystore nor an RSA private key
+}
+
+/**
+ * A key held by the wallet manager.
+ */
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletEntry {
+    pub name: String,
+    pub scheme: Scheme,
+    pub public_key: Vec<u8>,
+    pub is_default: bool
+}
+
+/**
+ * Manages many named wallet keys in a directory.
+ * Each key is an encrypted keystore file named <name>.keystore,
+ * and a file named default holds the name of the default signing key.
+ */
+pub struct WalletManager {
+    dir: PathBuf,
+    iterations: u32
+}
+
+impl From<KeystoreError> for WalletError {
+    fn from(why: KeystoreError) -> WalletError {
+        return WalletError::Keystore(why);
+    }
+}
+
+impl WalletManager {
+    // Open the wallet directory from the wallet config. The directory is created when a key is first added.
+    pub fn open(config: &WalletConfig) -> WalletManager {
+        return WalletManager {
+            dir: PathBuf::from(&config.dir),
+            iterations: config.kdf_iterations
+        }
+    }
+
+    // List every key, sorted by name.
+    pub fn list(&self) -> Result<Vec<WalletEntry>, WalletError> {
+        let entries = match read_dir(&self.dir) {
+            Err(_) if !self.dir.exists() => return Ok(vec![]),
+            Err(why) => return Err(io_error(&self.dir, why)),
+            Ok(entries) => entries
+        };
+        let default = self.default_name()?;
+
+        let mut names: Vec<String> = entries
+            .filter_map(|entry| entry.ok())
+            .map(|entry| entry.path())
+            .filter(|path| path.extension().is_some_and(|extension| extension == KEY_EXTENSION))
+            .filter_map(|path| path.file_stem().and_then(|stem| stem.to_str()).map(|stem| stem.to_string()))
+            .filter(|name| validate_name(name).is_ok())
+            .collect();
+        names.sort();
+
+        let mut list = vec![];
+        for name in names.into_iter() {
+            list.push(WalletEntry {
+                scheme: self.scheme(&name)?,
+                public_key: self.public_key(&name)?,
+                is_default: default.as_ref() == Some(&name),
+                name: name
+            });
+        }
+        return Ok(list);
+    }
+
+    // Generate a new key under a name, encrypted under a passphrase.
+    // The first key added becomes the default.
+    pub fn create(&self, name: &str, passphrase: &str, config: &WalletConfig) -> Result<Vec<u8>, WalletError> {
+        self.check_free(name)?;
+        let new_wallet = Wallet::generate(config);
+        self.add(name, &new_wallet, passphrase)?;
+        return Ok(new_wallet.public_key);
+    }
+
+    // Add an existing wallet under a name, encrypted under a passphrase.
+    pub fn add(&self, name: &str, key: &Wallet, passphrase: &str) -> Result<(), WalletError> {
+        self.check_free(name)?;
+        wallet::save_to_disk(&self.key_path(name), key, passphrase, self.iterations)?;
+        return self.default_if_first(name);
+    }
+
+    // Import a key file under a name.
+    // Keystore files are copied as they are, keeping their passphrase.
+    // Unencrypted DER private keys need a passphrase to encrypt them under.
+    pub fn import(&self, name: &str, data: &[u8], passphrase: Option<&str>) -> Result<(), WalletError> {
+        self.check_free(name)?;
+        match Keystore::from_bin(data) {
+            Ok(_) => {
+                wallet::write_owner_only(&self.key_path(name), data)?;
+                return self.default_if_first(name);
+            },
+            Err(KeystoreError::NotAKeystore) => {
+                let key = Wallet::load(Scheme::Rsa, data.to_vec()).map_err(|_| WalletError::InvalidKey)?;
+                let passphrase = passphrase.ok_or(WalletError::Keystore(KeystoreError::EmptyPassphrase))?;
+                return self.add(name, &key, passphrase);
+            },
+            Err(why) => return Err(WalletError::Keystore(why))
+        }
+    }
+
+    // Return the encrypted keystore of a key, to be written elsewhere and imported on another node.
+    pub fn export(&self, name: &str) -> Result<Vec<u8>, WalletError> {
+        let path = self.existing_key_path(name)?;
+        return read(&path).map_err(|why| io_error(&path, why));
+    }
+
+    // Unlock a key with its passphrase.
+    pub fn unlock(&self, name: &str, passphrase: &str) -> Result<Wallet, WalletError> {
+        return Ok(wallet::load_from_disk(&self.existing_key_path(name)?, passphrase)?);
+    }
+
+    // Return the public key of a key, which does not need its passphrase.
+    pub fn public_key(&self, name: &str) -> Result<Vec<u8>, WalletError> {
+        return Ok(wallet::load_public_key(&self.existing_key_path(name)?)?);
+    }
+
+    pub fn scheme(&self, name: &str) -> Result<Scheme, WalletError> {
+        return Ok(wallet::load_scheme(&self.existing_key_path(name)?)?);
+    }
+
+    pub fn change_passphrase(&self, name: &str, old: &str, new: &str) -> Result<(), WalletError> {
+        return Ok(wallet::change_passphrase(&self.existing_key_path(name)?, old, new, self.iterations)?);
+    }
+
+    // Return the name of the default key, if one is set.
+    pub fn default_name(&self) -> Result<Option<String>, WalletError> {
+        let path = self.dir.join(DEFAULT_FILE);
+        if !path.exists() {
+            return Ok(None);
+        }
+        let name = read_to_string(&path).map_err(|why| io_error(&path, why))?.trim().to_string();
+        return Ok(Some(name).filter(|name| !name.is_empty()));
+    }
+
+    pub fn set_default(&self, name: &str) -> Result<(), WalletError> {
+        self.existing_key_path(name)?;
+        let path = self.dir.join(DEFAULT_FILE);
+        return write(&path, format!("{}\n", name)).map_err(|why| io_error(&path, why));
+    }
+
+    // Return the given key name, or the default key's if none is given.
+    pub fn resolve(&self, name: Option<&str>) -> Result<String, WalletError> {
+        let name = match name {
+            Some(name) => name.to_string(),
+            None => self.default_name()?.ok_or(WalletError::NoDefault)?
+        };
+        self.existing_key_path(&name)?;
+        return Ok(name);
+    }
+
+    // Delete a key by moving its keystore into the deleted subdirectory, so it can still be recovered.
+    // The default key cannot be deleted unless forced, which leaves no default set.
+    pub fn delete(&self, name: &str, force: bool) -> Result<(), WalletError> {
+        let path = self.existing_key_path(name)?;
+        let is_default = self.default_name()?.as_deref() == Some(name);
+        if is_default && !force {
+            return Err(WalletError::IsDefault(name.to_string()));
+        }
+
+        let deleted_dir = self.dir.join(DELETED_DIR);
+        create_dir_all(&deleted_dir).map_err(|why| io_error(&deleted_dir, why))?;
+        let mut target = deleted_dir.join(format!("{}.{}", name, KEY_EXTENSION));
+        let mut copy = 1;
+        while target.exists() {
+            target = deleted_dir.join(format!("{}.{}.{}", name, copy, KEY_EXTENSION));
+            copy += 1;
+        }
+        rename(&path, &target).map_err(|why| io_error(&path, why))?;
+
+        if is_default {
+            let default_path = self.dir.join(DEFAULT_FILE);
+            std::fs::remove_file(&default_path).map_err(|why| io_error(&default_path, why))?;
+        }
+        return Ok(());
+    }
+
+    fn key_path(&self, name: &str) -> String {
+        return self.dir.join(format!("{}.{}", name, KEY_EXTENSION)).to_string_lossy().to_string();
+    }
+
+    fn existing_key_path(&self, name: &str) -> Result<String, WalletError> {
+        validate_name(name)?;
+        let path = self.key_path(name);
+        if !PathBuf::from(&path).exists() {
+            return Err(WalletError::NotFound(name.to_string()));
+        }
+        return Ok(path);
+    }
+
+    // Check a name is valid and no key uses it yet.
+    pub fn check_free(&self, name: &str) -> Result<(), WalletError> {
+        validate_name(name)?;
+        if PathBuf::from(self.key_path(name)).exists() {
+            return Err(WalletError::AlreadyExists(name.to_string()));
+        }
+        return Ok(());
+    }
+
+    fn default_if_first(&self, name: &str) -> Result<(), WalletError> {
+        if self.default_name()?.is_none() {
+            return self.set_default(name);
+        }
+        return Ok(());
+    }
+}
+
+// Check a key name is safe to use as a file name.
+fn validate_name(name: &str) -> Result<(), WalletError> {
+    let valid = !name.is_empty()
+        && name.len() <= MAX_NAME_LEN
+        && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_');
+    return match valid {
+        true => Ok(()),
+        false => Err(WalletError::InvalidName(name.to_string()))
+    };
+}
+
+fn io_error<P: AsRef<std::path::Path>>(path: P, why: std::io::Error) -> WalletError {
+    return WalletError::Io(format!("{}: {}", path.as_ref().display(), why));
+}
+
+
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::test_util::temp_path;
+    use crate::keystore::MIN_ITERATIONS;
+    use crate::wallet_struct::{test_wallet, test_ed25519_wallet};
+    use std::fs::remove_dir_all;
+
+    // Return a manager over a fresh temporary directory.
+    fn manager(name: &str) -> WalletManager {
+        return WalletManager { dir: temp_path(&format!("wallets-{}", name)), iterations: MIN_ITERATIONS };
+    }
+
+    #[test]
+    fn adds_lists_and_unlocks_keys() {
+        let manager = manager("list");
+        assert_eq!(manager.list(), Ok(vec![]));
+        assert_eq!(manager.resolve(None), Err(WalletError::NoDefault));
+
+        manager.add("main", &test_wallet(1), "one").unwrap();
+        manager.add("cold", &test_wallet(2), "two").unwrap();
+        assert_eq!(manager.add("main", &test_wallet(2), "two"), Err(WalletError::AlreadyExists("main".to_string())));
+
+        let list = manager.list().unwrap();
+        assert_eq!(list.iter().map(|entry| entry.name.as_str()).collect::<Vec<&str>>(), vec!["cold", "main"]);
+        assert_eq!(list[1], WalletEntry { name: "main".to_string(), scheme: Scheme::Rsa, public_key: test_wallet(1).public_key, is_default: true });
+        assert!(!list[0].is_default);
+
+        assert_eq!(manager.resolve(None), Ok("main".to_string()));
+        assert_eq!(manager.unlock("cold", "two").unwrap().private_key, test_wallet(2).private_key);
+        assert!(matches!(manager.unlock("cold", "one"), Err(WalletError::Keystore(KeystoreError::WrongPassphrase))));
+        assert!(matches!(manager.unlock("hot", "one"), Err(WalletError::NotFound(_))));
+
+        // Keys of every scheme sit side by side.
+        manager.add("fast", &test_ed25519_wallet(1), "three").unwrap();
+        assert_eq!(manager.scheme("fast"), Ok(Scheme::Ed25519));
+        assert_eq!(manager.unlock("fast", "three").unwrap().public_key, test_ed25519_wallet(1).public_key);
+        assert_eq!(manager.list().unwrap().iter().map(|entry| entry.scheme).collect::<Vec<Scheme>>(), vec![Scheme::Rsa, Scheme::Ed25519, Scheme::Rsa]);
+        remove_dir_all(&manager.dir).unwrap();
+    }
+
+    #[test]
+    fn switches_default_key() {
+        let manager = manager("default");
+        manager.add("main", &test_wallet(1), "one").unwrap();
+        manager.add("cold", &test_wallet(2), "two").unwrap();
+
+        manager.set_default("cold").unwrap();
+        assert_eq!(manager.resolve(None), Ok("cold".to_string()));
+        assert_eq!(manager.resolve(Some("main")), Ok("main".to_string()));
+        assert_eq!(manager.set_default("hot"), Err(WalletError::NotFound("hot".to_string())));
+        assert_eq!(manager.default_name(), Ok(Some("cold".to_string())));
+        remove_dir_all(&manager.dir).unwrap();
+    }
+
+    #[test]
+    fn imports_and_exports_keys() {
+        let source = manager("export");
+        source.add("main", &test_wallet(1), "one").unwrap();
+        let exported = source.export("main").unwrap();
+
+        // Exported keystores keep their passphrase, raw DER keys are encrypted on import.
+        let target = manager("import");
+        target.import("moved", &exported, None).unwrap();
+        assert_eq!(target.unlock("moved", "one").unwrap().private_key, test_wallet(1).private_key);
+        target.import("legacy", &test_wallet(2).private_key, Some("two")).unwrap();
+        assert_eq!(target.unlock("legacy", "two").unwrap().public_key, test_wallet(2).public_key);
+
+        assert_eq!(target.import("bad", b"not a key", Some("pass")), Err(WalletError::InvalidKey));
+        assert_eq!(target.import("raw", &test_wallet(2).private_key, None), Err(WalletError::Keystore(KeystoreError::EmptyPassphrase)));
+        assert_eq!(target.default_name(), Ok(Some("moved".to_string())));
+        remove_dir_all(&source.dir).unwrap();
+        remove_dir_all(&target.dir).unwrap();
+    }
+
+    #[test]
+    fn deletes_keys_recoverably() {
+        let manager = manager("delete");
+        manager.add("main", &test_wallet(1), "one").unwrap();
+        manager.add("cold", &test_wallet(2), "two").unwrap();
+
+        manager.delete("cold", false).unwrap();
+        manager.add("cold", &test_wallet(2), "two").unwrap();
+        manager.delete("cold", false).unwrap();
+        assert!(manager.dir.join(DELETED_DIR).join("cold.keystore").exists());
+        assert!(manager.dir.join(DELETED_DIR).join("cold.1.keystore").exists());
+        assert_eq!(manager.list().unwrap().len(), 1);
+
+        assert_eq!(manager.delete("main", false), Err(WalletError::IsDefault("main".to_string())));
+        manager.delete("main", true).unwrap();
+        assert_eq!(manager.default_name(), Ok(None));
+        assert_eq!(manager.delete("main", true), Err(WalletError::NotFound("main".to_string())));
+        remove_dir_all(&manager.dir).unwrap();
+    }
+
+    #[test]
+    fn rejects_unsafe_names() {
+        let manager = manager("names");
+        for name in ["", "../escape", "a/b", "with space", &"x".repeat(65)].iter() {
+            assert_eq!(manager.add(name, &test_wallet(1), "one"), Err(WalletError::InvalidName(name.to_string())));
+        }
+        assert_eq!(validate_name("node-1_key"), Ok(()));
+        assert!(!manager.dir.exists());
+    }
+}
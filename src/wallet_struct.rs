@@ -1,21 +1,17 @@
 extern crate ring;
 
-use crate::env::{KEY_ALGO, KEY_SIZE, KEY_PUB_EXP};
-use crate::key_parser;
-use std::process;
-use std::process::Command;
-use ring::{rand, signature};
-
-#[derive(Debug, Copy, Clone, PartialEq)]
-enum SignatureState {
-    SignatureInvalid,
-    SignatureValid
-}
+use crate::config::WalletConfig;
+use crate::signature_scheme::{Scheme, KeyError};
+use rand::SeedableRng;
+use rand::rngs::{OsRng, StdRng};
+use rsa::rand_core::CryptoRngCore;
 
 /**
- * A struct to hold a public and private key pair.
+ * A struct to hold a public and private key pair,
+ * along with the signature scheme they belong to.
  */
 pub struct Wallet {
+    pub scheme: Scheme,
     pub public_key: Vec<u8>,
     pub private_key: Vec<u8>
 }
@@ -23,84 +19,84 @@ pub struct Wallet {
 impl Wallet {
     // Create a new 2048-bit RSA wallet.
     pub fn new() -> Wallet {
-        let output = Command::new("openssl")
-            .arg("genpkey")
-            .arg("-algorithm")
-            .arg(format!("{}", KEY_ALGO))
-            .arg("-pkeyopt")
-            .arg(format!("rsa_keygen_bits:{}", KEY_SIZE))
-            .arg("-pkeyopt")
-            .arg(format!("rsa_keygen_pubexp:{}", KEY_PUB_EXP))
-            .arg("-outform")
-            .arg("DER")
-            .output()
-            .expect("Failed to execute process");
-
-        let key_data = if output.status.success() { output.stdout } else { output.stderr };
-        match output.status.success() {
-            false => {
-                panic!("Unable to generate wallet.");
-                process::exit(1);
-            }
-            true => ()
-        }
+        return Wallet::generate(&WalletConfig::default());
+    }
 
-        let public = key_parser::get_public_der(&key_data);
-        let private = key_data.clone();
+    // Create a new wallet using the scheme and key parameters from the wallet config.
+    pub fn generate(config: &WalletConfig) -> Wallet {
+        return Wallet::generate_with_rng(config, &mut OsRng);
+    }
 
-        return Wallet {
-            public_key: public,
-            private_key: private
-        }
+    // Create the wallet deterministically derived from a seed, for reproducible tests.
+    pub fn from_seed(config: &WalletConfig, seed: u64) -> Wallet {
+        return Wallet::generate_with_rng(config, &mut StdRng::seed_from_u64(seed));
     }
 
-    // Load an already generated wallet.
-    pub fn load(key_data: Vec<u8>) -> Wallet {
-        let public = key_parser::get_public_der(&key_data);
-        let private = key_data.clone();
+    fn generate_with_rng(config: &WalletConfig, rng: &mut dyn CryptoRngCore) -> Wallet {
+        let scheme = match Scheme::from_name(&config.key_algo) {
+            None => panic!("Unable to generate wallet: unsupported algorithm {}", config.key_algo),
+            Some(scheme) => scheme
+        };
+        let key_data = scheme.implementation().generate(config, rng)
+            .and_then(|key_data| Wallet::load(scheme, key_data));
+        return match key_data {
+            Err(why) => panic!("Unable to generate wallet: {:?}", why),
+            Ok(wallet) => wallet
+        };
+    }
 
-        return Wallet {
-            public_key: public,
-            private_key: private
-        }
+    // Load an already generated private key of a scheme.
+    pub fn load(scheme: Scheme, key_data: Vec<u8>) -> Result<Wallet, KeyError> {
+        return Ok(Wallet {
+            scheme: scheme,
+            public_key: scheme.implementation().public_key(&key_data)?,
+            private_key: key_data
+        });
     }
 
     // Sign arbitrary binary data using wallet private key.
-    // Note: Wallet holds ASN.1 DER encoded RSA key pair, defined by RSA foundation.
-    pub fn sign(&self, data: &Vec<u8>) -> [u8; 256] {
-
-        let _public = signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, &self.public_key);
-        let private = match signature::RsaKeyPair::from_der(&self.private_key) {
-            Err(why) => panic!("Failed to parse key file: {}", why),
-            Ok(res) => res
+    // The signature length depends on the scheme, e.g. 256 bytes for 2048-bit RSA or 64 for Ed25519.
+    pub fn sign(&self, data: &Vec<u8>) -> Vec<u8> {
+        return match self.scheme.implementation().sign(&self.private_key, data) {
+            Err(why) => panic!("Failed to sign with wallet key: {:?}", why),
+            Ok(sig) => sig
         };
+    }
 
-        let binary: &[u8] = &data;
-        let rng = rand::SystemRandom::new();
-        let mut sig_vec = vec![0; private.public_modulus_len()];
+    // Verify signature against binary data and public key, using the scheme the signature claims.
+    pub fn verify(scheme: Scheme, public_signer: &Vec<u8>, data: &Vec<u8>, signature: &[u8]) -> bool {
+        return scheme.implementation().verify(public_signer, data, signature);
+    }
+}
 
-        match private.sign(&signature::RSA_PKCS1_SHA256, &rng, binary, &mut sig_vec) {
-            Err(why) => println!("{}", why),
-            Ok(_) => ()
-        };
+// Return the wallet derived from a seed with the default key parameters.
+// Keys are cached by seed, as generating them is slow in unoptimised test builds.
+#[cfg(test)]
+pub fn test_wallet(seed: u64) -> Wallet {
+    use lazy_static::lazy_static;
+    use std::collections::HashMap;
+    use std::sync::Mutex;
 
-        let mut sig: [u8; 256] = [0; 256];
-        sig.copy_from_slice(&sig_vec[0..256]);
-        return sig;
+    lazy_static! {
+        static ref KEYS: Mutex<HashMap<u64, Vec<u8>>> = Mutex::new(HashMap::new());
     }
 
-    // Verify signature against binary data and public key.
-    pub fn verify(public_signer: &Vec<u8>, data: &Vec<u8>, signature: &[u8; 256]) -> bool {
-        let public_key = signature::UnparsedPublicKey::new(&signature::RSA_PKCS1_2048_8192_SHA256, public_signer);
-        let verification_res = public_key.verify(&data, signature)
-            .map(|_| SignatureState::SignatureValid)
-            .map_err(|_| SignatureState::SignatureInvalid);
+    let cached = KEYS.lock().unwrap().get(&seed).cloned();
+    let key_data = match cached {
+        Some(key_data) => key_data,
+        None => {
+            let key_data = Wallet::from_seed(&WalletConfig::default(), seed).private_key;
+            KEYS.lock().unwrap().insert(seed, key_data.clone());
+            key_data
+        }
+    };
 
-        let signature_valid = match verification_res {
-            Err(_) => false,
-            Ok(_) => true
-        };
+    return Wallet::load(Scheme::Rsa, key_data).unwrap();
+}
 
-        return signature_valid;
-    }
+// Return the Ed25519 wallet derived from a seed.
+#[cfg(test)]
+pub fn test_ed25519_wallet(seed: u64) -> Wallet {
+    let config = WalletConfig { key_algo: Scheme::Ed25519.name().to_string(), ..WalletConfig::default() };
+    return Wallet::from_seed(&config, seed);
 }